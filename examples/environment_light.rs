@@ -0,0 +1,78 @@
+use ayanami::rayt::*;
+
+// HDR画像 (resources/sky.hdr) の環境光だけで照らす
+// 明るい太陽の方向を重点的にサンプリングするので、少ないサンプル数でも影がはっきり出る
+struct EnvironmentScene {
+    world: ShapeList,
+    environment: EnvironmentLight,
+}
+
+impl EnvironmentScene {
+    fn new() -> Result<Self> {
+        let mut world = ShapeList::new();
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::fill(0.5))
+                .lambertian()
+                .sphere(float3::new(0.0, -1000.0, 0.0), 1000.0)
+                .build(),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::new(0.8, 0.3, 0.2))
+                .lambertian()
+                .sphere(float3::new(-1.1, 1.0, 0.0), 1.0)
+                .build(),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::fill(0.9))
+                .metal(0.0)
+                .sphere(float3::new(1.1, 1.0, 0.0), 1.0)
+                .build(),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::new(0.2, 0.5, 0.8))
+                .lambertian()
+                .cube()
+                .scale(float3::fill(0.8))
+                .rotate(Quat::from_axis_angle(&Float3::y_axis(), 0.2 * PI))
+                .translate(float3::new(0.0, 0.4, 1.6))
+                .build(),
+        );
+        // 太陽が右奥から当たるように回す
+        let environment = EnvironmentLight::new("resources/sky.hdr")?
+            .with_rotation(Quat::from_axis_angle(&Float3::y_axis(), 0.3 * PI))
+            .with_intensity(0.8);
+        Ok(Self { world, environment })
+    }
+}
+
+impl SceneWithDepth for EnvironmentScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(Camera::from_lookat(
+            float3::new(0.0, 2.0, 7.0),
+            float3::new(0.0, 0.8, 0.0),
+            Float3::y(),
+            40.0,
+            self.aspect(),
+        ))
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        trace_environment(&self.world, &self.environment, ray, depth)
+    }
+
+    fn width(&self) -> u32 {
+        360
+    }
+
+    fn spp(&self) -> usize {
+        16
+    }
+}
+
+fn main() -> Result<()> {
+    render_aa(EnvironmentScene::new()?)
+}
@@ -0,0 +1,72 @@
+use ayanami::rayt::*;
+use std::env;
+
+// 使い方: cargo run --example export_scene -- scene.gltf
+// ShapeBuilderで組み立てたシーンをOBJ (.obj) またはglTF (.gltf) に書き出す
+// 配置やカメラの画角をDCCツールで確認するためのもの
+fn build_world() -> ShapeList {
+    let mut world = ShapeList::new();
+    world.push(
+        ShapeBuilder::new()
+            .color_texture(float3::new(0.8, 0.8, 0.8))
+            .lambertian()
+            .rect_xz(-5.0, 5.0, -5.0, 5.0, 0.0)
+            .build(),
+    );
+    world.push(
+        ShapeBuilder::new()
+            .color_texture(float3::new(0.8, 0.2, 0.2))
+            .lambertian()
+            .cube()
+            .rotate(Quat::from_axis_angle(&Float3::y_axis(), 0.25 * PI))
+            .translate(float3::new(-1.5, 0.5, 0.0))
+            .build(),
+    );
+    world.push(
+        ShapeBuilder::new()
+            .color_texture(float3::new(0.9, 0.8, 0.5))
+            .metal(0.1)
+            .sphere(float3::new(0.0, 0.7, 0.0), 0.7)
+            .build(),
+    );
+    world.push(
+        ShapeBuilder::new()
+            .dielectric(1.5)
+            .sphere(float3::new(1.5, 0.5, 0.5), 0.5)
+            .scale(float3::new(1.0, 1.5, 1.0))
+            .build(),
+    );
+    world.push(
+        ShapeBuilder::new()
+            .color_texture(float3::new(1.0, 0.9, 0.8))
+            .diffuse_light(4.0)
+            .rect_xz(-1.0, 1.0, -1.0, 1.0, -4.0)
+            // 下向きの面にする
+            .rotate(Quat::from_axis_angle(&Float3::x_axis(), PI))
+            .build(),
+    );
+    world
+}
+
+fn main() -> Result<()> {
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "scene.gltf".to_string());
+    let camera = Camera::from_lookat(
+        float3::new(0.0, 2.0, 6.0),
+        float3::new(0.0, 0.5, 0.0),
+        Float3::y(),
+        40.0,
+        16.0 / 9.0,
+    );
+
+    let mut exporter = SceneExporter::new();
+    exporter.add_shape(&build_world());
+    exporter.set_camera(&camera);
+    exporter.write(&path)?;
+    for warning in &exporter.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    println!("exported {} meshes to {}", exporter.meshes.len(), path);
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use ayanami::rayt::*;
+use std::env;
+
+// 使い方: cargo run --example gltf_viewer -- resources/boxes.gltf
+// ファイルの最初のカメラを使う
+struct GltfViewerScene {
+    scene: GltfScene,
+}
+
+impl GltfViewerScene {
+    fn new(path: &str) -> Result<Self> {
+        let scene = GltfScene::load(path)?;
+        for warning in &scene.warnings {
+            eprintln!("warning: {}", warning);
+        }
+        if scene.cameras.is_empty() {
+            return Err(Error::InvalidScene(format!("{} has no camera", path)));
+        }
+        Ok(Self { scene })
+    }
+
+    fn background(&self, d: Float3) -> Float3 {
+        let t = 0.5 * (d.normalize().y + 1.0);
+        float3::one().lerp(&float3::new(0.5, 0.7, 1.0), t) * 0.2
+    }
+}
+
+impl SceneWithDepth for GltfViewerScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(self.scene.cameras[0].camera(self.aspect()))
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        if let Some(hit) = self.scene.world.hit(&ray, 0.001, f64::MAX) {
+            let emitted = hit.m.emited(&ray, &hit);
+            let scatter_result = if depth > 0 {
+                hit.m.scatter(&ray, &hit)
+            } else {
+                None
+            };
+            if let Some(scatter_info) = scatter_result {
+                emitted
+                    + self
+                        .trace(scatter_info.ray, depth - 1)
+                        .component_mul(&scatter_info.albedo)
+            } else {
+                emitted
+            }
+        } else {
+            self.background(ray.direction)
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "resources/boxes.gltf".to_string());
+    render_aa(GltfViewerScene::new(&path)?)
+}
@@ -0,0 +1,104 @@
+use ayanami::rayt::*;
+use std::sync::Arc;
+
+// HDR画像 (resources/sky.hdr) を内側から見た大きな球の発光テクスチャにして、その光で照らす
+// 太陽の部分は1を大きく超える値なので、くっきりした影ができる
+struct HdrScene {
+    world: ShapeList,
+}
+
+impl HdrScene {
+    fn new() -> Result<Self> {
+        let mut world = ShapeList::new();
+        // 同じファイルを2回開いても、デコードは1回だけ
+        let sky = ImageTexture::new("resources/sky.hdr", (1.0, 1.0))?;
+        let reflection = ImageTexture::new("resources/sky.hdr", (1.0, 1.0))?;
+        println!("cached images: {}", TextureCache::global().len());
+
+        // 球のvは南極から増え、画像のvは上端から増えるので上下を反転する
+        let sky =
+            UvTransformTexture::new(Box::new(sky), UvTransform::default().with_scale(1.0, -1.0));
+        world.push(
+            ShapeBuilder::new()
+                .texture(Arc::new(sky))
+                .diffuse_light(1.0)
+                .sphere(Float3::zeros(), 100.0)
+                .build(),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::fill(0.5))
+                .lambertian()
+                .sphere(float3::new(0.0, -1000.0, 0.0), 1000.0)
+                .build(),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::new(0.8, 0.3, 0.2))
+                .lambertian()
+                .sphere(float3::new(-1.1, 1.0, 0.0), 1.0)
+                .build(),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::fill(0.9))
+                .metal(0.0)
+                .sphere(float3::new(1.1, 1.0, 0.0), 1.0)
+                .build(),
+        );
+        // 発光テクスチャは明るさを変えずに小さな球にも貼れる
+        world.push(
+            ShapeBuilder::new()
+                .texture(Arc::new(reflection))
+                .diffuse_light(0.5)
+                .sphere(float3::new(0.0, 0.4, 1.6), 0.4)
+                .build(),
+        );
+        Ok(Self { world })
+    }
+}
+
+impl SceneWithDepth for HdrScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(Camera::from_lookat(
+            float3::new(0.0, 2.0, 7.0),
+            float3::new(0.0, 0.8, 0.0),
+            Float3::y(),
+            40.0,
+            self.aspect(),
+        ))
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        if let Some(hit) = self.world.hit(&ray, 0.001, f64::MAX) {
+            let emitted = hit.m.emited(&ray, &hit);
+            let scatter_result = if depth > 0 {
+                hit.m.scatter(&ray, &hit)
+            } else {
+                None
+            };
+            if let Some(scatter_info) = scatter_result {
+                emitted
+                    + self
+                        .trace(scatter_info.ray, depth - 1)
+                        .component_mul(&scatter_info.albedo)
+            } else {
+                emitted
+            }
+        } else {
+            Float3::zeros()
+        }
+    }
+
+    fn width(&self) -> u32 {
+        360
+    }
+
+    fn spp(&self) -> usize {
+        100
+    }
+}
+
+fn main() -> Result<()> {
+    render_aa(HdrScene::new()?)
+}
@@ -0,0 +1,27 @@
+use ayanami::rayt::*;
+use std::env;
+
+// 使い方: cargo run --example import_viewer -- resources/spheres.pbrt
+// pbrt-v4 (.pbrt) と Mitsuba 3 (.xml) のシーンを読み込んでレンダリングする
+// 解像度, サンプル数, 最大の反射回数はシーンファイルの設定を使う
+fn main() -> Result<()> {
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "resources/spheres.pbrt".to_string());
+    let scene = ImportedScene::load(&path)?;
+    for warning in &scene.warnings {
+        eprintln!("warning: {}", warning);
+    }
+
+    let mut settings = RenderSettings::default();
+    if let Some(max_depth) = scene.max_depth {
+        settings.max_depth = max_depth;
+    }
+    // 画像はPNGなどで保存するので、exrなどの出力先は使わない
+    if let Some(filename) = &scene.film.filename {
+        if filename.ends_with(".png") || filename.ends_with(".jpg") {
+            settings.output_file = filename.clone();
+        }
+    }
+    render_aa_with(scene, &settings)
+}
@@ -0,0 +1,83 @@
+use ayanami::rayt::*;
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+
+// 使い方: cargo run --example mesh_viewer -- resources/cube.obj
+// .plyは頂点カラーをLambertianのテクスチャとして表示する
+struct MeshScene {
+    world: ShapeList,
+}
+
+impl MeshScene {
+    fn new(path: &str) -> Result<Self> {
+        let mut world = ShapeList::new();
+        let shape: Box<dyn Shape> = match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("ply") => Box::new(TriangleMesh::from_data(
+                Arc::new(load_ply(path)?),
+                Arc::new(Lambertian::new(Box::new(VertexColorTexture::new(
+                    float3::fill(0.8),
+                )))),
+            )),
+            _ => ObjModel::load(path)?.into_shape(),
+        };
+        let mut model = Transform::new(shape);
+        model.rotate_mut(Quat::from_axis_angle(&Float3::y_axis(), PI * 0.2));
+        world.push(Box::new(model));
+
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::fill(0.5))
+                .lambertian()
+                .sphere(float3::new(0.0, -1000.5, 0.0), 1000.0)
+                .build(),
+        );
+
+        Ok(Self { world })
+    }
+
+    fn background(&self, d: Float3) -> Float3 {
+        let t = 0.5 * (d.normalize().y + 1.0);
+        float3::one().lerp(&float3::new(0.5, 0.7, 1.0), t)
+    }
+}
+
+impl SceneWithDepth for MeshScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(Camera::from_lookat(
+            float3::new(2.0, 2.0, 4.0),
+            float3::new(0.0, 0.3, 0.0),
+            Float3::y(),
+            30.0,
+            self.aspect(),
+        ))
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        if let Some(hit) = self.world.hit(&ray, 0.001, f64::MAX) {
+            let emitted = hit.m.emited(&ray, &hit);
+            let scatter_result = if depth > 0 {
+                hit.m.scatter(&ray, &hit)
+            } else {
+                None
+            };
+            if let Some(scatter_info) = scatter_result {
+                emitted
+                    + self
+                        .trace(scatter_info.ray, depth - 1)
+                        .component_mul(&scatter_info.albedo)
+            } else {
+                emitted
+            }
+        } else {
+            self.background(ray.direction)
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "resources/cube.obj".to_string());
+    render_aa(MeshScene::new(&path)?)
+}
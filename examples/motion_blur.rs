@@ -0,0 +1,101 @@
+use ayanami::rayt::*;
+use std::sync::Arc;
+
+// シャッターが開いている間 (時刻0から1) に動く形状のモーションブラー
+struct MotionBlurScene {
+    world: ShapeBvh,
+}
+
+fn lambertian(color: Float3) -> Arc<dyn Material> {
+    Arc::new(Lambertian::new(Box::new(ColorTexture::new(color))))
+}
+
+impl MotionBlurScene {
+    fn new() -> Result<Self> {
+        let mut world: Vec<Box<dyn Shape>> = vec![Box::new(Sphere::new(
+            float3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            lambertian(float3::fill(0.5)),
+        ))];
+
+        // 右へ動く球
+        world.push(Box::new(MovingSphere::new(
+            float3::new(-2.5, 0.5, 0.0),
+            float3::new(-1.5, 0.5, 0.0),
+            0.0,
+            1.0,
+            0.5,
+            lambertian(float3::new(0.8, 0.2, 0.2)),
+        )));
+
+        // 回りながら上がって縮む立方体
+        let keys = vec![
+            Keyframe::new(0.0).with_translation(float3::new(0.0, 0.5, 0.0)),
+            Keyframe::new(1.0)
+                .with_translation(float3::new(0.0, 1.0, 0.0))
+                .with_rotation(Quat::from_axis_angle(&Float3::y_axis(), 0.25 * PI))
+                .with_scale(float3::fill(0.7)),
+        ];
+        world.push(Box::new(AnimatedTransform::new(
+            Box::new(Cube::new(lambertian(float3::new(0.2, 0.8, 0.2)))),
+            keys,
+        )?));
+
+        // 止まっている球
+        world.push(Box::new(Sphere::new(
+            float3::new(2.0, 0.5, 0.0),
+            0.5,
+            lambertian(float3::new(0.2, 0.2, 0.8)),
+        )));
+
+        Ok(Self {
+            world: ShapeBvh::new(world),
+        })
+    }
+
+    fn background(&self, d: Float3) -> Float3 {
+        let t = 0.5 * (d.normalize().y + 1.0);
+        float3::one().lerp(&float3::new(0.5, 0.7, 1.0), t)
+    }
+}
+
+impl SceneWithDepth for MotionBlurScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(
+            Camera::from_lookat(
+                float3::new(0.0, 2.0, 6.0),
+                float3::new(0.0, 0.5, 0.0),
+                Float3::y(),
+                40.0,
+                self.aspect(),
+            )
+            .with_shutter(0.0, 1.0),
+        )
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        if let Some(hit) = self.world.hit(&ray, 0.001, f64::MAX) {
+            let scatter_result = if depth > 0 {
+                hit.m.scatter(&ray, &hit)
+            } else {
+                None
+            };
+            if let Some(scatter_info) = scatter_result {
+                self.trace(scatter_info.ray, depth - 1)
+                    .component_mul(&scatter_info.albedo)
+            } else {
+                Float3::zeros()
+            }
+        } else {
+            self.background(ray.direction)
+        }
+    }
+
+    fn spp(&self) -> usize {
+        50
+    }
+}
+
+fn main() -> Result<()> {
+    render_aa(MotionBlurScene::new()?)
+}
@@ -0,0 +1,131 @@
+use ayanami::rayt::*;
+use std::sync::Arc;
+
+// ノーマルマップとバンプマップの比較
+// 左: そのまま, 中央: ノーマルマップ (半球のくぼみを並べたもの), 右: バンプマップ (縞模様の高さ)
+struct NormalMapScene {
+    world: ShapeList,
+}
+
+// 半球のくぼみを並べた接空間のノーマルマップ
+fn dimple_normal_map(size: usize, cells: usize) -> Result<ImageTexture> {
+    let mut pixels = Vec::with_capacity(size * size);
+    for y in 0..size {
+        for x in 0..size {
+            let cell = size as f64 / cells as f64;
+            // セルの中心からの位置 (-1..1). 画像のyは下向きなので上向きにする
+            let dx = (x as f64 % cell) / cell * 2.0 - 1.0;
+            let dy = 1.0 - (y as f64 % cell) / cell * 2.0;
+            let r2 = dx * dx + dy * dy;
+            let n = if r2 < 0.8 {
+                // くぼみなので中心に向かって傾く
+                float3::new(-dx, -dy, (1.0 - r2).sqrt()).normalize()
+            } else {
+                Float3::z()
+            };
+            pixels.push((n + float3::one()) * 0.5);
+        }
+    }
+    ImageTexture::from_pixels(pixels, size, size, (1.0, 1.0))
+}
+
+// uの向きの縞模様の高さ
+struct Stripes;
+
+impl Texture for Stripes {
+    fn value(&self, u: f64, _v: f64, _p: Float3) -> Float3 {
+        float3::fill((u * PI2 * 16.0).sin() * 0.5 + 0.5)
+    }
+}
+
+impl NormalMapScene {
+    fn new() -> Result<Self> {
+        let mut world = ShapeList::new();
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::fill(0.5))
+                .lambertian()
+                .sphere(float3::new(0.0, -1000.0, 0.0), 1000.0)
+                .build(),
+        );
+
+        let color = float3::new(0.8, 0.6, 0.3);
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(color)
+                .lambertian()
+                .sphere(float3::new(-2.2, 1.0, 0.0), 1.0)
+                .build(),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(color)
+                .lambertian()
+                .normal_map(Arc::new(dimple_normal_map(512, 16)?), 1.0)
+                .sphere(float3::new(0.0, 1.0, 0.0), 1.0)
+                .build(),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(color)
+                .lambertian()
+                .bump_map(Arc::new(Stripes), 0.04)
+                .sphere(float3::new(2.2, 1.0, 0.0), 1.0)
+                .build(),
+        );
+
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::one())
+                .diffuse_light(4.0)
+                .sphere(float3::new(-3.0, 6.0, 4.0), 2.0)
+                .build(),
+        );
+        Ok(Self { world })
+    }
+}
+
+impl SceneWithDepth for NormalMapScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(Camera::from_lookat(
+            float3::new(0.0, 2.0, 7.0),
+            float3::new(0.0, 1.0, 0.0),
+            Float3::y(),
+            40.0,
+            self.aspect(),
+        ))
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        if let Some(hit) = self.world.hit(&ray, 0.001, f64::MAX) {
+            let emitted = hit.m.emited(&ray, &hit);
+            let scatter_result = if depth > 0 {
+                hit.m.scatter(&ray, &hit)
+            } else {
+                None
+            };
+            if let Some(scatter_info) = scatter_result {
+                emitted
+                    + self
+                        .trace(scatter_info.ray, depth - 1)
+                        .component_mul(&scatter_info.albedo)
+            } else {
+                emitted
+            }
+        } else {
+            float3::fill(0.1)
+        }
+    }
+
+    fn width(&self) -> u32 {
+        360
+    }
+
+    fn spp(&self) -> usize {
+        50
+    }
+}
+
+fn main() -> Result<()> {
+    render_aa(NormalMapScene::new()?)
+}
@@ -0,0 +1,98 @@
+use ayanami::rayt::*;
+
+// PhysicalCameraの露出でレンダリングする
+// 光源の輝度をcd/m^2で指定しているので、f値やシャッター速度, ISOを変えると明るさが変わる
+struct PhysicalScene {
+    world: ShapeList,
+    lens: PhysicalCamera,
+}
+
+impl PhysicalScene {
+    fn new(lens: PhysicalCamera) -> Self {
+        let mut world = ShapeList::new();
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::fill(0.5))
+                .lambertian()
+                .sphere(float3::new(0.0, -1000.0, 0.0), 1000.0)
+                .build(),
+        );
+        for (i, color) in [
+            float3::new(0.8, 0.2, 0.2),
+            float3::new(0.2, 0.8, 0.2),
+            float3::new(0.2, 0.2, 0.8),
+        ]
+        .iter()
+        .enumerate()
+        {
+            world.push(
+                ShapeBuilder::new()
+                    .color_texture(*color)
+                    .lambertian()
+                    .sphere(
+                        float3::new(i as f64 * 2.5 - 2.5, 1.0, -(i as f64) * 2.0),
+                        1.0,
+                    )
+                    .build(),
+            );
+        }
+        // 2500 cd/m^2の大きな照明
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::one())
+                .diffuse_light(2500.0)
+                .sphere(float3::new(0.0, 8.0, 0.0), 3.0)
+                .build(),
+        );
+        Self { world, lens }
+    }
+}
+
+impl SceneWithDepth for PhysicalScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(self.lens.camera(
+            float3::new(0.0, 2.0, 8.0),
+            float3::new(0.0, 1.0, -2.0),
+            Float3::y(),
+            self.aspect(),
+        ))
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        if let Some(hit) = self.world.hit(&ray, 0.001, f64::MAX) {
+            let emitted = hit.m.emited(&ray, &hit);
+            let scatter_result = if depth > 0 {
+                hit.m.scatter(&ray, &hit)
+            } else {
+                None
+            };
+            if let Some(scatter_info) = scatter_result {
+                emitted
+                    + self
+                        .trace(scatter_info.ray, depth - 1)
+                        .component_mul(&scatter_info.albedo)
+            } else {
+                emitted
+            }
+        } else {
+            Float3::zeros()
+        }
+    }
+
+    fn exposure(&self) -> f64 {
+        self.lens.exposure()
+    }
+}
+
+fn main() -> Result<()> {
+    // 室内の撮影を想定した設定
+    let lens = PhysicalCamera {
+        focal_length: 35.0,
+        f_number: 2.8,
+        shutter_speed: 1.0 / 60.0,
+        iso: 200.0,
+        ..PhysicalCamera::default()
+    };
+    println!("vfov {:.1}, EV100 {:.2}", lens.vfov(1.0), lens.ev100());
+    render_aa(PhysicalScene::new(lens))
+}
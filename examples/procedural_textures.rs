@@ -0,0 +1,103 @@
+use ayanami::rayt::*;
+use std::sync::Arc;
+
+// ノイズから作る模様の一覧
+// 上の段: fBm (simplex), 乱流, 大理石, 下の段: 木目, Voronoi (境目), Voronoi (セル)
+struct ProceduralScene {
+    world: ShapeList,
+}
+
+impl ProceduralScene {
+    fn new() -> Self {
+        let textures: Vec<Arc<dyn Texture>> = vec![
+            Arc::new(
+                NoiseTexture::fbm(2.0)
+                    .with_basis(NoiseBasis::Simplex)
+                    .with_ramp(ColorRamp::new(
+                        float3::new(0.1, 0.2, 0.5),
+                        float3::new(0.9, 0.9, 1.0),
+                    )),
+            ),
+            Arc::new(NoiseTexture::turbulence(2.0).with_seed(1)),
+            Arc::new(NoiseTexture::marble(2.0)),
+            Arc::new(NoiseTexture::wood(4.0).with_octaves(3)),
+            Arc::new(
+                NoiseTexture::voronoi(4.0, VoronoiFeature::Edge).with_ramp(
+                    ColorRamp::new(Float3::zeros(), float3::new(1.0, 0.8, 0.4))
+                        .stop(0.3, float3::one()),
+                ),
+            ),
+            Arc::new(
+                NoiseTexture::voronoi(4.0, VoronoiFeature::Cell).with_ramp(
+                    ColorRamp::new(float3::new(0.8, 0.2, 0.1), float3::new(0.2, 0.6, 0.2))
+                        .stop(0.5, float3::new(0.9, 0.8, 0.3)),
+                ),
+            ),
+        ];
+
+        let mut world = ShapeList::new();
+        for (i, texture) in textures.into_iter().enumerate() {
+            let x = (i % 3) as f64 * 2.2 - 2.2;
+            let y = if i < 3 { 1.1 } else { -1.1 };
+            world.push(
+                ShapeBuilder::new()
+                    .texture(texture)
+                    .lambertian()
+                    .sphere(float3::new(x, y, 0.0), 1.0)
+                    .build(),
+            );
+        }
+        Self { world }
+    }
+
+    fn background(&self, d: Float3) -> Float3 {
+        let t = 0.5 * (d.normalize().y + 1.0);
+        float3::one().lerp(&float3::new(0.5, 0.7, 1.0), t)
+    }
+}
+
+impl SceneWithDepth for ProceduralScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(Camera::from_lookat(
+            float3::new(0.0, 0.0, 9.0),
+            Float3::zeros(),
+            Float3::y(),
+            35.0,
+            self.aspect(),
+        ))
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        if let Some(hit) = self.world.hit(&ray, 0.001, f64::MAX) {
+            let scatter_result = if depth > 0 {
+                hit.m.scatter(&ray, &hit)
+            } else {
+                None
+            };
+            if let Some(scatter_info) = scatter_result {
+                self.trace(scatter_info.ray, depth - 1)
+                    .component_mul(&scatter_info.albedo)
+            } else {
+                Float3::zeros()
+            }
+        } else {
+            self.background(ray.direction)
+        }
+    }
+
+    fn width(&self) -> u32 {
+        360
+    }
+
+    fn height(&self) -> u32 {
+        240
+    }
+
+    fn spp(&self) -> usize {
+        30
+    }
+}
+
+fn main() -> Result<()> {
+    render_aa(ProceduralScene::new())
+}
@@ -0,0 +1,117 @@
+use ayanami::rayt::*;
+use std::env;
+
+// 使い方: cargo run --example projections -- fisheye
+// perspective, orthographic, fisheye, equirectangular, cubemap の投影でレンダリングする
+struct ProjectionScene {
+    world: ShapeList,
+    projection: String,
+}
+
+impl ProjectionScene {
+    fn new(projection: String) -> Self {
+        let mut world = ShapeList::new();
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::fill(0.5))
+                .lambertian()
+                .sphere(float3::new(0.0, -1000.0, 0.0), 1000.0)
+                .build(),
+        );
+        // カメラを囲むように色違いの球を並べる
+        for i in 0..8 {
+            let angle = i as f64 / 8.0 * PI2;
+            let color = float3::new(
+                0.5 + 0.4 * angle.cos(),
+                0.5 + 0.4 * angle.sin(),
+                0.5 - 0.4 * angle.cos(),
+            );
+            world.push(
+                ShapeBuilder::new()
+                    .color_texture(color)
+                    .lambertian()
+                    .sphere(float3::new(4.0 * angle.sin(), 1.0, -4.0 * angle.cos()), 1.0)
+                    .build(),
+            );
+        }
+        Self { world, projection }
+    }
+
+    fn background(&self, d: Float3) -> Float3 {
+        let t = 0.5 * (d.normalize().y + 1.0);
+        float3::one().lerp(&float3::new(0.5, 0.7, 1.0), t)
+    }
+}
+
+impl SceneWithDepth for ProjectionScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        let origin = float3::new(0.0, 1.0, 0.0);
+        let lookat = float3::new(0.0, 1.0, -1.0);
+        match self.projection.as_str() {
+            "orthographic" => Box::new(OrthographicCamera::from_lookat(
+                float3::new(0.0, 10.0, 0.0),
+                Float3::zeros(),
+                -Float3::z(),
+                10.0,
+                self.aspect(),
+            )),
+            "fisheye" => Box::new(FisheyeCamera::from_lookat(
+                origin,
+                lookat,
+                Float3::y(),
+                180.0,
+                self.aspect(),
+            )),
+            "equirectangular" => Box::new(EquirectangularCamera::from_lookat(
+                origin,
+                lookat,
+                Float3::y(),
+            )),
+            "cubemap" => Box::new(CubemapCamera::from_lookat(origin, lookat, Float3::y())),
+            _ => Box::new(Camera::from_lookat(
+                origin,
+                lookat,
+                Float3::y(),
+                90.0,
+                self.aspect(),
+            )),
+        }
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        if let Some(hit) = self.world.hit(&ray, 0.001, f64::MAX) {
+            let scatter_result = if depth > 0 {
+                hit.m.scatter(&ray, &hit)
+            } else {
+                None
+            };
+            if let Some(scatter_info) = scatter_result {
+                self.trace(scatter_info.ray, depth - 1)
+                    .component_mul(&scatter_info.albedo)
+            } else {
+                Float3::zeros()
+            }
+        } else {
+            self.background(ray.direction)
+        }
+    }
+
+    fn width(&self) -> u32 {
+        match self.projection.as_str() {
+            "equirectangular" => 400,
+            "cubemap" => 300,
+            _ => 200,
+        }
+    }
+
+    fn height(&self) -> u32 {
+        200
+    }
+}
+
+fn main() -> Result<()> {
+    let projection = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "perspective".to_string());
+    render_aa(ProjectionScene::new(projection))
+}
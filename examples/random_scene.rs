@@ -0,0 +1,111 @@
+use ayanami::rayt::*;
+
+struct RandomScene {
+    world: ShapeList,
+    sky: EnvironmentLight,
+}
+
+impl RandomScene {
+    fn new() -> Self {
+        let mut world = ShapeList::new();
+
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::fill(0.5))
+                .lambertian()
+                .sphere(float3::new(0.0, -1000.0, 0.0), 1000.0)
+                .build(),
+        );
+
+        for au in -11..11 {
+            let a = au as f64;
+            for bu in -11..11 {
+                let b = bu as f64;
+                let [rx, rz, material_choice]: [f64; 3] = rand::random();
+                let center = float3::new(a + 0.9 * rx, 0.2, b + 0.9 * rz);
+
+                world.push({
+                    if material_choice < 0.8 {
+                        let albedo = math::random3_limit(0.0, 1.0)
+                            .component_mul(&math::random3_limit(0.0, 1.0));
+                        ShapeBuilder::new()
+                            .color_texture(albedo)
+                            .lambertian()
+                            .sphere(center, 0.2)
+                            .build()
+                    } else if material_choice < 0.95 {
+                        let albedo = math::random3_limit(0.5, 1.0);
+                        let fuzz = rand::random::<f64>();
+                        ShapeBuilder::new()
+                            .color_texture(albedo)
+                            .metal(fuzz)
+                            .sphere(center, 0.2)
+                            .build()
+                    } else {
+                        ShapeBuilder::new()
+                            .dielectric(1.5)
+                            .sphere(center, 0.2)
+                            .build()
+                    }
+                });
+            }
+        }
+
+        world.push(
+            ShapeBuilder::new()
+                .dielectric(1.5)
+                .sphere(float3::new(0.0, 1.0, 0.0), 1.0)
+                .build(),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::new(0.4, 0.2, 0.1))
+                .lambertian()
+                .sphere(float3::new(-4.0, 1.0, 0.0), 1.0)
+                .build(),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::new(0.7, 0.6, 0.5))
+                .metal(0.0)
+                .sphere(float3::new(4.0, 1.0, 0.0), 1.0)
+                .build(),
+        );
+
+        // 午後の少し低い太陽. 影が手前に伸びる
+        let sky = SunSky::from_angles(35.0, -60.0)
+            .with_turbidity(3.0)
+            .with_ground_albedo(float3::fill(0.5))
+            .environment();
+        Self { world, sky }
+    }
+}
+
+impl SceneWithDepth for RandomScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(
+            Camera::from_lookat(
+                float3::new(13.0, 2.0, 3.0),
+                Float3::zeros(),
+                Float3::y(),
+                20.0,
+                self.aspect(),
+            )
+            // 中央の球にピントを合わせ、六角形の絞りでぼかす
+            .with_autofocus(&self.world)
+            .with_aperture(0.1)
+            .with_aperture_shape(Aperture::Polygon {
+                blades: 6,
+                rotation: 0.0,
+            }),
+        )
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        trace_environment(&self.world, &self.sky, ray, depth)
+    }
+}
+
+fn main() -> Result<()> {
+    render_aa(RandomScene::new())
+}
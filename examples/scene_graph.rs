@@ -0,0 +1,135 @@
+use ayanami::rayt::*;
+use std::sync::Arc;
+
+// SceneGraphで組み立てたシーンをレンダリングする
+// 画面中央のレイが当たったノードの名前を表示する
+struct GraphScene {
+    graph: SceneGraph,
+    world: ShapeBvh,
+}
+
+fn lambertian(color: Float3) -> Arc<dyn Material> {
+    Arc::new(Lambertian::new(Box::new(ColorTexture::new(color))))
+}
+
+impl GraphScene {
+    fn new() -> Result<Self> {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        graph.add(
+            root,
+            SceneNode::new("floor").with_shape(Sphere::new(
+                float3::new(0.0, -1000.0, 0.0),
+                1000.0,
+                lambertian(float3::fill(0.5)),
+            )),
+        )?;
+
+        // 台座とその上の球をまとめて回転させる
+        let table = graph.add(
+            root,
+            SceneNode::new("table").with_transform(Affine::rotation(Quat::from_axis_angle(
+                &Float3::y_axis(),
+                0.2 * PI,
+            ))),
+        )?;
+        graph.add(
+            table,
+            SceneNode::new("top")
+                .with_transform(
+                    Affine::scaling(float3::new(3.0, 0.2, 1.5))
+                        .then(&Affine::translation(float3::new(0.0, 1.0, 0.0))),
+                )
+                .with_shape(Cube::new(lambertian(Float3::zeros())))
+                // 形状のマテリアルを置き換える
+                .with_material(lambertian(float3::new(0.7, 0.3, 0.2))),
+        )?;
+        for (i, x) in [-1.0, 0.0, 1.0].iter().enumerate() {
+            let ball = graph.add(
+                table,
+                SceneNode::new(format!("ball{}", i))
+                    .with_transform(Affine::translation(float3::new(*x, 1.6, 0.0)))
+                    .with_shape(Sphere::new(
+                        Float3::zeros(),
+                        0.4,
+                        Arc::new(Metal::new(
+                            Box::new(ColorTexture::new(float3::new(0.8, 0.8, 0.9))),
+                            0.05,
+                        )),
+                    )),
+            )?;
+            // 真ん中の球は子ノードごと隠す
+            if i == 1 {
+                if let Some(node) = graph.node_mut(ball) {
+                    node.visible = false;
+                }
+            }
+        }
+
+        graph.add(
+            root,
+            SceneNode::new("light")
+                .with_transform(Affine::translation(float3::new(0.0, 6.0, 0.0)))
+                .with_shape(Sphere::new(
+                    Float3::zeros(),
+                    1.5,
+                    Arc::new(DiffuseLight::new(
+                        Box::new(ColorTexture::new(float3::fill(1.0))),
+                        4.0,
+                    )),
+                )),
+        )?;
+
+        let world = graph.build();
+        Ok(Self { graph, world })
+    }
+}
+
+impl SceneWithDepth for GraphScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(Camera::from_lookat(
+            float3::new(0.0, 3.0, 7.0),
+            float3::new(0.0, 1.0, 0.0),
+            Float3::y(),
+            40.0,
+            self.aspect(),
+        ))
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        if let Some(hit) = self.world.hit(&ray, 0.001, f64::MAX) {
+            let emitted = hit.m.emited(&ray, &hit);
+            let scatter_result = if depth > 0 {
+                hit.m.scatter(&ray, &hit)
+            } else {
+                None
+            };
+            if let Some(scatter_info) = scatter_result {
+                emitted
+                    + self
+                        .trace(scatter_info.ray, depth - 1)
+                        .component_mul(&scatter_info.albedo)
+            } else {
+                emitted
+            }
+        } else {
+            float3::fill(0.05)
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let scene = GraphScene::new()?;
+    let hit = scene
+        .camera()
+        .generate_ray(0.5, 0.5)
+        .and_then(|ray| scene.world.hit(&ray, 0.001, f64::MAX));
+    match hit {
+        Some(hit) => match scene.graph.hit_node(&hit) {
+            Some(node) => println!("center: {}", node.name),
+            None => println!("center: (unknown)"),
+        },
+        None => println!("center: (background)"),
+    }
+    render_aa(scene)
+}
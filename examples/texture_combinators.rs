@@ -0,0 +1,140 @@
+use ayanami::rayt::*;
+use std::sync::Arc;
+
+// テクスチャの組み合わせ
+// 左から: Voronoiのマスクで木目と大理石を混ぜた球, 回転して並べた画像の板,
+//         三平面投影の立方体, 形状の座標系の大理石を貼って回した立方体
+struct CombinatorScene {
+    world: ShapeList,
+}
+
+fn image() -> Result<Box<dyn Texture>> {
+    Ok(Box::new(ImageTexture::new(
+        "resources/shivaduke.jpg",
+        (1.0, 1.0),
+    )?))
+}
+
+impl CombinatorScene {
+    fn new() -> Result<Self> {
+        let mut world = ShapeList::new();
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(float3::fill(0.5))
+                .lambertian()
+                .sphere(float3::new(0.0, -1000.0, 0.0), 1000.0)
+                .build(),
+        );
+
+        let mask = RampTexture::new(
+            Box::new(NoiseTexture::voronoi(3.0, VoronoiFeature::Cell)),
+            ColorRamp::new(Float3::zeros(), Float3::zeros()).stop(0.5, float3::one()),
+        );
+        let mix = MixTexture::new(
+            Box::new(NoiseTexture::wood(4.0)),
+            Box::new(ScaleTexture::new(
+                Box::new(NoiseTexture::marble(3.0)),
+                float3::new(0.9, 0.95, 1.0),
+            )),
+            Box::new(mask),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .texture(Arc::new(mix))
+                .lambertian()
+                .sphere(float3::new(-3.3, 1.0, 0.0), 1.0)
+                .build(),
+        );
+
+        let tiled = UvTransformTexture::new(
+            image()?,
+            UvTransform::default()
+                .with_scale(3.0, 3.0)
+                .with_rotation(0.25 * PI)
+                .with_offset(0.5, 0.0),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .texture(Arc::new(tiled))
+                .lambertian()
+                .rect_xy(-2.0, 0.0, 0.0, 2.0, 0.0)
+                .build(),
+        );
+
+        world.push(
+            ShapeBuilder::new()
+                .texture(Arc::new(TriplanarTexture::new(image()?, 0.5)))
+                .lambertian()
+                .cube()
+                .scale(float3::fill(1.6))
+                .rotate(Quat::from_axis_angle(&Float3::y_axis(), 0.2 * PI))
+                .translate(float3::new(1.1, 0.8, 0.0))
+                .build(),
+        );
+
+        world.push(
+            ShapeBuilder::new()
+                .texture(Arc::new(ObjectSpaceTexture::new(Box::new(
+                    NoiseTexture::marble(2.0),
+                ))))
+                .lambertian()
+                .cube()
+                .scale(float3::fill(1.4))
+                .rotate(Quat::from_axis_angle(&Float3::x_axis(), 0.3 * PI))
+                .translate(float3::new(3.4, 1.0, 0.0))
+                .build(),
+        );
+        Ok(Self { world })
+    }
+
+    fn background(&self, d: Float3) -> Float3 {
+        let t = 0.5 * (d.normalize().y + 1.0);
+        float3::one().lerp(&float3::new(0.5, 0.7, 1.0), t)
+    }
+}
+
+impl SceneWithDepth for CombinatorScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(Camera::from_lookat(
+            float3::new(0.0, 3.0, 9.0),
+            float3::new(0.0, 0.8, 0.0),
+            Float3::y(),
+            45.0,
+            self.aspect(),
+        ))
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        if let Some(hit) = self.world.hit(&ray, 0.001, f64::MAX) {
+            let scatter_result = if depth > 0 {
+                hit.m.scatter(&ray, &hit)
+            } else {
+                None
+            };
+            if let Some(scatter_info) = scatter_result {
+                self.trace(scatter_info.ray, depth - 1)
+                    .component_mul(&scatter_info.albedo)
+            } else {
+                Float3::zeros()
+            }
+        } else {
+            self.background(ray.direction)
+        }
+    }
+
+    fn width(&self) -> u32 {
+        400
+    }
+
+    fn height(&self) -> u32 {
+        200
+    }
+
+    fn spp(&self) -> usize {
+        30
+    }
+}
+
+fn main() -> Result<()> {
+    render_aa(CombinatorScene::new()?)
+}
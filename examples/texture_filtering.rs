@@ -0,0 +1,109 @@
+use ayanami::rayt::*;
+use std::sync::Arc;
+
+// 遠くまで続く床でテクスチャのフィルタを比べる
+// 左半分はフィルタ無し (手前: 市松模様, 奥: 最近傍の画像), 右半分はフィルタ有り (手前: 解析的な平均, 奥: EWA)
+struct TextureFilteringScene {
+    world: ShapeList,
+}
+
+fn checker(filtered: bool) -> Arc<dyn Texture> {
+    Arc::new(
+        CheckerTexture::new(
+            Box::new(ColorTexture::new(float3::fill(0.05))),
+            Box::new(ColorTexture::new(float3::fill(0.9))),
+            2.0,
+        )
+        .with_filtering(filtered),
+    )
+}
+
+fn image(filter: TextureFilter) -> Result<Arc<dyn Texture>> {
+    Ok(Arc::new(
+        ImageTexture::new("resources/shivaduke.jpg", (10.0, 36.0))?.with_filter(filter),
+    ))
+}
+
+impl TextureFilteringScene {
+    fn new() -> Result<Self> {
+        let mut world = ShapeList::new();
+        let y = -0.5;
+        world.push(
+            ShapeBuilder::new()
+                .texture(checker(false))
+                .lambertian()
+                .rect_xz(-50.0, 0.0, -20.0, 5.0, y)
+                .build(),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .texture(checker(true))
+                .lambertian()
+                .rect_xz(0.0, 50.0, -20.0, 5.0, y)
+                .build(),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .texture(image(TextureFilter::Nearest)?)
+                .lambertian()
+                .rect_xz(-50.0, 0.0, -200.0, -20.0, y)
+                .build(),
+        );
+        world.push(
+            ShapeBuilder::new()
+                .texture(image(TextureFilter::Ewa)?)
+                .lambertian()
+                .rect_xz(0.0, 50.0, -200.0, -20.0, y)
+                .build(),
+        );
+        Ok(Self { world })
+    }
+
+    fn background(&self, d: Float3) -> Float3 {
+        let t = 0.5 * (d.normalize().y + 1.0);
+        float3::one().lerp(&float3::new(0.5, 0.7, 1.0), t)
+    }
+}
+
+impl SceneWithDepth for TextureFilteringScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(Camera::from_lookat(
+            float3::new(0.0, 1.0, 5.0),
+            float3::new(0.0, 0.0, -10.0),
+            Float3::y(),
+            60.0,
+            self.aspect(),
+        ))
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        if let Some(hit) = self.world.hit(&ray, 0.001, f64::MAX) {
+            let scatter_result = if depth > 0 {
+                hit.m.scatter(&ray, &hit)
+            } else {
+                None
+            };
+            if let Some(scatter_info) = scatter_result {
+                self.trace(scatter_info.ray, depth - 1)
+                    .component_mul(&scatter_info.albedo)
+            } else {
+                Float3::zeros()
+            }
+        } else {
+            self.background(ray.direction)
+        }
+    }
+
+    fn width(&self) -> u32 {
+        400
+    }
+
+    // 少ないサンプル数でもフィルタ有りの側はちらつかない
+    fn spp(&self) -> usize {
+        4
+    }
+}
+
+fn main() -> Result<()> {
+    render_aa(TextureFilteringScene::new()?)
+}
@@ -0,0 +1,6 @@
+//! ayanami: CPUで動く小さなパストレーサ
+//!
+//! シーンを組み立ててレンダリングするのに必要なものは全て [`rayt`] にある.
+//! カメラ, 形状, マテリアル, テクスチャ, レンダリングの設定とレンダリングする関数.
+
+pub mod rayt;
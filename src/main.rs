@@ -1,12 +1,63 @@
-#![allow(dead_code)]
-
-mod rayt;
 mod scene;
 
-use crate::rayt::*;
 use crate::scene::*;
+use ayanami::rayt::*;
+use std::env;
+
+// 使い方:
+//   cargo run                        静止画を render.png に書き出す
+//   cargo run -- --frames 1-48 [--fps 24] [--out-dir frames] [--gif anim.gif] [--apng anim.png] [--overwrite]
+//                                    アニメーションを frames/frame_0001.png から連番で書き出す
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        return render_aa(CornelBoxScene::new()?);
+    }
 
-fn main() {
-    render_aa(CornelBoxScene::new());
+    let settings = parse_animation_args(&args)?;
+    let animation = CornelBoxAnimation::fly_through();
+    render_animation(
+        &settings,
+        |time| CornelBoxScene::at(&animation, time),
+        |_, path, status| match status {
+            FrameStatus::Skipped => println!("skip {:?}", path),
+            FrameStatus::Rendering => println!("render {:?}", path),
+        },
+    )
 }
 
+fn parse_animation_args(args: &[String]) -> Result<AnimationSettings> {
+    let invalid = |msg: String| Error::InvalidSettings(msg);
+    let mut settings = AnimationSettings::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--overwrite" {
+            settings.skip_existing = false;
+            continue;
+        }
+        let value = iter
+            .next()
+            .ok_or_else(|| invalid(format!("{} needs a value", arg)))?;
+        match arg.as_str() {
+            "--frames" => {
+                let (start, end) = value.split_once('-').unwrap_or((value, value));
+                let parse = |s: &str| {
+                    s.parse::<u32>()
+                        .map_err(|_| invalid(format!("invalid frame range {:?}", value)))
+                };
+                settings.start_frame = parse(start)?;
+                settings.end_frame = parse(end)?;
+            }
+            "--fps" => {
+                settings.fps = value
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid fps {:?}", value)))?;
+            }
+            "--out-dir" => settings.output_dir = value.clone(),
+            "--gif" => settings.gif_file = Some(value.clone()),
+            "--apng" => settings.apng_file = Some(value.clone()),
+            _ => return Err(invalid(format!("unknown option {:?}", arg))),
+        }
+    }
+    Ok(settings)
+}
@@ -0,0 +1,104 @@
+use crate::rayt::*;
+
+// 軸平行境界箱
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Float3,
+    pub max: Float3,
+}
+
+impl Aabb {
+    pub fn new(min: Float3, max: Float3) -> Self {
+        Self { min, max }
+    }
+
+    // 何も含まない箱 (unionの単位元)
+    pub fn empty() -> Self {
+        Self {
+            min: float3::fill(f64::INFINITY),
+            max: float3::fill(f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Float3>) -> Self {
+        points
+            .into_iter()
+            .fold(Self::empty(), |acc, p| acc.union_point(p))
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    pub fn union_point(&self, p: &Float3) -> Self {
+        Self {
+            min: self.min.inf(p),
+            max: self.max.sup(p),
+        }
+    }
+
+    // 各軸の両側にmarginだけ広げる
+    pub fn padded(&self, margin: f64) -> Self {
+        Self {
+            min: self.min.add_scalar(-margin),
+            max: self.max.add_scalar(margin),
+        }
+    }
+
+    pub fn center(&self) -> Float3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn size(&self) -> Float3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.size();
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn corners(&self) -> [Float3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            float3::new(a.x, a.y, a.z),
+            float3::new(b.x, a.y, a.z),
+            float3::new(a.x, b.y, a.z),
+            float3::new(b.x, b.y, a.z),
+            float3::new(a.x, a.y, b.z),
+            float3::new(b.x, a.y, b.z),
+            float3::new(a.x, b.y, b.z),
+            float3::new(b.x, b.y, b.z),
+        ]
+    }
+
+    // スラブ法. 交差区間の入口のtを返す
+    pub fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<f64> {
+        let mut tmin = t0;
+        let mut tmax = t1;
+        for i in 0..3 {
+            let inv = ray.direction[i].recip();
+            let mut near = (self.min[i] - ray.origin[i]) * inv;
+            let mut far = (self.max[i] - ray.origin[i]) * inv;
+            if inv < 0.0 {
+                std::mem::swap(&mut near, &mut far);
+            }
+            // 丸め誤差で隣接する箱の間をすり抜けないよう少し広げる
+            far *= 1.0 + 4.0 * f64::EPSILON;
+            // NaN (0 * inf) のときは区間を狭めない
+            if near > tmin {
+                tmin = near;
+            }
+            if far < tmax {
+                tmax = far;
+            }
+            if tmax < tmin {
+                return None;
+            }
+        }
+        Some(tmin)
+    }
+}
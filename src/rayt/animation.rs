@@ -0,0 +1,492 @@
+use crate::rayt::*;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbImage};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+// キーフレームの間を補間できる値
+pub trait Interpolate: Clone {
+    fn interpolate(&self, other: &Self, t: f64) -> Self;
+}
+
+impl Interpolate for f64 {
+    fn interpolate(&self, other: &Self, t: f64) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Float3 {
+    fn interpolate(&self, other: &Self, t: f64) -> Self {
+        self.lerp(other, t)
+    }
+}
+
+impl Interpolate for Quat {
+    fn interpolate(&self, other: &Self, t: f64) -> Self {
+        math::slerp(self, other, t)
+    }
+}
+
+// キーから次のキーまでの補間の仕方
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    // 次のキーまで値を変えない
+    Step,
+    Linear,
+    // CSSのcubic-bezierと同じ (0, 0), (x1, y1), (x2, y2), (1, 1) のイージング曲線
+    Bezier { x1: f64, y1: f64, x2: f64, y2: f64 },
+}
+
+impl Interpolation {
+    pub const EASE_IN_OUT: Interpolation = Interpolation::Bezier {
+        x1: 0.42,
+        y1: 0.0,
+        x2: 0.58,
+        y2: 1.0,
+    };
+
+    // キーの間の経過時間の割合tを補間の重みにする
+    pub fn weight(&self, t: f64) -> f64 {
+        match *self {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => t,
+            Interpolation::Bezier { x1, y1, x2, y2 } => {
+                let bezier = |a: f64, b: f64, s: f64| {
+                    let r = 1.0 - s;
+                    3.0 * r * r * s * a + 3.0 * r * s * s * b + s * s * s
+                };
+                // x1, x2が[0, 1]ならxはsについて単調増加なので二分法で解く
+                let (x1, x2) = (x1.clamp(0.0, 1.0), x2.clamp(0.0, 1.0));
+                let (mut lo, mut hi) = (0.0, 1.0);
+                for _ in 0..32 {
+                    let mid = 0.5 * (lo + hi);
+                    if bezier(x1, x2, mid) < t {
+                        lo = mid;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                bezier(y1, y2, 0.5 * (lo + hi))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackKey<T> {
+    pub time: f64,
+    pub value: T,
+    // このキーから次のキーまでの補間
+    pub interpolation: Interpolation,
+}
+
+// 時刻 (秒) ごとのキーを補間する値の列. キーは少なくとも一つある
+// 最初のキーより前と最後のキーより後は端のキーの値になる
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    keys: Vec<TrackKey<T>>,
+}
+
+impl<T: Interpolate> Track<T> {
+    // 時刻0にキーが一つだけある (常に同じ値の) トラック
+    pub fn constant(value: T) -> Self {
+        Self {
+            keys: vec![TrackKey {
+                time: 0.0,
+                value,
+                interpolation: Interpolation::Linear,
+            }],
+        }
+    }
+
+    // キーを追加する. 同じ時刻のキーがあれば置き換える
+    pub fn key(mut self, time: f64, value: T, interpolation: Interpolation) -> Self {
+        let key = TrackKey {
+            time,
+            value,
+            interpolation,
+        };
+        let i = self.keys.partition_point(|k| k.time < time);
+        match self.keys.get(i) {
+            Some(k) if k.time == time => self.keys[i] = key,
+            _ => self.keys.insert(i, key),
+        }
+        self
+    }
+
+    pub fn keys(&self) -> &[TrackKey<T>] {
+        &self.keys
+    }
+
+    pub fn sample(&self, time: f64) -> T {
+        let i = self.keys.partition_point(|k| k.time <= time);
+        if i == 0 {
+            return self.keys[0].value.clone();
+        }
+        if i == self.keys.len() {
+            return self.keys[i - 1].value.clone();
+        }
+        let (k0, k1) = (&self.keys[i - 1], &self.keys[i]);
+        let t = (time - k0.time) / (k1.time - k0.time);
+        k0.value.interpolate(&k1.value, k0.interpolation.weight(t))
+    }
+}
+
+// Camera::from_lookatの引数とレンズ, シャッターのトラック
+#[derive(Debug, Clone)]
+pub struct CameraTrack {
+    pub origin: Track<Float3>,
+    pub lookat: Track<Float3>,
+    pub vup: Track<Float3>,
+    // 度数法
+    pub vfov: Track<f64>,
+    // 絞りの半径. 0ならピンホールカメラ
+    pub lens_radius: Track<f64>,
+    pub aperture: Aperture,
+    // Noneならlookatにピントを合わせる
+    pub focus_distance: Option<Track<f64>>,
+    // フレームの時刻からの相対的なシャッターの範囲 (秒)
+    pub shutter: (f64, f64),
+}
+
+impl CameraTrack {
+    // 動かないピンホールカメラ. 各トラックにキーを足して動かす
+    pub fn new(origin: Float3, lookat: Float3, vup: Float3, vfov: f64) -> Self {
+        Self {
+            origin: Track::constant(origin),
+            lookat: Track::constant(lookat),
+            vup: Track::constant(vup),
+            vfov: Track::constant(vfov),
+            lens_radius: Track::constant(0.0),
+            aperture: Aperture::Disk,
+            focus_distance: None,
+            shutter: (0.0, 0.0),
+        }
+    }
+
+    // フレームの時刻からopenからcloseまでシャッターを開く. 24fpsで180度なら (0.0, 1.0 / 48.0)
+    pub fn with_shutter(self, open: f64, close: f64) -> Self {
+        Self {
+            shutter: (open, close),
+            ..self
+        }
+    }
+
+    // 時刻timeのフレームでシャッターが開いている範囲 (絶対時刻)
+    pub fn shutter_at(&self, time: f64) -> (f64, f64) {
+        (time + self.shutter.0, time + self.shutter.1)
+    }
+
+    pub fn camera(&self, time: f64, aspect: f64) -> Camera {
+        let origin = self.origin.sample(time);
+        let lookat = self.lookat.sample(time);
+        let (open, close) = self.shutter_at(time);
+        let camera = Camera::from_lookat(
+            origin,
+            lookat,
+            self.vup.sample(time),
+            self.vfov.sample(time),
+            aspect,
+        );
+        match &self.focus_distance {
+            Some(track) => camera.with_focus_distance(track.sample(time)),
+            None => camera,
+        }
+        .with_aperture(self.lens_radius.sample(time))
+        .with_aperture_shape(self.aperture)
+        .with_shutter(open, close)
+    }
+}
+
+// TransformTrack::animateがシャッターの間に作るキーフレームの区間の数
+const TRACK_SHUTTER_STEPS: usize = 8;
+
+// 拡大縮小, 回転, 平行移動のトラック. Keyframeと同じ順に適用する
+#[derive(Debug, Clone)]
+pub struct TransformTrack {
+    pub translation: Track<Float3>,
+    pub rotation: Track<Quat>,
+    pub scale: Track<Float3>,
+}
+
+impl Default for TransformTrack {
+    fn default() -> Self {
+        Self {
+            translation: Track::constant(Float3::zeros()),
+            rotation: Track::constant(Quat::identity()),
+            scale: Track::constant(float3::one()),
+        }
+    }
+}
+
+impl TransformTrack {
+    pub fn keyframe(&self, time: f64) -> Keyframe {
+        Keyframe::new(time)
+            .with_translation(self.translation.sample(time))
+            .with_rotation(self.rotation.sample(time))
+            .with_scale(self.scale.sample(time))
+    }
+
+    pub fn affine(&self, time: f64) -> Affine {
+        self.keyframe(time).affine()
+    }
+
+    // startからendまでを等分した時刻と、その間にあるトラックのキーの時刻のキーフレーム
+    pub fn keyframes(&self, start: f64, end: f64, steps: usize) -> Vec<Keyframe> {
+        if end <= start {
+            return vec![self.keyframe(start)];
+        }
+        let steps = steps.max(1);
+        let mut times: Vec<f64> = (0..=steps)
+            .map(|i| start + (end - start) * i as f64 / steps as f64)
+            .collect();
+        let key_times = (self.translation.keys().iter().map(|k| k.time))
+            .chain(self.rotation.keys().iter().map(|k| k.time))
+            .chain(self.scale.keys().iter().map(|k| k.time));
+        times.extend(key_times.filter(|&t| t > start && t < end));
+        times.sort_by(f64::total_cmp);
+        times.dedup();
+        times.into_iter().map(|t| self.keyframe(t)).collect()
+    }
+
+    // トラックでshapeを動かす. start..end (CameraTrack::shutter_atの範囲) のレイの時刻で正しく動く
+    pub fn animate(
+        &self,
+        shape: Box<dyn Shape>,
+        start: f64,
+        end: f64,
+    ) -> Result<AnimatedTransform> {
+        AnimatedTransform::new(shape, self.keyframes(start, end, TRACK_SHUTTER_STEPS))
+    }
+}
+
+// 連番画像のレンダリング設定. フレームfの時刻は(f - 1) / fps秒で、frame_0001が時刻0
+// 時刻はフレーム番号だけで決まるので、範囲を分けてレンダリングしても同じフレームは同じ時刻になる
+#[derive(Debug, Clone)]
+pub struct AnimationSettings {
+    // 両端を含む
+    pub start_frame: u32,
+    pub end_frame: u32,
+    pub fps: f64,
+    // frame_0001.pngなどを書き出すディレクトリ
+    pub output_dir: String,
+    // 既にある連番画像はレンダリングしない (中断したレンダリングの再開用)
+    pub skip_existing: bool,
+    // 連番画像をまとめたアニメーションGIF / APNGの出力先
+    pub gif_file: Option<String>,
+    pub apng_file: Option<String>,
+    // ガンマと最大の反射回数に使う. 出力先とプレビューは使わない
+    pub render: RenderSettings,
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        Self {
+            start_frame: 1,
+            end_frame: 24,
+            fps: 24.0,
+            output_dir: "frames".to_string(),
+            skip_existing: true,
+            gif_file: None,
+            apng_file: None,
+            render: RenderSettings::default(),
+        }
+    }
+}
+
+impl AnimationSettings {
+    pub fn validate(&self) -> Result<()> {
+        self.render.validate()?;
+        if self.start_frame > self.end_frame {
+            return Err(Error::InvalidSettings(format!(
+                "start_frame {} is after end_frame {}",
+                self.start_frame, self.end_frame
+            )));
+        }
+        if !(self.fps.is_finite() && self.fps > 0.0) {
+            return Err(Error::InvalidSettings(format!(
+                "fps must be positive, got {}",
+                self.fps
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn frame_path(&self, frame: u32) -> PathBuf {
+        Path::new(&self.output_dir).join(format!("frame_{:04}.png", frame))
+    }
+
+    pub fn frame_time(&self, frame: u32) -> f64 {
+        (frame as f64 - 1.0) / self.fps
+    }
+}
+
+// render_animationが各フレームで何をするか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameStatus {
+    // 既にある画像を使う
+    Skipped,
+    // これからレンダリングする
+    Rendering,
+}
+
+// フレームごとにscene_atで時刻のシーンを作ってレンダリングし、連番画像に書き出す
+// モーションブラーにはカメラのシャッターをその時刻からの範囲にする (CameraTrack::with_shutter)
+// progressはフレーム番号, 画像のパス, 状態で各フレームの前に呼ばれる
+pub fn render_animation<S: SceneWithDepth + Sync>(
+    settings: &AnimationSettings,
+    mut scene_at: impl FnMut(f64) -> Result<S>,
+    mut progress: impl FnMut(u32, &Path, FrameStatus),
+) -> Result<()> {
+    settings.validate()?;
+    fs::create_dir_all(&settings.output_dir)?;
+    for frame in settings.start_frame..=settings.end_frame {
+        let path = settings.frame_path(frame);
+        if settings.skip_existing && path.exists() {
+            progress(frame, &path, FrameStatus::Skipped);
+            continue;
+        }
+        progress(frame, &path, FrameStatus::Rendering);
+        let scene = scene_at(settings.frame_time(frame))?;
+        let image = render_aa_image(&scene, &settings.render)?;
+        // 途中で止めても書きかけの画像が残らないように、書き終えてから名前を変える
+        let partial = path.with_extension("png.partial");
+        image.save_with_format(&partial, image::ImageFormat::Png)?;
+        fs::rename(&partial, &path)?;
+    }
+
+    if settings.gif_file.is_none() && settings.apng_file.is_none() {
+        return Ok(());
+    }
+    let frames = (settings.start_frame..=settings.end_frame)
+        .map(|frame| Ok(image::open(settings.frame_path(frame))?.to_rgb8()))
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(path) = &settings.gif_file {
+        write_gif(path, &frames, settings.fps)?;
+    }
+    if let Some(path) = &settings.apng_file {
+        write_apng(path, &frames, settings.fps)?;
+    }
+    Ok(())
+}
+
+// 1フレームの長さ (ミリ秒)
+fn frame_delay_ms(fps: f64) -> u32 {
+    (1000.0 / fps).round().max(1.0) as u32
+}
+
+pub fn write_gif(path: impl AsRef<Path>, frames: &[RgbImage], fps: f64) -> Result<()> {
+    let mut encoder = GifEncoder::new(BufWriter::new(File::create(path)?));
+    encoder.set_repeat(Repeat::Infinite)?;
+    let delay = Delay::from_numer_denom_ms(frame_delay_ms(fps), 1);
+    encoder.encode_frames(frames.iter().map(|img| {
+        let rgba = image::DynamicImage::ImageRgb8(img.clone()).to_rgba8();
+        Frame::from_parts(rgba, 0, 0, delay)
+    }))?;
+    Ok(())
+}
+
+pub fn write_apng(path: impl AsRef<Path>, frames: &[RgbImage], fps: f64) -> Result<()> {
+    let (width, height) = match frames.first() {
+        Some(img) => img.dimensions(),
+        None => {
+            return Err(Error::InvalidSettings(
+                "animation has no frames".to_string(),
+            ))
+        }
+    };
+    if frames.iter().any(|img| img.dimensions() != (width, height)) {
+        return Err(Error::InvalidSettings(
+            "all frames must have the same size".to_string(),
+        ));
+    }
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0)?;
+    encoder.set_frame_delay(frame_delay_ms(fps).min(u16::MAX as u32) as u16, 1000)?;
+    let mut writer = encoder.write_header()?;
+    for img in frames {
+        writer.write_image_data(img.as_raw())?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_holds_value_until_next_key() {
+        let track = Track::constant(1.0).key(0.0, 1.0, Interpolation::Step).key(
+            1.0,
+            3.0,
+            Interpolation::Step,
+        );
+        assert_eq!(track.sample(0.0), 1.0);
+        assert_eq!(track.sample(0.99), 1.0);
+        assert_eq!(track.sample(1.0), 3.0);
+    }
+
+    #[test]
+    fn linear_interpolates_between_keys() {
+        let track = Track::constant(2.0).key(2.0, 6.0, Interpolation::Linear);
+        assert!((track.sample(0.5) - 3.0).abs() < 1e-12);
+        assert!((track.sample(1.0) - 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn bezier_ease_in_out() {
+        let ease = Interpolation::EASE_IN_OUT;
+        assert!(ease.weight(0.0).abs() < 1e-6);
+        assert!((ease.weight(1.0) - 1.0).abs() < 1e-6);
+        // 対称な曲線なので中点は0.5
+        assert!((ease.weight(0.5) - 0.5).abs() < 1e-6);
+        // 始めはゆっくり, 終わりもゆっくり
+        assert!(ease.weight(0.1) < 0.1);
+        assert!(ease.weight(0.9) > 0.9);
+        let weights = (0..=20)
+            .map(|i| ease.weight(i as f64 / 20.0))
+            .collect::<Vec<_>>();
+        assert!(weights.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn straight_bezier_is_linear() {
+        let linear = Interpolation::Bezier {
+            x1: 1.0 / 3.0,
+            y1: 1.0 / 3.0,
+            x2: 2.0 / 3.0,
+            y2: 2.0 / 3.0,
+        };
+        for i in 0..=10 {
+            let t = i as f64 / 10.0;
+            assert!((linear.weight(t) - t).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn sample_clamps_outside_keys() {
+        let track = Track::constant(0.0)
+            .key(1.0, 1.0, Interpolation::Linear)
+            .key(2.0, 5.0, Interpolation::Linear);
+        assert_eq!(track.sample(-1.0), 0.0);
+        assert_eq!(track.sample(2.0), 5.0);
+        assert_eq!(track.sample(10.0), 5.0);
+    }
+
+    #[test]
+    fn keys_are_sorted_and_replaced() {
+        let track = Track::constant(0.0)
+            .key(3.0, 3.0, Interpolation::Linear)
+            .key(1.0, 1.0, Interpolation::Linear)
+            .key(2.0, 7.0, Interpolation::Linear)
+            .key(2.0, 2.0, Interpolation::Linear);
+        let times = track.keys().iter().map(|k| k.time).collect::<Vec<_>>();
+        assert_eq!(times, vec![0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(track.sample(2.0), 2.0);
+        assert!((track.sample(2.5) - 2.5).abs() < 1e-12);
+    }
+}
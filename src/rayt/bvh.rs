@@ -0,0 +1,348 @@
+use crate::rayt::*;
+
+const MAX_LEAF_SIZE: usize = 4;
+const SAH_BINS: usize = 12;
+
+enum BvhNodeKind {
+    Leaf { start: usize, count: usize },
+    // 左の子は常に自分の次のノード
+    Interior { right: usize, axis: usize },
+}
+
+struct BvhNode {
+    bounds: Aabb,
+    kind: BvhNodeKind,
+}
+
+// プリミティブをインデックスで扱う汎用のBVH
+// 交差判定自体は呼び出し側のクロージャに任せる
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(bounds.len() * 2),
+            indices: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            let centers: Vec<Float3> = bounds.iter().map(Aabb::center).collect();
+            bvh.build(bounds, &centers, 0, bounds.len());
+        }
+        bvh
+    }
+
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|n| n.bounds)
+    }
+
+    fn build(&mut self, bounds: &[Aabb], centers: &[Float3], start: usize, end: usize) -> usize {
+        let node_bounds = self.indices[start..end]
+            .iter()
+            .fold(Aabb::empty(), |acc, &i| acc.union(&bounds[i]));
+        let node = self.nodes.len();
+        let count = end - start;
+        self.nodes.push(BvhNode {
+            bounds: node_bounds,
+            kind: BvhNodeKind::Leaf { start, count },
+        });
+        if count <= MAX_LEAF_SIZE {
+            return node;
+        }
+
+        let center_bounds =
+            Aabb::from_points(self.indices[start..end].iter().map(|&i| &centers[i]));
+        let axis = center_bounds.size().iamax();
+        let extent = center_bounds.size()[axis];
+        if extent <= 0.0 {
+            return node;
+        }
+
+        let mid = match self.sah_split(bounds, centers, start, end, axis, &center_bounds) {
+            Some(mid) => mid,
+            None => {
+                // SAHで良い分割が見つからなければ中央値で分ける
+                let mid = start + count / 2;
+                self.indices[start..end].select_nth_unstable_by(count / 2, |&a, &b| {
+                    centers[a][axis].total_cmp(&centers[b][axis])
+                });
+                mid
+            }
+        };
+
+        self.build(bounds, centers, start, mid);
+        let right = self.build(bounds, centers, mid, end);
+        self.nodes[node].kind = BvhNodeKind::Interior { right, axis };
+        node
+    }
+
+    // ビン分割のSAH. 分割位置を返し、indicesをその位置で分ける
+    fn sah_split(
+        &mut self,
+        bounds: &[Aabb],
+        centers: &[Float3],
+        start: usize,
+        end: usize,
+        axis: usize,
+        center_bounds: &Aabb,
+    ) -> Option<usize> {
+        let min = center_bounds.min[axis];
+        let extent = center_bounds.size()[axis];
+        let bin_of = |i: usize| {
+            let b = ((centers[i][axis] - min) / extent * SAH_BINS as f64) as usize;
+            b.min(SAH_BINS - 1)
+        };
+
+        let mut bins = [(Aabb::empty(), 0usize); SAH_BINS];
+        for &i in &self.indices[start..end] {
+            let b = bin_of(i);
+            bins[b].0 = bins[b].0.union(&bounds[i]);
+            bins[b].1 += 1;
+        }
+
+        let mut best: Option<(usize, f64)> = None;
+        for split in 1..SAH_BINS {
+            let (lb, lc) = bins[..split]
+                .iter()
+                .fold((Aabb::empty(), 0), |(b, c), (bb, bc)| (b.union(bb), c + bc));
+            let (rb, rc) = bins[split..]
+                .iter()
+                .fold((Aabb::empty(), 0), |(b, c), (bb, bc)| (b.union(bb), c + bc));
+            if lc == 0 || rc == 0 {
+                continue;
+            }
+            let cost = lb.surface_area() * lc as f64 + rb.surface_area() * rc as f64;
+            if best.is_none_or(|(_, c)| cost < c) {
+                best = Some((split, cost));
+            }
+        }
+
+        let (split, _) = best?;
+        let mut mid = start;
+        for i in start..end {
+            if bin_of(self.indices[i]) < split {
+                self.indices.swap(i, mid);
+                mid += 1;
+            }
+        }
+        if mid == start || mid == end {
+            None
+        } else {
+            Some(mid)
+        }
+    }
+
+    // hit(プリミティブのインデックス, 現在の最近傍のt)
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        t0: f64,
+        t1: f64,
+        mut hit: impl FnMut(usize, f64) -> Option<HitInfo>,
+    ) -> Option<HitInfo> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut hit_info: Option<HitInfo> = None;
+        let mut closest_so_far = t1;
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if node.bounds.hit(ray, t0, closest_so_far).is_none() {
+                continue;
+            }
+            match node.kind {
+                BvhNodeKind::Leaf { start, count } => {
+                    for &i in &self.indices[start..start + count] {
+                        if let Some(info) = hit(i, closest_so_far) {
+                            closest_so_far = info.t;
+                            hit_info = Some(info);
+                        }
+                    }
+                }
+                BvhNodeKind::Interior { right, axis } => {
+                    // 近い方の子を先に調べる
+                    if ray.direction[axis] < 0.0 {
+                        stack.push(n + 1);
+                        stack.push(right);
+                    } else {
+                        stack.push(right);
+                        stack.push(n + 1);
+                    }
+                }
+            }
+        }
+        hit_info
+    }
+}
+
+// Shapeの集まりをBVHで探索する. ShapeListの代わりに使う
+// 境界箱の無い形状 (bounds()がNone) は毎回すべて調べる
+pub struct ShapeBvh {
+    shapes: Vec<Box<dyn Shape>>,
+    // BVHのプリミティブ番号 -> shapesの番号
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>,
+    bvh: Bvh,
+}
+
+impl ShapeBvh {
+    pub fn new(shapes: Vec<Box<dyn Shape>>) -> Self {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        let mut bounds = Vec::new();
+        for (i, shape) in shapes.iter().enumerate() {
+            match shape.bounds() {
+                Some(b) => {
+                    bounded.push(i);
+                    bounds.push(b);
+                }
+                None => unbounded.push(i),
+            }
+        }
+        Self {
+            shapes,
+            bounded,
+            unbounded,
+            bvh: Bvh::new(&bounds),
+        }
+    }
+
+    pub fn shapes(&self) -> &[Box<dyn Shape>] {
+        &self.shapes
+    }
+}
+
+impl From<ShapeList> for ShapeBvh {
+    fn from(list: ShapeList) -> Self {
+        Self::new(list.objects)
+    }
+}
+
+impl Shape for ShapeBvh {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let mut hit_info = self.bvh.hit(ray, t0, t1, |i, closest| {
+            self.shapes[self.bounded[i]].hit(ray, t0, closest)
+        });
+        for &i in &self.unbounded {
+            let closest = hit_info.as_ref().map_or(t1, |h| h.t);
+            if let Some(info) = self.shapes[i].hit(ray, t0, closest) {
+                hit_info = Some(info);
+            }
+        }
+        // 一番近い交点だけ画面上の微分を求める
+        hit_info.map(|hit| hit.with_differentials(ray))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        if self.unbounded.is_empty() {
+            self.bvh.bounds()
+        } else {
+            None
+        }
+    }
+
+    fn export(&self, exporter: &mut SceneExporter) {
+        for shape in &self.shapes {
+            shape.export(exporter);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 再現できるように決まった列を使う
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> f64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        fn point(&mut self, size: f64) -> Float3 {
+            float3::new(self.next(), self.next(), self.next()).map(|x| (x * 2.0 - 1.0) * size)
+        }
+    }
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Box::new(ColorTexture::new(float3::fill(
+            0.5,
+        )))))
+    }
+
+    fn shapes(rng: &mut Lcg) -> Vec<Box<dyn Shape>> {
+        let m = material();
+        let mut shapes: Vec<Box<dyn Shape>> = Vec::new();
+        for _ in 0..100 {
+            let center = rng.point(10.0);
+            shapes.push(Box::new(Sphere::new(
+                center,
+                0.2 + rng.next(),
+                Arc::clone(&m),
+            )));
+            shapes.push(Box::new(Triangle::new(
+                center + rng.point(1.5),
+                center + rng.point(1.5),
+                center + rng.point(1.5),
+                Arc::clone(&m),
+            )));
+        }
+        shapes
+    }
+
+    #[test]
+    fn shape_bvh_matches_brute_force() {
+        let mut rng = Lcg(1);
+        let mut list = ShapeList::new();
+        for shape in shapes(&mut rng) {
+            list.push(shape);
+        }
+        let bvh = ShapeBvh::new(shapes(&mut Lcg(1)));
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let ray = Ray::new(rng.point(15.0), rng.point(1.0));
+            let expected = list.hit(&ray, 0.001, f64::MAX).map(|h| h.t);
+            let actual = bvh.hit(&ray, 0.001, f64::MAX).map(|h| h.t);
+            assert_eq!(expected, actual, "ray {:?}", ray.origin);
+            hits += expected.is_some() as usize;
+        }
+        // 当たるレイと外れるレイの両方を試している
+        assert!(hits > 100 && hits < 1900, "{} hits", hits);
+    }
+
+    #[test]
+    fn mesh_bvh_matches_brute_force() {
+        let mut rng = Lcg(2);
+        let positions: Vec<Float3> = (0..600).map(|_| rng.point(5.0)).collect();
+        let indices: Vec<[usize; 3]> = (0..200).map(|i| [3 * i, 3 * i + 1, 3 * i + 2]).collect();
+        let m = material();
+        let mesh =
+            TriangleMesh::new(positions, Vec::new(), Vec::new(), indices, Arc::clone(&m)).unwrap();
+        let data = mesh.data();
+        for _ in 0..2000 {
+            let ray = Ray::new(rng.point(8.0), rng.point(1.0));
+            let expected = (0..data.indices().len())
+                .filter_map(|i| data.hit_triangle(i, &ray, 0.001, f64::MAX, &m))
+                .map(|h| h.t)
+                .min_by(f64::total_cmp);
+            let actual = mesh.hit(&ray, 0.001, f64::MAX).map(|h| h.t);
+            assert_eq!(expected, actual, "ray {:?}", ray.origin);
+        }
+    }
+
+    #[test]
+    fn empty_bvh_misses() {
+        let bvh = ShapeBvh::new(Vec::new());
+        let ray = Ray::new(Float3::zeros(), Float3::x());
+        assert!(bvh.hit(&ray, 0.001, f64::MAX).is_none());
+        assert!(Bvh::new(&[]).bounds().is_none());
+    }
+}
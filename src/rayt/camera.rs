@@ -1,11 +1,32 @@
 use crate::rayt::*;
 
-#[derive(Debug)]
+// レンズの絞りの形. ボケの形になる
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Aperture {
+    #[default]
+    Disk,
+    // 絞り羽根の枚数の正多角形. rotationは回転 (ラジアン)
+    Polygon {
+        blades: u32,
+        rotation: f64,
+    },
+}
+
+// f値から絞りの半径を求めるときのセンサーの高さ (35mm判, 単位はmm)
+const SENSOR_HEIGHT_MM: f64 = 24.0;
+
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub origin: Float3,
     pub u: Float3, // left bottom -> right bottom
     pub v: Float3, // left bottom -> left up
     pub w: Float3, // world origin -> left bottom
+    // 0ならピンホールカメラ. スクリーン (u, v, w) の面にピントが合う
+    pub lens_radius: f64,
+    pub aperture: Aperture,
+    // シャッターが開いている時刻の範囲. レイの時刻はこの間で一様に取る
+    pub shutter_open: f64,
+    pub shutter_close: f64,
 }
 
 impl Camera {
@@ -15,11 +36,16 @@ impl Camera {
             u,
             v,
             w,
+            lens_radius: 0.0,
+            aperture: Aperture::Disk,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
         }
     }
 
     // vfov = vertical hov
     // aspect = w / h
+    // lookatまでの距離にピントが合う (with_focus_distanceで変えられる)
     pub fn from_lookat(
         origin: Float3,
         lookat: Float3,
@@ -29,25 +55,300 @@ impl Camera {
     ) -> Self {
         let halfh = (vfov.to_radians() * 0.5).tan();
         let halfw = aspect * halfh;
-        let w = (origin - lookat).normalize(); // lookat -> camera origin
+        let focus = (origin - lookat).norm();
+        let w = (origin - lookat) / focus; // lookat -> camera origin
         let u = vup.cross(&w);
         let v = w.cross(&u);
 
-        let uw = u * halfw;
-        let vh = v * halfh;
+        let uw = u * (halfw * focus);
+        let vh = v * (halfh * focus);
 
         Self {
             origin,
             u: uw * 2.0,
             v: vh * 2.0,
-            w: origin - w - uw - vh, // origin - wがスクリーンの中心のワールド座標
+            w: lookat - uw - vh, // lookatがスクリーンの中心
+            lens_radius: 0.0,
+            aperture: Aperture::Disk,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    // 絞りの半径 (ワールド座標の単位)
+    pub fn with_aperture(self, lens_radius: f64) -> Self {
+        Self {
+            lens_radius: lens_radius.max(0.0),
+            ..self
+        }
+    }
+
+    // f値で絞りを決める. 35mm判のセンサーで1単位 = 1mとしたときの大きさになる
+    pub fn with_f_stop(self, f_number: f64) -> Self {
+        let half_vfov = (self.v.norm() * 0.5 / self.focus_distance()).atan();
+        let focal_length_mm = SENSOR_HEIGHT_MM * 0.5 / half_vfov.tan();
+        let lens_radius = focal_length_mm / (2.0 * f_number) * 0.001;
+        self.with_aperture(lens_radius)
+    }
+
+    pub fn with_aperture_shape(self, aperture: Aperture) -> Self {
+        Self { aperture, ..self }
+    }
+
+    pub fn with_shutter(self, open: f64, close: f64) -> Self {
+        Self {
+            shutter_open: open,
+            shutter_close: close,
+            ..self
+        }
+    }
+
+    // 画角を変えずにスクリーンをoriginからdistanceの位置に動かす
+    // 正の有限な値でなければスクリーンが潰れるので、何もしない
+    pub fn with_focus_distance(self, distance: f64) -> Self {
+        if !(distance.is_finite() && distance > 0.0) {
+            return self;
+        }
+        let k = distance / self.focus_distance();
+        Self {
+            u: self.u * k,
+            v: self.v * k,
+            w: self.origin + (self.w - self.origin) * k,
+            ..self
         }
     }
 
+    // 画面中央のレイが最初に当たった点にピントを合わせる. 何にも当たらなければそのまま
+    pub fn with_autofocus(self, world: &dyn Shape) -> Self {
+        let ray = Ray::new(self.origin, self.center() - self.origin);
+        match world.hit(&ray, EPS, f64::MAX) {
+            Some(hit) => {
+                let forward = (self.center() - self.origin).normalize();
+                let distance = (hit.p - self.origin).dot(&forward);
+                self.with_focus_distance(distance)
+            }
+            None => self,
+        }
+    }
+
+    // originからピントの合う面までの距離
+    pub fn focus_distance(&self) -> f64 {
+        (self.center() - self.origin).norm()
+    }
+
+    fn center(&self) -> Float3 {
+        self.w + (self.u + self.v) * 0.5
+    }
+
     pub fn ray(&self, u: f64, v: f64) -> Ray {
-        Ray {
-            origin: self.origin,
-            direction: self.w + self.u * u + self.v * v - self.origin,
+        let (lens, time) = self.sample_lens();
+        self.ray_through(u, v, lens, time)
+    }
+
+    // 3本のレイでレンズ上の位置と時刻を揃える
+    pub fn ray_differential(&self, u: f64, v: f64, du: f64, dv: f64) -> Ray {
+        let (lens, time) = self.sample_lens();
+        let rx = self.ray_through(u + du, v, lens, time);
+        let ry = self.ray_through(u, v + dv, lens, time);
+        self.ray_through(u, v, lens, time)
+            .with_differential(RayDifferential {
+                rx_origin: rx.origin,
+                rx_direction: rx.direction,
+                ry_origin: ry.origin,
+                ry_direction: ry.direction,
+            })
+    }
+
+    // レンズ上の位置 (スクリーンのu, vの向き) と時刻
+    fn sample_lens(&self) -> (Float3, f64) {
+        let time = math::random_limit(self.shutter_open, self.shutter_close);
+        if self.lens_radius <= 0.0 {
+            return (Float3::zeros(), time);
+        }
+        let lens = match self.aperture {
+            Aperture::Disk => math::random_in_unit_disk(),
+            Aperture::Polygon { blades, rotation } => {
+                math::random_in_regular_polygon(blades.max(3), rotation)
+            }
+        } * self.lens_radius;
+        let offset = self.u.normalize() * lens.x + self.v.normalize() * lens.y;
+        (offset, time)
+    }
+
+    fn ray_through(&self, u: f64, v: f64, lens: Float3, time: f64) -> Ray {
+        let target = self.w + self.u * u + self.v * v;
+        let origin = self.origin + lens;
+        Ray::with_time(origin, target - origin, time)
+    }
+}
+
+// 写真の用語で設定するカメラ. 長さはmmで、シーンの1単位を1mとする
+// 光源の強さをcd/m^2 (nit) で指定すると、露出を掛けて適正な明るさになる
+#[derive(Debug, Clone)]
+pub struct PhysicalCamera {
+    pub focal_length: f64,
+    pub sensor_width: f64,
+    pub sensor_height: f64,
+    pub f_number: f64,
+    // 秒. 時刻0からシャッターを開けるので、動く形状の時刻も秒で指定する
+    pub shutter_speed: f64,
+    pub iso: f64,
+    // Noneならlookatにピントを合わせる
+    pub focus_distance: Option<f64>,
+}
+
+impl Default for PhysicalCamera {
+    // 35mm判, 50mm, f/8, 1/125秒, ISO 100
+    fn default() -> Self {
+        Self {
+            focal_length: 50.0,
+            sensor_width: 36.0,
+            sensor_height: SENSOR_HEIGHT_MM,
+            f_number: 8.0,
+            shutter_speed: 1.0 / 125.0,
+            iso: 100.0,
+            focus_distance: None,
+        }
+    }
+}
+
+impl PhysicalCamera {
+    // 画像の縦横比に合わせてセンサーを切り取ったときの垂直画角 (度数法)
+    pub fn vfov(&self, aspect: f64) -> f64 {
+        let height = if aspect >= self.sensor_width / self.sensor_height {
+            self.sensor_width / aspect
+        } else {
+            self.sensor_height
+        };
+        (height * 0.5 / self.focal_length).atan().to_degrees() * 2.0
+    }
+
+    // 絞りの半径 (m)
+    pub fn lens_radius(&self) -> f64 {
+        self.focal_length / (2.0 * self.f_number) * 0.001
+    }
+
+    // ISO 100での露出値
+    pub fn ev100(&self) -> f64 {
+        (self.f_number * self.f_number / self.shutter_speed * 100.0 / self.iso).log2()
+    }
+
+    // 輝度 (nit) に掛けるとフィルムの値になる係数
+    // 1.2はレンズやセンサーの損失を含めた係数 (ISO 2720のK = 12.5から)
+    pub fn exposure(&self) -> f64 {
+        1.0 / (1.2 * self.ev100().exp2())
+    }
+
+    pub fn camera(&self, origin: Float3, lookat: Float3, vup: Float3, aspect: f64) -> Camera {
+        let camera = Camera::from_lookat(origin, lookat, vup, self.vfov(aspect), aspect);
+        match self.focus_distance {
+            Some(distance) => camera.with_focus_distance(distance),
+            None => camera,
+        }
+        .with_aperture(self.lens_radius())
+        .with_shutter(0.0, self.shutter_speed)
+    }
+}
+
+// Camera::from_lookatの引数をまとめたもの. シーンファイルの読み込み結果に使う
+#[derive(Debug, Clone)]
+pub struct CameraParams {
+    pub name: Option<String>,
+    pub origin: Float3,
+    pub lookat: Float3,
+    pub vup: Float3,
+    // 度数法
+    pub vfov: f64,
+    pub aspect: Option<f64>,
+    // 左手系のレンダラ(pbrtなど)と同じ向きの画像になるよう左右反転する
+    pub mirror: bool,
+    // 被写界深度. lens_radiusが0ならピンホール
+    pub lens_radius: f64,
+    pub focus_distance: Option<f64>,
+}
+
+impl CameraParams {
+    // ファイルにアスペクト比が無ければ引数の値を使う
+    pub fn camera(&self, aspect: f64) -> Camera {
+        let camera = Camera::from_lookat(
+            self.origin,
+            self.lookat,
+            self.vup,
+            self.vfov,
+            self.aspect.unwrap_or(aspect),
+        );
+        let camera = match self.focus_distance {
+            Some(distance) => camera.with_focus_distance(distance),
+            None => camera,
+        }
+        .with_aperture(self.lens_radius);
+        if self.mirror {
+            Camera {
+                w: camera.w + camera.u,
+                u: -camera.u,
+                ..camera
+            }
+        } else {
+            camera
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera::from_lookat(Float3::zeros(), -Float3::z() * 2.0, Float3::y(), 40.0, 1.5)
+    }
+
+    #[test]
+    fn from_lookat_focuses_on_lookat() {
+        assert!((camera().focus_distance() - 2.0).abs() < 1e-9);
+        // 画角はピントの距離によらない
+        let near = Camera::from_lookat(Float3::zeros(), -Float3::z(), Float3::y(), 40.0, 1.5);
+        let far = camera();
+        assert!((near.v.norm() * 2.0 - far.v.norm()).abs() < 1e-9);
+        let (a, b) = (near.ray(0.0, 1.0), far.ray(0.0, 1.0));
+        assert!((a.direction.normalize() - b.direction.normalize()).norm() < 1e-9);
+    }
+
+    #[test]
+    fn f_stop_follows_focal_length() {
+        // 35mm判で焦点距離50mmになる画角
+        let vfov = (12.0f64 / 50.0).atan().to_degrees() * 2.0;
+        let camera = Camera::from_lookat(Float3::zeros(), -Float3::z(), Float3::y(), vfov, 1.5);
+        // 50mm f/2 の絞りの半径は12.5mm
+        assert!((camera.clone().with_f_stop(2.0).lens_radius - 0.0125).abs() < 1e-12);
+        // ピントの距離を変えても焦点距離は同じ
+        let moved = camera.with_focus_distance(7.0).with_f_stop(2.0);
+        assert!((moved.lens_radius - 0.0125).abs() < 1e-12);
+    }
+
+    #[test]
+    fn autofocus_focuses_on_the_first_hit() {
+        let material = Arc::new(Lambertian::new(Box::new(ColorTexture::new(float3::one()))));
+        let sphere = Sphere::new(float3::new(0.0, 0.0, -5.0), 1.0, material);
+        let camera = camera().with_autofocus(&sphere);
+        assert!((camera.focus_distance() - 4.0).abs() < 1e-9);
+        // 何にも当たらなければそのまま
+        let camera = Camera::from_lookat(Float3::zeros(), Float3::z(), Float3::y(), 40.0, 1.5)
+            .with_autofocus(&sphere);
+        assert!((camera.focus_distance() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn focus_distance_moves_the_screen() {
+        let camera = camera().with_focus_distance(5.0);
+        assert!((camera.focus_distance() - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn invalid_focus_distance_is_ignored() {
+        let before = camera().focus_distance();
+        for distance in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let camera = camera().with_focus_distance(distance);
+            assert_eq!(camera.focus_distance(), before);
         }
     }
 }
@@ -9,6 +9,20 @@ pub fn gamma(color: Float3, factor: f64) -> Float3 {
     Float3::from_iterator(color.iter().map(|x| x.powf(factor)))
 }
 
+// sRGBの伝達関数の逆変換 (0..1)
+pub fn srgb_to_linear(x: f64) -> f64 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// 相対輝度 (Rec.709)
+pub fn luminance(color: Float3) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
 pub fn float3_to_rgb(color: Float3) -> [u8; 3] {
     [f64_to_u8(color.x), f64_to_u8(color.y), f64_to_u8(color.z)]
 }
@@ -0,0 +1,459 @@
+use crate::rayt::*;
+
+// 正距円筒図法 (equirectangular) の画像で全方向から入ってくる光
+// 画像のuが経度, vが天頂からの角度 (v = 0が真上). EquirectangularCameraで撮った画像と同じ向き
+// 明るい方向ほど多く選ぶように、輝度で重み付けした2次元の累積分布を作っておく
+#[derive(Clone)]
+pub struct EnvironmentLight {
+    texture: ImageTexture,
+    width: usize,
+    height: usize,
+    // ピクセルごとの選ばれやすさ (周りを含めた輝度 x sinθ)
+    weights: Vec<f64>,
+    // 行を選ぶ累積分布 (height + 1個)
+    marginal: Vec<f64>,
+    // 行の中で列を選ぶ累積分布 (行ごとにwidth + 1個)
+    conditional: Vec<f64>,
+    total: f64,
+    rotation: Quat,
+    intensity: f64,
+    // 画像を左右反転して読む
+    flip: bool,
+    sun: Option<SunLight>,
+}
+
+impl EnvironmentLight {
+    // HDR画像はリニアのまま, 8bitの画像はsRGBとして読む
+    pub fn new(path: &str) -> Result<Self> {
+        Ok(Self::from_texture(ImageTexture::new(path, (1.0, 1.0))?))
+    }
+
+    pub fn from_texture(texture: ImageTexture) -> Self {
+        let (width, height) = texture.size();
+        let mut weights = Vec::with_capacity(width * height);
+        let mut conditional = Vec::with_capacity((width + 1) * height);
+        let mut row_sums = Vec::with_capacity(height);
+        let brightness: Vec<f64> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| luminance(texture.pixel(x, y)).max(0.0))
+            .collect();
+        for y in 0..height {
+            // 極に近い行は立体角が小さい
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            // バイリニア補間で隣のピクセルの光が混ざるので、周りの3x3の最大で重み付けする
+            // 暗いピクセルの端で太陽の光を拾ったときに、確率密度が小さすぎて値が跳ねないようにする
+            let row: Vec<f64> = (0..width)
+                .map(|x| {
+                    let mut max = 0.0f64;
+                    for j in y.saturating_sub(1)..(y + 2).min(height) {
+                        for i in [x + width - 1, x, x + 1] {
+                            max = max.max(brightness[j * width + i % width]);
+                        }
+                    }
+                    max * sin_theta
+                })
+                .collect();
+            let sum = cumulative(&row, &mut conditional);
+            weights.extend(row);
+            row_sums.push(sum);
+        }
+        let mut marginal = Vec::with_capacity(height + 1);
+        let total = cumulative(&row_sums, &mut marginal);
+        Self {
+            texture,
+            width,
+            height,
+            weights,
+            marginal,
+            conditional,
+            total,
+            rotation: Quat::identity(),
+            intensity: 1.0,
+            flip: false,
+            sun: None,
+        }
+    }
+
+    // 一様な色の環境光
+    pub fn constant(color: Float3) -> Self {
+        let texture = ImageTexture::from_pixels(vec![color], 1, 1, (1.0, 1.0))
+            .expect("a single pixel is a valid image");
+        Self::from_texture(texture)
+    }
+
+    // 画像の座標系からワールドへの回転
+    pub fn with_rotation(mut self, rotation: Quat) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_flip(mut self, flip: bool) -> Self {
+        self.flip = flip;
+        self
+    }
+
+    // 画像に太陽を加える. 太陽の向きは画像の座標系で、画像と一緒に回る
+    pub fn with_sun(mut self, sun: SunLight) -> Self {
+        self.sun = Some(sun);
+        self
+    }
+
+    // 方向dから入ってくる光
+    pub fn radiance(&self, d: &Float3) -> Float3 {
+        let (u, v) = self.direction_to_uv(d);
+        let sun = match &self.sun {
+            Some(sun) if sun.contains(&self.rotation.inverse_transform_vector(d)) => sun.radiance,
+            _ => Float3::zeros(),
+        };
+        (self.lookup(u, v) + sun) * self.intensity
+    }
+
+    // 明るさに比例した確率で方向を選ぶ. (方向, その方向の光, 立体角あたりの確率密度)
+    // 真っ黒な画像ではNone
+    pub fn sample(&self) -> Option<(Float3, Float3, f64)> {
+        let d = match &self.sun {
+            Some(sun) if rand::random::<f64>() < self.sun_probability() => {
+                self.rotation.transform_vector(&sun.sample())
+            }
+            _ => self.sample_image()?,
+        };
+        let pdf = self.pdf(&d);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((d, self.radiance(&d), pdf))
+    }
+
+    // sampleが方向dを選ぶ確率密度
+    pub fn pdf(&self, d: &Float3) -> f64 {
+        match &self.sun {
+            Some(sun) => {
+                let p = self.sun_probability();
+                let local = self.rotation.inverse_transform_vector(d);
+                p * sun.pdf(&local) + (1.0 - p) * self.image_pdf(d)
+            }
+            None => self.image_pdf(d),
+        }
+    }
+
+    // 太陽と画像のどちらを選ぶかは、それぞれの光の量の比で決める
+    fn sun_probability(&self) -> f64 {
+        let sun = match &self.sun {
+            Some(sun) => luminance(sun.radiance).max(0.0) * sun.solid_angle(),
+            None => return 0.0,
+        };
+        // 重みの合計はおよそ画像の輝度の立体角での積分になる
+        let image = self.total * 2.0 * PI * PI / (self.width * self.height) as f64;
+        if sun + image > 0.0 {
+            sun / (sun + image)
+        } else {
+            0.0
+        }
+    }
+
+    fn sample_image(&self) -> Option<Float3> {
+        if self.total <= 0.0 {
+            return None;
+        }
+        let (y, fy) = sample_cumulative(&self.marginal, rand::random::<f64>());
+        let row = &self.conditional[y * (self.width + 1)..(y + 1) * (self.width + 1)];
+        let (x, fx) = sample_cumulative(row, rand::random::<f64>());
+        let u = (x as f64 + fx) / self.width as f64;
+        let v = (y as f64 + fy) / self.height as f64;
+        Some(self.uv_to_direction(u, v))
+    }
+
+    fn image_pdf(&self, d: &Float3) -> f64 {
+        if self.total <= 0.0 {
+            return 0.0;
+        }
+        let (u, v) = self.direction_to_uv(d);
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.uv_pdf(x, y, v)
+    }
+
+    // uvの正方形あたりの密度を立体角あたりに直す
+    fn uv_pdf(&self, x: usize, y: usize, v: f64) -> f64 {
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let pdf_uv =
+            self.weights[y * self.width + x] * (self.width * self.height) as f64 / self.total;
+        pdf_uv / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn direction_to_uv(&self, d: &Float3) -> (f64, f64) {
+        let d = self.rotation.inverse_transform_vector(d).normalize();
+        let u = 0.5 + d.x.atan2(-d.z) / PI2;
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        let u = u.rem_euclid(1.0);
+        (if self.flip { 1.0 - u } else { u }, v)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Float3 {
+        let u = if self.flip { 1.0 - u } else { u };
+        self.rotation.transform_vector(&equirect_direction(u, v))
+    }
+
+    // 経度方向は繰り返し, 緯度方向は端で止めるバイリニア補間
+    fn lookup(&self, u: f64, v: f64) -> Float3 {
+        let (w, h) = (self.width as i64, self.height as i64);
+        let x = u * self.width as f64 - 0.5;
+        let y = v * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let texel = |i: i64, j: i64| {
+            self.texture
+                .pixel(i.rem_euclid(w) as usize, j.clamp(0, h - 1) as usize)
+        };
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = texel(x0, y0).lerp(&texel(x0 + 1, y0), fx);
+        let bottom = texel(x0, y0 + 1).lerp(&texel(x0 + 1, y0 + 1), fx);
+        top.lerp(&bottom, fy)
+    }
+}
+
+// 正距円筒図法の画像のuvが表す方向 (回転する前)
+pub fn equirect_direction(u: f64, v: f64) -> Float3 {
+    let lon = PI2 * (u - 0.5);
+    let (sin_theta, cos_theta) = (PI * v).sin_cos();
+    float3::new(sin_theta * lon.sin(), cos_theta, -sin_theta * lon.cos())
+}
+
+// 太陽のように見かけの大きさを持つ遠くの円盤の光
+// 大きさがあるので影の縁がぼける
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SunLight {
+    direction: Float3,
+    // 円盤の縁の方向とのなす角のcos
+    cos_max: f64,
+    radiance: Float3,
+}
+
+impl SunLight {
+    // angular_diameterは見かけの直径 (度). 地上から見た太陽は約0.53度
+    pub fn new(direction: Float3, angular_diameter: f64, radiance: Float3) -> Self {
+        Self {
+            direction: direction.normalize(),
+            cos_max: (0.5 * angular_diameter.to_radians()).cos(),
+            radiance,
+        }
+    }
+
+    pub fn direction(&self) -> Float3 {
+        self.direction
+    }
+
+    pub fn radiance(&self) -> Float3 {
+        self.radiance
+    }
+
+    pub fn solid_angle(&self) -> f64 {
+        PI2 * (1.0 - self.cos_max)
+    }
+
+    fn contains(&self, d: &Float3) -> bool {
+        d.normalize().dot(&self.direction) >= self.cos_max
+    }
+
+    // 円錐の中の一様な方向
+    fn sample(&self) -> Float3 {
+        let cos_theta = 1.0 - rand::random::<f64>() * (1.0 - self.cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = PI2 * rand::random::<f64>();
+        let (t, b) = math::orthonormal_basis(&self.direction);
+        (t * phi.cos() + b * phi.sin()) * sin_theta + self.direction * cos_theta
+    }
+
+    fn pdf(&self, d: &Float3) -> f64 {
+        if self.contains(d) {
+            1.0 / self.solid_angle()
+        } else {
+            0.0
+        }
+    }
+}
+
+// 累積分布を0..1に正規化してcdfに追加し、合計を返す. 合計が0なら一様にする
+fn cumulative(values: &[f64], cdf: &mut Vec<f64>) -> f64 {
+    let sum: f64 = values.iter().sum();
+    let n = values.len() as f64;
+    let mut acc = 0.0;
+    cdf.push(0.0);
+    for (i, x) in values.iter().enumerate() {
+        acc += x;
+        cdf.push(if sum > 0.0 {
+            acc / sum
+        } else {
+            (i + 1) as f64 / n
+        });
+    }
+    sum
+}
+
+// 累積分布からrを含む区間を探す. (区間の番号, 区間の中の位置 0..1)
+fn sample_cumulative(cdf: &[f64], r: f64) -> (usize, f64) {
+    let n = cdf.len() - 1;
+    let i = cdf.partition_point(|&c| c <= r).clamp(1, n) - 1;
+    let width = cdf[i + 1] - cdf[i];
+    let f = if width > 0.0 {
+        (r - cdf[i]) / width
+    } else {
+        0.5
+    };
+    (i, f.clamp(0.0, 1.0))
+}
+
+// 2つのサンプリング方法のどちらでも選べる方向の重み (power heuristic)
+fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other * other);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+// 環境光を直接サンプリングするパストレーサ
+// 拡散面では環境光の方向にシャドウレイを飛ばし、散乱した方向と多重重点的サンプリング (MIS) で組み合わせる
+// 何にも当たらないレイ (カメラから直接見た方向を含む) は環境光の色になる
+pub fn trace_environment(
+    world: &dyn Shape,
+    environment: &EnvironmentLight,
+    ray: Ray,
+    depth: usize,
+) -> Float3 {
+    trace_mis(world, environment, ray, depth, 0.0)
+}
+
+// scattering_pdfは直前の面がこのレイを選んだ確率密度. 0なら環境光のサンプリングと組み合わせていない
+fn trace_mis(
+    world: &dyn Shape,
+    environment: &EnvironmentLight,
+    ray: Ray,
+    depth: usize,
+    scattering_pdf: f64,
+) -> Float3 {
+    let hit = match world.hit(&ray, 0.001, f64::MAX) {
+        Some(hit) => hit,
+        None => {
+            let radiance = environment.radiance(&ray.direction);
+            if scattering_pdf <= 0.0 {
+                return radiance;
+            }
+            let light_pdf = environment.pdf(&ray.direction);
+            return radiance * power_heuristic(scattering_pdf, light_pdf);
+        }
+    };
+    let emitted = hit.m.emited(&ray, &hit);
+    if depth == 0 {
+        return emitted;
+    }
+    // 光漏れになる向きを選んでscatterがNoneでも、直接光は足して続きのレイだけ打ち切る
+    let scatter_info = hit.m.scatter(&ray, &hit);
+    let albedo = match &scatter_info {
+        Some(scatter_info) => scatter_info.albedo,
+        None => match hit.m.direct_albedo(&ray, &hit) {
+            Some(albedo) => albedo,
+            None => return emitted,
+        },
+    };
+
+    // 鏡面などのscattering_pdfが0のマテリアルはbsdf_pdfが0になるので直接光を足さない
+    let mut direct = Float3::zeros();
+    if let Some((d, radiance, light_pdf)) = environment.sample() {
+        let bsdf_pdf = hit.m.scattering_pdf(&ray, &hit, &d);
+        let shadow = Ray::with_time(hit.p, d, ray.time);
+        if bsdf_pdf > 0.0 && world.hit(&shadow, 0.001, f64::MAX).is_none() {
+            // albedoは f·cos / pdf なので、f·cos = albedo x bsdf_pdf
+            let weight = power_heuristic(light_pdf, bsdf_pdf) * bsdf_pdf / light_pdf;
+            direct = radiance.component_mul(&albedo) * weight;
+        }
+    }
+    let indirect = match scatter_info {
+        Some(scatter_info) => {
+            let pdf = hit
+                .m
+                .scattering_pdf(&ray, &hit, &scatter_info.ray.direction);
+            trace_mis(world, environment, scatter_info.ray, depth - 1, pdf).component_mul(&albedo)
+        }
+        None => Float3::zeros(),
+    };
+    emitted + direct + indirect
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 明るさが場所で違う8x4の環境光
+    fn synthetic() -> EnvironmentLight {
+        let pixels = (0..32)
+            .map(|i| float3::fill(((i * 7) % 5) as f64 + 0.1))
+            .collect();
+        let texture = ImageTexture::from_pixels(pixels, 8, 4, (1.0, 1.0)).unwrap();
+        EnvironmentLight::from_texture(texture)
+    }
+
+    #[test]
+    fn sample_pdf_matches_pdf() {
+        let sun = SunLight::new(float3::new(1.0, 1.0, 0.0), 5.0, float3::fill(50.0));
+        let rotation = Quat::from_axis_angle(&Float3::y_axis(), 0.3);
+        for env in [
+            synthetic(),
+            synthetic().with_sun(sun).with_rotation(rotation),
+        ] {
+            for _ in 0..500 {
+                let (d, radiance, pdf) = env.sample().unwrap();
+                assert!(pdf > 0.0);
+                assert!((pdf - env.pdf(&d)).abs() <= 1e-9 * pdf);
+                assert_eq!(radiance, env.radiance(&d));
+            }
+        }
+    }
+
+    #[test]
+    fn image_pdf_integrates_to_one() {
+        let env = synthetic();
+        // uvの格子の中点で立体角について積分する
+        let (nu, nv) = (256, 128);
+        let mut sum = 0.0;
+        for j in 0..nv {
+            let v = (j as f64 + 0.5) / nv as f64;
+            for i in 0..nu {
+                let u = (i as f64 + 0.5) / nu as f64;
+                let d = equirect_direction(u, v);
+                sum += env.pdf(&d) * 2.0 * PI * PI * (PI * v).sin();
+            }
+        }
+        sum /= (nu * nv) as f64;
+        assert!((sum - 1.0).abs() < 1e-2, "{}", sum);
+    }
+
+    #[test]
+    fn mis_weights_sum_to_one() {
+        for (a, b) in [(1.0, 1.0), (0.2, 3.0), (5.0, 0.01), (0.0, 2.0)] {
+            let sum = power_heuristic(a, b) + power_heuristic(b, a);
+            assert!((sum - 1.0).abs() < 1e-12);
+        }
+        // 環境光とLambertianの確率密度で組み合わせる
+        let env = synthetic();
+        for _ in 0..100 {
+            let (d, _, light_pdf) = env.sample().unwrap();
+            let bsdf_pdf = d.y.max(0.0) * FRAC_1_PI;
+            if bsdf_pdf > 0.0 {
+                let sum =
+                    power_heuristic(light_pdf, bsdf_pdf) + power_heuristic(bsdf_pdf, light_pdf);
+                assert!((sum - 1.0).abs() < 1e-12);
+            }
+        }
+        assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+    }
+}
@@ -0,0 +1,67 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Png(png::EncodingError),
+    InvalidScene(String),
+    InvalidSettings(String),
+    #[cfg(feature = "window")]
+    Window(minifb::Error),
+}
+
+// 第二引数を省略すると Error になる
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Image(e) => write!(f, "image error: {}", e),
+            Error::Png(e) => write!(f, "png error: {}", e),
+            Error::InvalidScene(msg) => write!(f, "invalid scene: {}", msg),
+            Error::InvalidSettings(msg) => write!(f, "invalid settings: {}", msg),
+            #[cfg(feature = "window")]
+            Error::Window(e) => write!(f, "window error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Image(e) => Some(e),
+            Error::Png(e) => Some(e),
+            #[cfg(feature = "window")]
+            Error::Window(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        Error::Image(e)
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(e: png::EncodingError) -> Self {
+        Error::Png(e)
+    }
+}
+
+#[cfg(feature = "window")]
+impl From<minifb::Error> for Error {
+    fn from(e: minifb::Error) -> Self {
+        Error::Window(e)
+    }
+}
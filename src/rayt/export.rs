@@ -0,0 +1,568 @@
+use crate::rayt::*;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+// 書き出し用のポリゴンメッシュ. 頂点はワールド座標
+// facesは三角形か四角形の頂点インデックス
+pub struct ExportMesh {
+    pub name: String,
+    pub positions: Vec<Float3>,
+    pub normals: Vec<Float3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub faces: Vec<Vec<usize>>,
+    pub material: usize,
+}
+
+impl ExportMesh {
+    fn triangles(&self) -> impl Iterator<Item = [usize; 3]> + '_ {
+        self.faces
+            .iter()
+            .flat_map(|f| (1..f.len().saturating_sub(1)).map(move |i| [f[0], f[i], f[i + 1]]))
+    }
+}
+
+// 書き出すカメラ. from_lookatと同じ引数
+struct ExportCamera {
+    origin: Float3,
+    forward: Float3,
+    up: Float3,
+    vfov: f64,
+    aspect: f64,
+}
+
+// Shapeの木をたどってポリゴンメッシュに変換し、OBJ/glTFに書き出す
+// 球はテッセレーションし、Transformは頂点に焼き込む
+// マテリアルはMaterial::previewの近似になる
+#[derive(Default)]
+pub struct SceneExporter {
+    pub meshes: Vec<ExportMesh>,
+    pub materials: Vec<MaterialPreview>,
+    // 書き出せなかった形状などについての警告
+    pub warnings: Vec<String>,
+    material_ids: HashMap<*const (), usize>,
+    transform: Option<Mat4>,
+    camera: Option<ExportCamera>,
+}
+
+impl SceneExporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_shape(&mut self, shape: &dyn Shape) {
+        shape.export(self);
+    }
+
+    pub fn set_camera(&mut self, camera: &Camera) {
+        let center = camera.w + camera.u * 0.5 + camera.v * 0.5;
+        let forward = center - camera.origin;
+        let height = camera.v.norm();
+        self.camera = Some(ExportCamera {
+            origin: camera.origin,
+            forward: forward.normalize(),
+            up: camera.v.normalize(),
+            vfov: (height * 0.5 / forward.norm()).atan() * 2.0,
+            aspect: camera.u.norm() / height,
+        });
+    }
+
+    pub fn warn(&mut self, msg: String) {
+        if !self.warnings.contains(&msg) {
+            self.warnings.push(msg);
+        }
+    }
+
+    // fの中で追加した形状にmを適用する. 入れ子にすると外側の行列が後に掛かる
+    pub fn with_transform(&mut self, m: &Mat4, f: impl FnOnce(&mut Self)) {
+        let parent = self.transform;
+        self.transform = Some(parent.map_or(*m, |p| p * m));
+        f(self);
+        self.transform = parent;
+    }
+
+    // 頂点は形状のローカル座標. normals, uvsは空でもよい
+    pub fn add_polygons(
+        &mut self,
+        name: &str,
+        positions: Vec<Float3>,
+        normals: Vec<Float3>,
+        uvs: Vec<(f64, f64)>,
+        faces: Vec<Vec<usize>>,
+        material: &Arc<dyn Material>,
+    ) {
+        let m = self.transform.unwrap_or_else(Mat4::identity);
+        let linear = m.fixed_slice::<3, 3>(0, 0).into_owned();
+        let normal_matrix = linear.try_inverse().map_or(linear, |inv| inv.transpose());
+        let flip = linear.determinant() < 0.0;
+        let key = Arc::as_ptr(material) as *const ();
+        let material = match self.material_ids.get(&key) {
+            Some(&id) => id,
+            None => {
+                self.materials.push(material.preview());
+                self.material_ids.insert(key, self.materials.len() - 1);
+                self.materials.len() - 1
+            }
+        };
+        self.meshes.push(ExportMesh {
+            name: name.to_string(),
+            positions: positions
+                .iter()
+                .map(|p| m.transform_point(&na::Point3::from(*p)).coords)
+                .collect(),
+            normals: normals
+                .iter()
+                .map(|n| (normal_matrix * n).normalize())
+                .collect(),
+            uvs,
+            faces: faces
+                .into_iter()
+                .map(|mut f| {
+                    if flip {
+                        f.reverse();
+                    }
+                    f
+                })
+                .collect(),
+            material,
+        });
+    }
+
+    // 拡張子で形式を判定する (.obj または .gltf)
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("obj") => self.write_obj(path),
+            Some("gltf") => self.write_gltf(path),
+            _ => Err(Error::InvalidSettings(format!(
+                "{}: unknown export format (expected .obj or .gltf)",
+                path.display()
+            ))),
+        }
+    }
+
+    // 同じ名前の.mtlも書き出す. OBJにはカメラが無いのでコメントに残す
+    pub fn write_obj(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mtl_path = path.with_extension("mtl");
+        let mtl_name = mtl_path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let mut obj = String::new();
+        obj.push_str("# exported by ayanami\n");
+        if let Some(c) = &self.camera {
+            let lookat = c.origin + c.forward;
+            let _ = writeln!(
+                obj,
+                "# camera: origin {} {} {}, lookat {} {} {}, vup {} {} {}, vfov {}, aspect {}",
+                c.origin.x,
+                c.origin.y,
+                c.origin.z,
+                lookat.x,
+                lookat.y,
+                lookat.z,
+                c.up.x,
+                c.up.y,
+                c.up.z,
+                c.vfov.to_degrees(),
+                c.aspect
+            );
+        }
+        let _ = writeln!(obj, "mtllib {}", mtl_name);
+        let (mut v_offset, mut vt_offset, mut vn_offset) = (1, 1, 1);
+        for mesh in &self.meshes {
+            let _ = writeln!(obj, "o {}", mesh.name);
+            for p in &mesh.positions {
+                let _ = writeln!(obj, "v {} {} {}", p.x, p.y, p.z);
+            }
+            // OBJはv = 0が画像の下端
+            for (u, v) in &mesh.uvs {
+                let _ = writeln!(obj, "vt {} {}", u, 1.0 - v);
+            }
+            for n in &mesh.normals {
+                let _ = writeln!(obj, "vn {} {} {}", n.x, n.y, n.z);
+            }
+            let _ = writeln!(obj, "usemtl material{}", mesh.material);
+            for face in &mesh.faces {
+                obj.push('f');
+                for &i in face {
+                    let _ = match (mesh.uvs.is_empty(), mesh.normals.is_empty()) {
+                        (true, true) => write!(obj, " {}", i + v_offset),
+                        (false, true) => write!(obj, " {}/{}", i + v_offset, i + vt_offset),
+                        (true, false) => write!(obj, " {}//{}", i + v_offset, i + vn_offset),
+                        (false, false) => {
+                            write!(obj, " {}/{}/{}", i + v_offset, i + vt_offset, i + vn_offset)
+                        }
+                    };
+                }
+                obj.push('\n');
+            }
+            v_offset += mesh.positions.len();
+            vt_offset += mesh.uvs.len();
+            vn_offset += mesh.normals.len();
+        }
+
+        // ObjModelで読み込むと同じ種類のマテリアルになるようにする
+        let mut mtl = String::new();
+        for (i, m) in self.materials.iter().enumerate() {
+            let _ = writeln!(mtl, "newmtl material{}", i);
+            let _ = writeln!(mtl, "Kd {} {} {}", m.color.x, m.color.y, m.color.z);
+            if m.emission.max() > 0.0 {
+                let e = m.emission;
+                let _ = writeln!(mtl, "Ke {} {} {}", e.x, e.y, e.z);
+            }
+            if m.transmission > 0.0 {
+                let _ = writeln!(mtl, "Ni {}\nillum 7", m.ior);
+            } else if m.metallic >= 0.5 {
+                let c = m.color;
+                let _ = writeln!(mtl, "Ks {} {} {}", c.x, c.y, c.z);
+                let _ = writeln!(mtl, "Ns {}\nillum 3", (1.0 - m.roughness) * 1000.0);
+            } else {
+                mtl.push_str("illum 1\n");
+            }
+            mtl.push('\n');
+        }
+
+        fs::write(path, obj)?;
+        fs::write(mtl_path, mtl)?;
+        Ok(())
+    }
+
+    // 頂点データは同じ名前の.binに書き出す
+    pub fn write_gltf(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let bin_path = path.with_extension("bin");
+        let bin_name = bin_path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let mut bin: Vec<u8> = Vec::new();
+        let mut views = Vec::new();
+        let mut accessors = Vec::new();
+        // bufferViewとaccessorを1つずつ追加し、accessorのインデックスを返す
+        let mut push = |bytes: Vec<u8>, target: u32, accessor: String| {
+            let offset = bin.len();
+            bin.extend_from_slice(&bytes);
+            views.push(format!(
+                "{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{},\"target\":{}}}",
+                offset,
+                bytes.len(),
+                target
+            ));
+            accessors.push(format!(
+                "{{\"bufferView\":{},{}}}",
+                views.len() - 1,
+                accessor
+            ));
+            accessors.len() - 1
+        };
+        let f32_bytes = |values: &mut dyn Iterator<Item = f64>| -> Vec<u8> {
+            values.flat_map(|x| (x as f32).to_le_bytes()).collect()
+        };
+
+        let mut meshes = Vec::new();
+        let mut nodes = Vec::new();
+        // 頂点の無いメッシュはglTFでは書けない (accessorのcountは1以上)
+        for mesh in self.meshes.iter().filter(|m| !m.positions.is_empty()) {
+            if mesh
+                .positions
+                .iter()
+                .any(|p| !p.iter().all(|x| x.is_finite()))
+            {
+                return Err(Error::InvalidScene(format!(
+                    "mesh {} has a non-finite vertex position",
+                    mesh.name
+                )));
+            }
+            let bounds = Aabb::from_points(&mesh.positions);
+            let positions = push(
+                f32_bytes(&mut mesh.positions.iter().flat_map(|p| p.iter().copied())),
+                34962,
+                format!(
+                    "\"componentType\":5126,\"count\":{},\"type\":\"VEC3\",\"min\":{},\"max\":{}",
+                    mesh.positions.len(),
+                    json_floats(bounds.min.iter().map(|&x| x as f32 as f64))?,
+                    json_floats(bounds.max.iter().map(|&x| x as f32 as f64))?
+                ),
+            );
+            let mut attributes = format!("\"POSITION\":{}", positions);
+            if !mesh.normals.is_empty() {
+                let normals = push(
+                    f32_bytes(&mut mesh.normals.iter().flat_map(|n| n.iter().copied())),
+                    34962,
+                    format!(
+                        "\"componentType\":5126,\"count\":{},\"type\":\"VEC3\"",
+                        mesh.normals.len()
+                    ),
+                );
+                let _ = write!(attributes, ",\"NORMAL\":{}", normals);
+            }
+            if !mesh.uvs.is_empty() {
+                let uvs = push(
+                    f32_bytes(&mut mesh.uvs.iter().flat_map(|&(u, v)| [u, v])),
+                    34962,
+                    format!(
+                        "\"componentType\":5126,\"count\":{},\"type\":\"VEC2\"",
+                        mesh.uvs.len()
+                    ),
+                );
+                let _ = write!(attributes, ",\"TEXCOORD_0\":{}", uvs);
+            }
+            let triangles: Vec<u32> = mesh.triangles().flatten().map(|i| i as u32).collect();
+            let indices = push(
+                triangles.iter().flat_map(|i| i.to_le_bytes()).collect(),
+                34963,
+                format!(
+                    "\"componentType\":5125,\"count\":{},\"type\":\"SCALAR\"",
+                    triangles.len()
+                ),
+            );
+            meshes.push(format!(
+                "{{\"name\":{},\"primitives\":[{{\"attributes\":{{{}}},\"indices\":{},\"material\":{}}}]}}",
+                json_string(&mesh.name),
+                attributes,
+                indices,
+                mesh.material
+            ));
+            nodes.push(format!(
+                "{{\"name\":{},\"mesh\":{}}}",
+                json_string(&mesh.name),
+                meshes.len() - 1
+            ));
+        }
+
+        let mut cameras = Vec::new();
+        if let Some(c) = &self.camera {
+            // glTFのカメラは-zが前, +yが上
+            let back = -c.forward;
+            let right = c.up.cross(&back).normalize();
+            let up = back.cross(&right);
+            let rotation =
+                na::UnitQuaternion::from_rotation_matrix(&na::Rotation3::from_basis_unchecked(&[
+                    right, up, back,
+                ]));
+            let q = rotation.coords;
+            cameras.push(format!(
+                "{{\"type\":\"perspective\",\"perspective\":{{\"yfov\":{},\"aspectRatio\":{},\"znear\":0.01}}}}",
+                json_float(c.vfov)?,
+                json_float(c.aspect)?
+            ));
+            nodes.push(format!(
+                "{{\"name\":\"camera\",\"camera\":0,\"translation\":{},\"rotation\":{}}}",
+                json_floats(c.origin.iter().copied())?,
+                json_floats([q.x, q.y, q.z, q.w].into_iter())?
+            ));
+        }
+
+        let mut extensions: Vec<&str> = Vec::new();
+        let materials = self
+            .materials
+            .iter()
+            .enumerate()
+            .map(|(i, m)| -> Result<String> {
+                let c = m.color;
+                let mut json = format!(
+                    "{{\"name\":\"material{}\",\"pbrMetallicRoughness\":{{\"baseColorFactor\":{},\"metallicFactor\":{},\"roughnessFactor\":{}}}",
+                    i,
+                    json_floats([c.x, c.y, c.z, 1.0].into_iter())?,
+                    json_float(m.metallic)?,
+                    json_float(m.roughness)?
+                );
+                let mut material_extensions = Vec::new();
+                let strength = m.emission.max();
+                if strength > 0.0 {
+                    // emissiveFactorは0-1なので、それを超える分は強度の拡張に入れる
+                    let scale = strength.max(1.0);
+                    let _ = write!(
+                        json,
+                        ",\"emissiveFactor\":{}",
+                        json_floats((m.emission / scale).iter().copied())?
+                    );
+                    if scale > 1.0 {
+                        extensions.push("KHR_materials_emissive_strength");
+                        material_extensions.push(format!(
+                            "\"KHR_materials_emissive_strength\":{{\"emissiveStrength\":{}}}",
+                            json_float(scale)?
+                        ));
+                    }
+                }
+                if m.transmission > 0.0 {
+                    extensions.push("KHR_materials_transmission");
+                    extensions.push("KHR_materials_ior");
+                    material_extensions.push(format!(
+                        "\"KHR_materials_transmission\":{{\"transmissionFactor\":{}}}",
+                        json_float(m.transmission)?
+                    ));
+                    material_extensions.push(format!(
+                        "\"KHR_materials_ior\":{{\"ior\":{}}}",
+                        json_float(m.ior)?
+                    ));
+                }
+                if !material_extensions.is_empty() {
+                    let _ = write!(json, ",\"extensions\":{{{}}}", material_extensions.join(","));
+                }
+                json.push('}');
+                Ok(json)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        extensions.sort_unstable();
+        extensions.dedup();
+
+        let mut json = String::new();
+        json.push_str("{\n\"asset\":{\"version\":\"2.0\",\"generator\":\"ayanami\"},\n");
+        if !extensions.is_empty() {
+            let names: Vec<String> = extensions.iter().map(|e| json_string(e)).collect();
+            let _ = writeln!(json, "\"extensionsUsed\":[{}],", names.join(","));
+        }
+        let _ = writeln!(
+            json,
+            "\"scene\":0,\n\"scenes\":[{{\"nodes\":[{}]}}],",
+            (0..nodes.len())
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        for (name, items) in [
+            ("nodes", &nodes),
+            ("meshes", &meshes),
+            ("materials", &materials),
+            ("cameras", &cameras),
+            ("accessors", &accessors),
+            ("bufferViews", &views),
+        ] {
+            if !items.is_empty() {
+                let _ = writeln!(json, "\"{}\":[\n{}\n],", name, items.join(",\n"));
+            }
+        }
+        let _ = write!(
+            json,
+            "\"buffers\":[{{\"uri\":{},\"byteLength\":{}}}]\n}}\n",
+            json_string(&bin_name),
+            bin.len()
+        );
+
+        fs::write(path, json)?;
+        fs::write(bin_path, bin)?;
+        Ok(())
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// JSONにはinfやNaNを書けない
+fn json_float(x: f64) -> Result<String> {
+    if x.is_finite() {
+        Ok(x.to_string())
+    } else {
+        Err(Error::InvalidScene(format!(
+            "cannot write a non-finite number ({}) to glTF",
+            x
+        )))
+    }
+}
+
+fn json_floats(values: impl Iterator<Item = f64>) -> Result<String> {
+    let items = values.map(json_float).collect::<Result<Vec<_>>>()?;
+    Ok(format!("[{}]", items.join(",")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exporter(positions: Vec<Float3>) -> SceneExporter {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new(Box::new(ColorTexture::new(
+            float3::fill(0.5),
+        ))));
+        let mut exporter = SceneExporter::new();
+        let faces = if positions.is_empty() {
+            Vec::new()
+        } else {
+            vec![vec![0, 1, 2]]
+        };
+        exporter.add_polygons("mesh", positions, Vec::new(), Vec::new(), faces, &material);
+        exporter
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ayanami-{}-{}.gltf", name, std::process::id()))
+    }
+
+    #[test]
+    fn skips_meshes_without_positions() {
+        let path = temp_path("empty");
+        exporter(Vec::new()).write_gltf(&path).unwrap();
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(!json.contains("\"meshes\""));
+        assert!(!json.contains("inf"));
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("bin")).unwrap();
+    }
+
+    #[test]
+    fn rejects_non_finite_positions() {
+        let path = temp_path("nan");
+        let positions = vec![
+            Float3::zeros(),
+            Float3::x(),
+            float3::new(0.0, f64::NAN, 0.0),
+        ];
+        assert!(matches!(
+            exporter(positions).write_gltf(&path),
+            Err(Error::InvalidScene(_))
+        ));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn cube_has_six_distinct_faces() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new(Box::new(ColorTexture::new(
+            float3::fill(0.5),
+        ))));
+        let mut exporter = SceneExporter::new();
+        exporter.add_shape(&Cube::new(material));
+        let normals: Vec<Float3> = exporter
+            .meshes
+            .iter()
+            .flat_map(|mesh| {
+                mesh.faces.iter().map(|f| {
+                    let p = |i: usize| mesh.positions[f[i]];
+                    (p(1) - p(0)).cross(&(p(2) - p(0))).normalize()
+                })
+            })
+            .collect();
+        assert_eq!(normals.len(), 6);
+        for (i, a) in normals.iter().enumerate() {
+            // 外向きで、どの2つも同じ向きではない
+            let center: Float3 = exporter.meshes[i].positions.iter().sum::<Float3>() / 4.0;
+            assert!(a.dot(&center) > 0.0, "face {} points inwards", i);
+            for b in &normals[i + 1..] {
+                assert!((a - b).norm() > 1e-6, "{:?} appears twice", a);
+            }
+        }
+    }
+
+    #[test]
+    fn json_floats_rejects_infinity() {
+        assert_eq!(json_floats([1.0, 0.5].into_iter()).unwrap(), "[1,0.5]");
+        assert!(json_floats([f64::INFINITY].into_iter()).is_err());
+    }
+}
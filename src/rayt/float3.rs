@@ -12,10 +12,31 @@ pub fn fill(x: f64) -> Float3 {
     vector![x, x, x]
 }
 
+// sRGBの8bitの色をリニアにする
 pub fn from_rgb(r: u8, g: u8, b: u8) -> Float3 {
-    vector![r as f64 / 255.0, b as f64 / 255.0, g as f64 / 255.0]
+    from_rgb_linear(r, g, b).map(color::srgb_to_linear)
+}
+
+// 色ではないデータ用. チャンネルの値をそのまま0..1にする
+pub fn from_rgb_linear(r: u8, g: u8, b: u8) -> Float3 {
+    vector![r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0]
 }
 
 pub fn new(x: f64, y: f64, z: f64) -> Float3 {
     vector![x, y, z]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_rgb_keeps_channel_order() {
+        let c = from_rgb_linear(255, 0, 51);
+        assert_eq!(c, new(1.0, 0.0, 0.2));
+        let c = from_rgb(255, 0, 128);
+        assert_eq!(c.x, 1.0);
+        assert_eq!(c.y, 0.0);
+        assert!(c.z > 0.2 && c.z < 0.25);
+    }
+}
@@ -0,0 +1,513 @@
+use crate::rayt::*;
+use std::path::Path;
+
+// 対応しているglTF拡張
+const SUPPORTED_EXTENSIONS: [&str; 3] = [
+    "KHR_materials_ior",
+    "KHR_materials_transmission",
+    "KHR_materials_emissive_strength",
+];
+
+// glTF 2.0 (.gltf/.glb) のシーン
+// ノード階層はTransformの入れ子になり、world以下にまとめられる
+pub struct GltfScene {
+    pub world: ShapeList,
+    pub cameras: Vec<CameraParams>,
+    // 非対応の機能や近似したマテリアルについての警告
+    pub warnings: Vec<String>,
+}
+
+impl GltfScene {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let (document, buffers, images) = gltf::import(path).map_err(|e| match e {
+            gltf::Error::Io(e) => Error::Io(e),
+            e => Error::InvalidScene(format!("{}: {}", path.display(), e)),
+        })?;
+        GltfLoader::new(&document, &buffers, &images).load()
+    }
+}
+
+struct GltfLoader<'a> {
+    document: &'a gltf::Document,
+    buffers: &'a [gltf::buffer::Data],
+    images: &'a [gltf::image::Data],
+    // メッシュごとのプリミティブ. 複数のノードから参照されても1度だけ作る
+    meshes: Vec<Option<Vec<Arc<TriangleMesh>>>>,
+    // マテリアルごとに、頂点カラーを掛けないものと掛けるもの
+    materials: Vec<[Option<Arc<dyn Material>>; 2]>,
+    textures: Vec<Option<Arc<ImageTexture>>>,
+    cameras: Vec<CameraParams>,
+    warnings: Vec<String>,
+}
+
+impl<'a> GltfLoader<'a> {
+    fn new(
+        document: &'a gltf::Document,
+        buffers: &'a [gltf::buffer::Data],
+        images: &'a [gltf::image::Data],
+    ) -> Self {
+        Self {
+            document,
+            buffers,
+            images,
+            meshes: vec![None; document.meshes().len()],
+            materials: vec![[None, None]; document.materials().len()],
+            textures: vec![None; document.textures().len()],
+            cameras: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn warn(&mut self, msg: String) {
+        if !self.warnings.contains(&msg) {
+            self.warnings.push(msg);
+        }
+    }
+
+    fn load(mut self) -> Result<GltfScene> {
+        for ext in self.document.extensions_used() {
+            if !SUPPORTED_EXTENSIONS.contains(&ext) {
+                self.warn(format!(
+                    "extension {} is not supported and was ignored",
+                    ext
+                ));
+            }
+        }
+        if self.document.animations().len() > 0 {
+            self.warn("animations are not supported and were ignored".to_string());
+        }
+        if self.document.skins().len() > 0 {
+            self.warn("skins are not supported; meshes are rendered in bind pose".to_string());
+        }
+
+        let mut world = ShapeList::new();
+        let scene = self
+            .document
+            .default_scene()
+            .or_else(|| self.document.scenes().next());
+        match scene {
+            Some(scene) => {
+                for node in scene.nodes() {
+                    if let Some(shape) = self.node(&node, &Mat4::identity())? {
+                        world.push(shape);
+                    }
+                }
+            }
+            None => self.warn("file has no scene".to_string()),
+        }
+
+        Ok(GltfScene {
+            world,
+            cameras: self.cameras,
+            warnings: self.warnings,
+        })
+    }
+
+    // ノードをTransformに変換する. 子孫に形状が無ければNone
+    fn node(&mut self, node: &gltf::Node, parent: &Mat4) -> Result<Option<Box<dyn Shape>>> {
+        let local = Mat4::from_fn(|r, c| node.transform().matrix()[c][r] as f64);
+        let world_matrix = parent * local;
+
+        if let Some(camera) = node.camera() {
+            self.camera(&camera, &world_matrix);
+        }
+        if node.weights().is_some() {
+            self.warn("morph target weights are not supported".to_string());
+        }
+
+        let mut shapes = ShapeList::new();
+        if let Some(mesh) = node.mesh() {
+            for primitive in self.mesh(&mesh)? {
+                shapes.push(Box::new(primitive));
+            }
+        }
+        for child in node.children() {
+            if let Some(shape) = self.node(&child, &world_matrix)? {
+                shapes.push(shape);
+            }
+        }
+        if shapes.objects.is_empty() {
+            return Ok(None);
+        }
+
+        // 拡大率0などで潰れたノードは描画しない
+        let affine = match Affine::from_matrix(local) {
+            Some(affine) => affine,
+            None => {
+                self.warn(format!(
+                    "node {} has a non-invertible transform and was skipped",
+                    node.index()
+                ));
+                return Ok(None);
+            }
+        };
+        let transform = Transform::from_affine(Box::new(shapes), affine);
+        Ok(Some(Box::new(transform)))
+    }
+
+    fn camera(&mut self, camera: &gltf::Camera, world_matrix: &Mat4) {
+        let perspective = match camera.projection() {
+            gltf::camera::Projection::Perspective(p) => p,
+            gltf::camera::Projection::Orthographic(_) => {
+                self.warn(format!(
+                    "orthographic camera {:?} is not supported and was skipped",
+                    camera.name().unwrap_or("")
+                ));
+                return;
+            }
+        };
+        // glTFのカメラは-zを向き、+yが上
+        let origin = world_matrix.transform_point(&na::Point3::origin()).coords;
+        let forward = world_matrix
+            .transform_vector(&float3::new(0.0, 0.0, -1.0))
+            .normalize();
+        let vup = world_matrix
+            .transform_vector(&float3::new(0.0, 1.0, 0.0))
+            .normalize();
+        self.cameras.push(CameraParams {
+            name: camera.name().map(str::to_string),
+            origin,
+            lookat: origin + forward,
+            vup,
+            vfov: (perspective.yfov() as f64).to_degrees(),
+            aspect: perspective.aspect_ratio().map(|a| a as f64),
+            mirror: false,
+            lens_radius: 0.0,
+            focus_distance: None,
+        });
+    }
+
+    fn mesh(&mut self, mesh: &gltf::Mesh) -> Result<Vec<Arc<TriangleMesh>>> {
+        if let Some(primitives) = &self.meshes[mesh.index()] {
+            return Ok(primitives.clone());
+        }
+        let mut primitives = Vec::new();
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                self.warn(format!(
+                    "primitive mode {:?} in mesh {:?} is not supported and was skipped",
+                    primitive.mode(),
+                    mesh.name().unwrap_or("")
+                ));
+                continue;
+            }
+            if primitive.morph_targets().len() > 0 {
+                self.warn("morph targets are not supported and were ignored".to_string());
+            }
+
+            let buffers = self.buffers;
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions: Vec<Float3> = match reader.read_positions() {
+                Some(iter) => iter.map(to_float3).collect(),
+                None => {
+                    self.warn(format!(
+                        "primitive in mesh {:?} has no positions and was skipped",
+                        mesh.name().unwrap_or("")
+                    ));
+                    continue;
+                }
+            };
+            let normals: Vec<Float3> = reader
+                .read_normals()
+                .map_or_else(Vec::new, |iter| iter.map(to_float3).collect());
+            let uvs: Vec<(f64, f64)> = reader.read_tex_coords(0).map_or_else(Vec::new, |iter| {
+                iter.into_f32().map(|[u, v]| (u as f64, v as f64)).collect()
+            });
+            let colors: Vec<Float3> = reader.read_colors(0).map_or_else(Vec::new, |iter| {
+                iter.into_rgb_f32().map(to_float3).collect()
+            });
+            let flat: Vec<usize> = match reader.read_indices() {
+                Some(iter) => iter.into_u32().map(|i| i as usize).collect(),
+                None => (0..positions.len()).collect(),
+            };
+            if !flat.len().is_multiple_of(3) {
+                return Err(Error::InvalidScene(format!(
+                    "mesh {:?} has {} indices, which is not a multiple of 3",
+                    mesh.name().unwrap_or(""),
+                    flat.len()
+                )));
+            }
+            let indices: Vec<[usize; 3]> =
+                flat.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect();
+
+            let material = self.material(&primitive.material(), !colors.is_empty())?;
+            let data = MeshData::new(positions, normals, uvs, indices)?.with_colors(colors)?;
+            primitives.push(Arc::new(TriangleMesh::from_data(Arc::new(data), material)));
+        }
+        self.meshes[mesh.index()] = Some(primitives.clone());
+        Ok(primitives)
+    }
+
+    // metallic-roughnessを既存のマテリアルに近似する
+    // emissive -> DiffuseLight, transmission -> Dielectric, metallic >= 0.5 -> Metal, それ以外 -> Lambertian
+    // vertex_colorsならCOLOR_0をベースカラーに掛ける
+    fn material(
+        &mut self,
+        material: &gltf::Material,
+        vertex_colors: bool,
+    ) -> Result<Arc<dyn Material>> {
+        let index = match material.index() {
+            Some(i) => i,
+            None => {
+                return Ok(Arc::new(Lambertian::new(with_vertex_colors(
+                    Box::new(ColorTexture::new(float3::fill(0.8))),
+                    vertex_colors,
+                ))))
+            }
+        };
+        if let Some(m) = &self.materials[index][vertex_colors as usize] {
+            return Ok(Arc::clone(m));
+        }
+        let name = material.name().unwrap_or("").to_string();
+        let pbr = material.pbr_metallic_roughness();
+
+        if pbr.metallic_roughness_texture().is_some() {
+            self.warn(format!(
+                "material {:?}: metallicRoughnessTexture is not supported; factors are used",
+                name
+            ));
+        }
+        if material.occlusion_texture().is_some() {
+            self.warn(format!(
+                "material {:?}: occlusionTexture is not supported",
+                name
+            ));
+        }
+        if material.alpha_mode() == gltf::material::AlphaMode::Blend {
+            self.warn(format!(
+                "material {:?}: alpha blending is not supported; rendered as opaque",
+                name
+            ));
+        }
+
+        let emissive = to_float3(material.emissive_factor())
+            * material.emissive_strength().unwrap_or(1.0) as f64;
+        let result: Arc<dyn Material> = if emissive.max() > 0.0 {
+            let intensity = emissive.max();
+            let factor = emissive / intensity;
+            let emit = match material.emissive_texture() {
+                Some(info) => with_factor(self.texture(&info, &name)?, factor),
+                None => Box::new(ColorTexture::new(factor)),
+            };
+            Arc::new(DiffuseLight::new(emit, intensity))
+        } else if material
+            .transmission()
+            .is_some_and(|t| t.transmission_factor() > 0.0)
+        {
+            Arc::new(Dielectric::new(material.ior().unwrap_or(1.5) as f64))
+        } else {
+            let [r, g, b, _] = pbr.base_color_factor();
+            let factor = float3::new(r as f64, g as f64, b as f64);
+            let albedo = match pbr.base_color_texture() {
+                Some(info) => with_factor(self.texture(&info, &name)?, factor),
+                None => Box::new(ColorTexture::new(factor)),
+            };
+            let albedo = with_vertex_colors(albedo, vertex_colors);
+            if pbr.metallic_factor() >= 0.5 {
+                Arc::new(Metal::new(albedo, pbr.roughness_factor() as f64))
+            } else {
+                Arc::new(Lambertian::new(albedo))
+            }
+        };
+
+        let result: Arc<dyn Material> = match material.normal_texture() {
+            Some(normal) => {
+                if normal.tex_coord() != 0 {
+                    self.warn(format!("material {:?}: only TEXCOORD_0 is supported", name));
+                }
+                let map = self.image_texture(&normal.texture(), ColorSpace::Linear, &name)?;
+                Arc::new(NormalMap::new(result, Box::new(map), normal.scale() as f64))
+            }
+            None => result,
+        };
+
+        self.materials[index][vertex_colors as usize] = Some(Arc::clone(&result));
+        Ok(result)
+    }
+
+    fn texture(&mut self, info: &gltf::texture::Info, material: &str) -> Result<Box<dyn Texture>> {
+        if info.tex_coord() != 0 {
+            self.warn(format!(
+                "material {:?}: only TEXCOORD_0 is supported",
+                material
+            ));
+        }
+        let texture = info.texture();
+        if let Some(t) = &self.textures[texture.index()] {
+            return Ok(Box::new(Arc::clone(t)));
+        }
+        let image = Arc::new(self.image_texture(&texture, ColorSpace::Srgb, material)?);
+        self.textures[texture.index()] = Some(Arc::clone(&image));
+        Ok(Box::new(image))
+    }
+
+    // サンプラーのフィルタと繰り返し方を反映した画像テクスチャ
+    fn image_texture(
+        &mut self,
+        texture: &gltf::Texture,
+        color_space: ColorSpace,
+        material: &str,
+    ) -> Result<ImageTexture> {
+        use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+        let data = &self.images[texture.source().index()];
+        let image = ImageTexture::from_pixels(
+            image_pixels(data, color_space),
+            data.width as usize,
+            data.height as usize,
+            (1.0, 1.0),
+        )?;
+        let sampler = texture.sampler();
+        // ミップマップを使わない縮小フィルタが指定されていなければトライリニアにする
+        let filter = match (sampler.mag_filter(), sampler.min_filter()) {
+            (Some(MagFilter::Nearest), _) => TextureFilter::Nearest,
+            (_, Some(MinFilter::Nearest | MinFilter::Linear)) => TextureFilter::Bilinear,
+            _ => TextureFilter::Trilinear,
+        };
+        let wrap = |mode| match mode {
+            WrappingMode::ClampToEdge => WrapMode::Clamp,
+            WrappingMode::MirroredRepeat => WrapMode::Mirror,
+            WrappingMode::Repeat => WrapMode::Repeat,
+        };
+        if sampler.wrap_s() != sampler.wrap_t() {
+            self.warn(format!(
+                "material {:?}: different wrap modes for s and t are not supported",
+                material
+            ));
+        }
+        Ok(image.with_filter(filter).with_wrap(wrap(sampler.wrap_s())))
+    }
+}
+
+// テクスチャに係数を掛ける. 係数が1なら包まない
+fn with_factor(texture: Box<dyn Texture>, factor: Float3) -> Box<dyn Texture> {
+    if factor == float3::one() {
+        texture
+    } else {
+        Box::new(MultiplyTexture::new(
+            texture,
+            Box::new(ColorTexture::new(factor)),
+        ))
+    }
+}
+
+fn with_vertex_colors(albedo: Box<dyn Texture>, vertex_colors: bool) -> Box<dyn Texture> {
+    if vertex_colors {
+        Box::new(MultiplyTexture::new(
+            albedo,
+            Box::new(VertexColorTexture::new(float3::one())),
+        ))
+    } else {
+        albedo
+    }
+}
+
+fn to_float3(v: [f32; 3]) -> Float3 {
+    float3::new(v[0] as f64, v[1] as f64, v[2] as f64)
+}
+
+// 0..1に正規化してから色空間を変換する. 浮動小数点数の画像はリニアな値としてそのまま使う
+fn image_pixels(data: &gltf::image::Data, color_space: ColorSpace) -> Vec<Float3> {
+    use gltf::image::Format;
+    let (channels, bytes_per_channel) = match data.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+    let channel = |px: &[u8], c: usize| -> f64 {
+        let c = c.min(channels - 1);
+        let b = &px[c * bytes_per_channel..(c + 1) * bytes_per_channel];
+        match bytes_per_channel {
+            1 => b[0] as f64 / 255.0,
+            2 => u16::from_le_bytes([b[0], b[1]]) as f64 / 65535.0,
+            _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+        }
+    };
+    data.pixels
+        .chunks_exact(channels * bytes_per_channel)
+        .map(|px| {
+            // R8G8は輝度とアルファとして扱う
+            let c = if channels == 2 {
+                float3::fill(channel(px, 0))
+            } else {
+                float3::new(channel(px, 0), channel(px, 1), channel(px, 2))
+            };
+            if bytes_per_channel == 4 {
+                c.map(|x| if x.is_finite() { x } else { 0.0 })
+            } else {
+                color_space.to_linear(c)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boxes() -> GltfScene {
+        GltfScene::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/boxes.gltf")).unwrap()
+    }
+
+    fn hit(scene: &GltfScene, origin: Float3, direction: Float3) -> HitInfo {
+        scene
+            .world
+            .hit(&Ray::new(origin, direction), 0.001, f64::MAX)
+            .unwrap()
+    }
+
+    fn assert_near(a: Float3, b: Float3) {
+        assert!((a - b).norm() < 1e-6, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn imports_node_transforms() {
+        let scene = boxes();
+        // metal box: 親の(0, 0.5, 0)と自身の(0.8, 0, 0)に0.8倍の単位立方体. 上面はy = 0.9
+        let h = hit(&scene, float3::new(0.8, 2.0, 0.0), -Float3::y());
+        assert!((h.t - 1.1).abs() < 1e-6, "{}", h.t);
+        // photo box: y軸まわりに0.5ラジアン回転した単位立方体
+        let h = hit(&scene, float3::new(-0.7, 0.5, 5.0), -Float3::z());
+        assert!((h.t - (5.0 - 0.5 / 0.5f64.cos())).abs() < 1e-6, "{}", h.t);
+        assert_near(h.n, float3::new(0.5f64.sin(), 0.0, 0.5f64.cos()));
+    }
+
+    #[test]
+    fn imports_materials() {
+        let scene = boxes();
+        let metal = hit(&scene, float3::new(0.8, 2.0, 0.0), -Float3::y())
+            .m
+            .preview();
+        assert_near(metal.color, float3::new(0.9, 0.8, 0.6));
+        assert_eq!(metal.metallic, 1.0);
+        let ground = hit(&scene, float3::new(2.5, 5.0, 2.5), -Float3::y());
+        assert!((ground.t - 5.0).abs() < 1e-6);
+        assert_near(ground.m.preview().color, float3::fill(0.5));
+        // emissiveFactor x KHR_materials_emissive_strength
+        let light = hit(&scene, float3::new(0.0, 2.0, 0.0), Float3::y())
+            .m
+            .preview();
+        assert_near(light.emission, float3::fill(4.0));
+    }
+
+    #[test]
+    fn imports_camera() {
+        let scene = boxes();
+        assert_eq!(scene.cameras.len(), 1);
+        let camera = &scene.cameras[0];
+        assert_near(camera.origin, float3::new(0.0, 1.5, 5.0));
+        // x軸まわりに下へ約0.25ラジアン傾いている
+        let angle = 2.0 * 0.124_674_733_385_227_69f64.asin();
+        let forward = (camera.lookat - camera.origin).normalize();
+        assert_near(forward, float3::new(0.0, -angle.sin(), -angle.cos()));
+        assert!((camera.vfov - 0.6f64.to_degrees()).abs() < 1e-4);
+        assert!(scene.warnings.is_empty(), "{:?}", scene.warnings);
+    }
+}
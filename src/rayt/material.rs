@@ -11,11 +11,54 @@ impl ScatterInfo {
     }
 }
 
+// シーンの書き出しなどで使う、マテリアルのおおよその見た目
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialPreview {
+    pub color: Float3,
+    pub emission: Float3,
+    pub metallic: f64,
+    pub roughness: f64,
+    pub transmission: f64,
+    pub ior: f64,
+}
+
+impl Default for MaterialPreview {
+    fn default() -> Self {
+        Self {
+            color: float3::fill(0.5),
+            emission: Float3::zeros(),
+            metallic: 0.0,
+            roughness: 1.0,
+            transmission: 0.0,
+            ior: 1.5,
+        }
+    }
+}
+
+// テクスチャは中央の値で代表させる
+fn preview_color(texture: &dyn Texture) -> Float3 {
+    texture.value(0.5, 0.5, Float3::zeros())
+}
+
 pub trait Material: Sync + Send {
     fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo>;
     fn emited(&self, _ray: &Ray, _hit: &HitInfo) -> Float3 {
         Float3::zeros()
     }
+    // scatterが方向directionを選ぶ確率密度 (立体角あたり)
+    // 0を返すマテリアルは光源の方向を直接サンプリングしない. 0以外を返すなら
+    // scatterのalbedoは方向によらない f·cos / pdf でなければならない
+    fn scattering_pdf(&self, _ray: &Ray, _hit: &HitInfo, _direction: &Float3) -> f64 {
+        0.0
+    }
+    // scatterがNoneのときに光源の直接サンプリングに使うalbedo
+    // 光漏れになる向きを選んで散乱をやめたマテリアルでも、反射率そのものは求まる
+    fn direct_albedo(&self, _ray: &Ray, _hit: &HitInfo) -> Option<Float3> {
+        None
+    }
+    fn preview(&self) -> MaterialPreview {
+        MaterialPreview::default()
+    }
 }
 
 pub struct Lambertian {
@@ -29,10 +72,23 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
-        let r = hit.n + math::random_in_unit_sphere();
-        let r = Ray::new(hit.p, r);
-        Some(ScatterInfo::new(r, self.albedo.value(hit.u, hit.v, hit.p)))
+    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
+        // 単位球面上の点を足すとcosに比例した向きになる
+        let r = hit.n + math::random_unit_vector();
+        let r = Ray::with_time(hit.p, r, ray.time);
+        Some(ScatterInfo::new(r, self.albedo.value_at(hit)))
+    }
+
+    fn scattering_pdf(&self, _ray: &Ray, hit: &HitInfo, direction: &Float3) -> f64 {
+        let cosine = hit.n.dot(&direction.normalize());
+        cosine.max(0.0) * FRAC_1_PI
+    }
+
+    fn preview(&self) -> MaterialPreview {
+        MaterialPreview {
+            color: preview_color(self.albedo.as_ref()),
+            ..Default::default()
+        }
     }
 }
 
@@ -50,16 +106,25 @@ impl Metal {
 impl Material for Metal {
     fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
         let mut reflected = math::reflect(&ray.direction.normalize(), &hit.n);
-        reflected = reflected + math::random_in_unit_sphere() * self.fuzz;
+        reflected += math::random_in_unit_sphere() * self.fuzz;
         if reflected.dot(&hit.n) > 0.0 {
             Some(ScatterInfo::new(
-                Ray::new(hit.p, reflected),
-                self.albedo.value(hit.u, hit.v, hit.p),
+                Ray::with_time(hit.p, reflected, ray.time),
+                self.albedo.value_at(hit),
             ))
         } else {
             None
         }
     }
+
+    fn preview(&self) -> MaterialPreview {
+        MaterialPreview {
+            color: preview_color(self.albedo.as_ref()),
+            metallic: 1.0,
+            roughness: self.fuzz,
+            ..Default::default()
+        }
+    }
 }
 
 pub struct Dielectric {
@@ -87,14 +152,27 @@ impl Material for Dielectric {
         if let Some(refracted) = math::refract(&ray.direction, &outward_normal, eta) {
             let rand = rand::random::<f64>();
             if rand > math::schilick(self.ri, cosine) {
-                return Some(ScatterInfo::new(Ray::new(hit.p, refracted), float3::one()));
+                return Some(ScatterInfo::new(
+                    Ray::with_time(hit.p, refracted, ray.time),
+                    float3::one(),
+                ));
             }
         }
         Some(ScatterInfo::new(
-            Ray::new(hit.p, reflected),
+            Ray::with_time(hit.p, reflected, ray.time),
             na::vector![1.0, 1.0, 1.0],
         ))
     }
+
+    fn preview(&self) -> MaterialPreview {
+        MaterialPreview {
+            color: float3::one(),
+            roughness: 0.0,
+            transmission: 1.0,
+            ior: self.ri,
+            ..Default::default()
+        }
+    }
 }
 
 pub struct DiffuseLight {
@@ -114,6 +192,232 @@ impl Material for DiffuseLight {
     }
 
     fn emited(&self, _ray: &Ray, hit: &HitInfo) -> Float3 {
-        self.emit.value(hit.u, hit.v, hit.p) * self.intensity
+        self.emit.value_at(hit) * self.intensity
+    }
+
+    fn preview(&self) -> MaterialPreview {
+        MaterialPreview {
+            color: Float3::zeros(),
+            emission: preview_color(self.emit.as_ref()) * self.intensity,
+            ..Default::default()
+        }
+    }
+}
+
+// シェーディング法線が視点から見て裏を向かないように残す最小のcos
+const SHADING_NORMAL_MIN_COS: f64 = 0.01;
+
+// 法線をnsに置き換えた交差情報. nsは視点から見て裏を向かないように少し起こす
+fn with_shading_normal(ray: &Ray, hit: &HitInfo, ns: Float3) -> HitInfo {
+    let wo = -ray.direction.normalize();
+    // 視点側を正にして考える
+    let side = if wo.dot(&hit.n) >= 0.0 { 1.0 } else { -1.0 };
+    let mut ns = ns * side;
+    let cos = ns.dot(&wo);
+    if cos < SHADING_NORMAL_MIN_COS {
+        ns = (ns + wo * (SHADING_NORMAL_MIN_COS - cos)).normalize();
+    }
+    HitInfo {
+        n: ns * side,
+        ..hit.clone()
+    }
+    .with_frame(hit.tangent, hit.bitangent)
+}
+
+// 幾何法線とシェーディング法線で面のどちら側に出るかが食い違う向きは、光漏れになるので使わない
+fn same_side(ray: &Ray, hit: &HitInfo, shading: &HitInfo, direction: &Float3) -> bool {
+    let wo = -ray.direction;
+    let geometric = direction.dot(&hit.n) * wo.dot(&hit.n);
+    let shaded = direction.dot(&shading.n) * wo.dot(&shading.n);
+    geometric * shaded >= 0.0
+}
+
+// 法線をnsに置き換えてmaterialで散乱させる
+fn scatter_with_normal(
+    material: &dyn Material,
+    ray: &Ray,
+    hit: &HitInfo,
+    ns: Float3,
+) -> Option<ScatterInfo> {
+    let shading = with_shading_normal(ray, hit, ns);
+    let info = material.scatter(ray, &shading)?;
+    same_side(ray, hit, &shading, &info.ray.direction).then_some(info)
+}
+
+// scatter_with_normalが向きを捨てたときのalbedo
+fn direct_albedo_with_normal(
+    material: &dyn Material,
+    ray: &Ray,
+    hit: &HitInfo,
+    ns: Float3,
+) -> Option<Float3> {
+    let shading = with_shading_normal(ray, hit, ns);
+    match material.scatter(ray, &shading) {
+        Some(info) => Some(info.albedo),
+        None => material.direct_albedo(ray, &shading),
+    }
+}
+
+// scatter_with_normalと同じ法線でmaterialの確率密度を求める
+fn scattering_pdf_with_normal(
+    material: &dyn Material,
+    ray: &Ray,
+    hit: &HitInfo,
+    ns: Float3,
+    direction: &Float3,
+) -> f64 {
+    let shading = with_shading_normal(ray, hit, ns);
+    if same_side(ray, hit, &shading, direction) {
+        material.scattering_pdf(ray, &shading, direction)
+    } else {
+        0.0
+    }
+}
+
+// 接空間のノーマルマップで法線を変える. テクスチャはImageTexture::new_linearなどで読む
+// OpenGL形式 (赤が+u, 緑が画像の上 = -v, 青が法線) で、strengthは傾きの強さ
+pub struct NormalMap {
+    material: Arc<dyn Material>,
+    map: Box<dyn Texture>,
+    strength: f64,
+}
+
+impl NormalMap {
+    pub fn new(material: Arc<dyn Material>, map: Box<dyn Texture>, strength: f64) -> Self {
+        Self {
+            material,
+            map,
+            strength,
+        }
+    }
+
+    fn shading_normal(&self, hit: &HitInfo) -> Option<Float3> {
+        let c = self.map.value_at(hit) * 2.0 - float3::one();
+        let ns = hit.tangent * (c.x * self.strength) - hit.bitangent * (c.y * self.strength)
+            + hit.n * c.z;
+        ns.try_normalize(EPS)
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
+        match self.shading_normal(hit) {
+            Some(ns) => scatter_with_normal(self.material.as_ref(), ray, hit, ns),
+            None => self.material.scatter(ray, hit),
+        }
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &HitInfo, direction: &Float3) -> f64 {
+        match self.shading_normal(hit) {
+            Some(ns) => scattering_pdf_with_normal(self.material.as_ref(), ray, hit, ns, direction),
+            None => self.material.scattering_pdf(ray, hit, direction),
+        }
+    }
+
+    fn direct_albedo(&self, ray: &Ray, hit: &HitInfo) -> Option<Float3> {
+        match self.shading_normal(hit) {
+            Some(ns) => direct_albedo_with_normal(self.material.as_ref(), ray, hit, ns),
+            None => self.material.direct_albedo(ray, hit),
+        }
+    }
+
+    fn emited(&self, ray: &Ray, hit: &HitInfo) -> Float3 {
+        self.material.emited(ray, hit)
+    }
+
+    fn preview(&self) -> MaterialPreview {
+        self.material.preview()
+    }
+}
+
+// 画面上の微分が無いときに高さの差分を取るuvの幅
+const BUMP_DELTA: f64 = 1.0 / 1024.0;
+
+// 高さのテクスチャ (rgbの平均) の勾配で法線を傾ける. scaleは高さの倍率 (ワールド座標の長さ)
+// pbrtと同じように、表面をuvの向き (dpdu, dpdv) にずらした点で高さを取る
+pub struct BumpMap {
+    material: Arc<dyn Material>,
+    height: Box<dyn Texture>,
+    scale: f64,
+}
+
+impl BumpMap {
+    pub fn new(material: Arc<dyn Material>, height: Box<dyn Texture>, scale: f64) -> Self {
+        Self {
+            material,
+            height,
+            scale,
+        }
+    }
+
+    fn height_at(&self, hit: &HitInfo) -> f64 {
+        self.height.value_at(hit).mean() * self.scale
+    }
+
+    // uvと位置を一緒にずらした交差情報. 画面上の微分はそのまま使う
+    fn shifted(hit: &HitInfo, du: f64, dv: f64, dpdu: Float3, dpdv: Float3) -> HitInfo {
+        HitInfo {
+            p: hit.p + dpdu * du + dpdv * dv,
+            u: hit.u + du,
+            v: hit.v + dv,
+            ..hit.clone()
+        }
+    }
+
+    fn shading_normal(&self, hit: &HitInfo) -> Float3 {
+        // uvを持たない形状では接空間の単位ベクトルを使う
+        let (dpdu, dpdv) = if hit.dpdu.cross(&hit.dpdv).norm_squared() > 0.0 {
+            (hit.dpdu, hit.dpdv)
+        } else {
+            (hit.tangent, hit.bitangent)
+        };
+        // 1画素ぶんのuvの変化で差分を取る
+        let width = |a: f64, b: f64| {
+            let w = 0.5 * (a.abs() + b.abs());
+            if w > 0.0 {
+                w
+            } else {
+                BUMP_DELTA
+            }
+        };
+        let (du, dv) = match &hit.differentials {
+            Some(d) => (width(d.dudx, d.dudy), width(d.dvdx, d.dvdy)),
+            None => (BUMP_DELTA, BUMP_DELTA),
+        };
+        let h = self.height_at(hit);
+        let dhdu = (self.height_at(&Self::shifted(hit, du, 0.0, dpdu, dpdv)) - h) / du;
+        let dhdv = (self.height_at(&Self::shifted(hit, 0.0, dv, dpdu, dpdv)) - h) / dv;
+        // 高さの分だけ法線の向きに動かした面の接ベクトルから法線を作る
+        let ns = (dpdu + hit.n * dhdu).cross(&(dpdv + hit.n * dhdv));
+        match ns.try_normalize(EPS) {
+            Some(ns) if ns.dot(&hit.n) < 0.0 => -ns,
+            Some(ns) => ns,
+            None => hit.n,
+        }
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
+        let ns = self.shading_normal(hit);
+        scatter_with_normal(self.material.as_ref(), ray, hit, ns)
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &HitInfo, direction: &Float3) -> f64 {
+        let ns = self.shading_normal(hit);
+        scattering_pdf_with_normal(self.material.as_ref(), ray, hit, ns, direction)
+    }
+
+    fn direct_albedo(&self, ray: &Ray, hit: &HitInfo) -> Option<Float3> {
+        let ns = self.shading_normal(hit);
+        direct_albedo_with_normal(self.material.as_ref(), ray, hit, ns)
+    }
+
+    fn emited(&self, ray: &Ray, hit: &HitInfo) -> Float3 {
+        self.material.emited(ray, hit)
+    }
+
+    fn preview(&self) -> MaterialPreview {
+        self.material.preview()
     }
 }
@@ -2,6 +2,7 @@ use na::vector;
 use nalgebra as na;
 pub type Quat = na::UnitQuaternion<f64>;
 pub type Float3 = na::Vector3<f64>;
+pub type Mat4 = na::Matrix4<f64>;
 
 // 引数はnormalizeされている
 pub fn reflect(incident: &Float3, normal: &Float3) -> Float3 {
@@ -19,6 +20,22 @@ pub fn refract(incident: &Float3, normal: &Float3, eta: f64) -> Option<Float3> {
     }
 }
 
+// 単位ベクトルnに直交する単位ベクトルの組 (Duff et al. 2017)
+pub fn orthonormal_basis(n: &Float3) -> (Float3, Float3) {
+    let sign = 1.0f64.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    (
+        vector![1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x],
+        vector![b, sign + n.y * n.y * a, -n.y],
+    )
+}
+
+// 最短経路で球面線形補間する. ほぼ同じ回転のときは正規化した線形補間になる
+pub fn slerp(a: &Quat, b: &Quat, t: f64) -> Quat {
+    a.try_slerp(b, t, 1e-9).unwrap_or_else(|| a.nlerp(b, t))
+}
+
 pub fn random_limit(min: f64, max: f64) -> f64 {
     min + (max - min) * rand::random::<f64>()
 }
@@ -40,6 +57,47 @@ pub fn random_in_unit_sphere() -> Float3 {
     }
 }
 
+// 単位球面上の一様な点
+pub fn random_unit_vector() -> Float3 {
+    loop {
+        let point = random_in_unit_sphere();
+        let norm = point.norm();
+        if norm > super::EPS {
+            return point / norm;
+        }
+    }
+}
+
+// z = 0の単位円板の中の一様な点
+pub fn random_in_unit_disk() -> Float3 {
+    loop {
+        let point = vector![random_limit(-1.0, 1.0), random_limit(-1.0, 1.0), 0.0];
+        if point.norm_squared() < 1.0 {
+            return point;
+        }
+    }
+}
+
+// 単位円に内接する正多角形 (z = 0) の中の一様な点
+// rotationは最初の頂点の角度 (ラジアン)
+pub fn random_in_regular_polygon(sides: u32, rotation: f64) -> Float3 {
+    // 中心と辺が作る三角形はすべて同じ面積なので、一つ選んで三角形の中で一様に取る
+    let step = std::f64::consts::PI * 2.0 / sides as f64;
+    let i = (rand::random::<f64>() * sides as f64) as u32 % sides;
+    let a0 = rotation + step * i as f64;
+    let a1 = a0 + step;
+    let (mut s, mut t) = (rand::random::<f64>(), rand::random::<f64>());
+    if s + t > 1.0 {
+        s = 1.0 - s;
+        t = 1.0 - t;
+    }
+    vector![
+        a0.cos() * s + a1.cos() * t,
+        a0.sin() * s + a1.sin() * t,
+        0.0
+    ]
+}
+
 pub fn schilick(ri: f64, cosine: f64) -> f64 {
     let r0 = ((1.0 - ri) / (1.0 + ri)).powi(2);
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
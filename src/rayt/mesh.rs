@@ -0,0 +1,519 @@
+use crate::rayt::*;
+
+// 三角形メッシュの頂点バッファとインデックス
+// normals, uvs, colorsは空か、positionsと同じ長さ
+// Arcで包んで複数のTriangleMesh, Triangleから共有できる
+pub struct MeshData {
+    positions: Vec<Float3>,
+    normals: Vec<Float3>,
+    uvs: Vec<(f64, f64)>,
+    colors: Vec<Float3>,
+    indices: Vec<[usize; 3]>,
+}
+
+impl MeshData {
+    pub fn new(
+        positions: Vec<Float3>,
+        normals: Vec<Float3>,
+        uvs: Vec<(f64, f64)>,
+        indices: Vec<[usize; 3]>,
+    ) -> Result<Self> {
+        let n = positions.len();
+        if !normals.is_empty() && normals.len() != n {
+            return Err(Error::InvalidScene(format!(
+                "mesh has {} positions but {} normals",
+                n,
+                normals.len()
+            )));
+        }
+        if !uvs.is_empty() && uvs.len() != n {
+            return Err(Error::InvalidScene(format!(
+                "mesh has {} positions but {} uvs",
+                n,
+                uvs.len()
+            )));
+        }
+        if let Some(i) = indices.iter().flatten().find(|&&i| i >= n) {
+            return Err(Error::InvalidScene(format!(
+                "mesh index {} is out of range ({} positions)",
+                i, n
+            )));
+        }
+        Ok(Self {
+            positions,
+            normals,
+            uvs,
+            colors: Vec::new(),
+            indices,
+        })
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Float3>) -> Result<Self> {
+        if !colors.is_empty() && colors.len() != self.positions.len() {
+            return Err(Error::InvalidScene(format!(
+                "mesh has {} positions but {} colors",
+                self.positions.len(),
+                colors.len()
+            )));
+        }
+        self.colors = colors;
+        Ok(self)
+    }
+
+    pub fn positions(&self) -> &[Float3] {
+        &self.positions
+    }
+
+    pub fn normals(&self) -> &[Float3] {
+        &self.normals
+    }
+
+    pub fn uvs(&self) -> &[(f64, f64)] {
+        &self.uvs
+    }
+
+    pub fn colors(&self) -> &[Float3] {
+        &self.colors
+    }
+
+    pub fn indices(&self) -> &[[usize; 3]] {
+        &self.indices
+    }
+
+    // 頂点に行列を適用したコピー. 法線には逆転置行列を使う
+    // 行列が面の向きを反転する場合は三角形の頂点順も反転する
+    pub fn transformed(&self, m: &Mat4) -> Self {
+        let linear = m.fixed_slice::<3, 3>(0, 0).into_owned();
+        let normal_matrix = linear.try_inverse().map_or(linear, |inv| inv.transpose());
+        let flip = linear.determinant() < 0.0;
+        Self {
+            positions: self
+                .positions
+                .iter()
+                .map(|p| m.transform_point(&na::Point3::from(*p)).coords)
+                .collect(),
+            normals: self
+                .normals
+                .iter()
+                .map(|n| (normal_matrix * n).normalize())
+                .collect(),
+            uvs: self.uvs.clone(),
+            colors: self.colors.clone(),
+            indices: self
+                .indices
+                .iter()
+                .map(|&[a, b, c]| if flip { [a, c, b] } else { [a, b, c] })
+                .collect(),
+        }
+    }
+
+    // 指定した三角形と、それが使う頂点だけを書き出す
+    fn export_triangles(
+        &self,
+        triangles: &[usize],
+        name: &str,
+        material: &Arc<dyn Material>,
+        exporter: &mut SceneExporter,
+    ) {
+        let mut remap = vec![usize::MAX; self.positions.len()];
+        let mut used = Vec::new();
+        let faces = triangles
+            .iter()
+            .map(|&t| {
+                self.indices[t]
+                    .iter()
+                    .map(|&i| {
+                        if remap[i] == usize::MAX {
+                            remap[i] = used.len();
+                            used.push(i);
+                        }
+                        remap[i]
+                    })
+                    .collect()
+            })
+            .collect();
+        let pick = |values: &[Float3]| -> Vec<Float3> {
+            if values.is_empty() {
+                Vec::new()
+            } else {
+                used.iter().map(|&i| values[i]).collect()
+            }
+        };
+        let uvs = if self.uvs.is_empty() {
+            Vec::new()
+        } else {
+            used.iter().map(|&i| self.uvs[i]).collect()
+        };
+        exporter.add_polygons(
+            name,
+            pick(&self.positions),
+            pick(&self.normals),
+            uvs,
+            faces,
+            material,
+        );
+    }
+
+    pub fn triangle_bounds(&self, index: usize) -> Aabb {
+        let [i0, i1, i2] = self.indices[index];
+        Aabb::from_points([
+            &self.positions[i0],
+            &self.positions[i1],
+            &self.positions[i2],
+        ])
+    }
+
+    // Woop et al. "Watertight Ray/Triangle Intersection" (2013)
+    // 辺の上を通るレイが隣り合う三角形の両方から漏れることがない
+    pub fn hit_triangle(
+        &self,
+        index: usize,
+        ray: &Ray,
+        t0: f64,
+        t1: f64,
+        material: &Arc<dyn Material>,
+    ) -> Option<HitInfo> {
+        let [i0, i1, i2] = self.indices[index];
+        let d = ray.direction;
+
+        // レイの向きが+zになるように軸を入れ替える
+        let kz = d.iamax();
+        let mut kx = (kz + 1) % 3;
+        let mut ky = (kx + 1) % 3;
+        if d[kz] < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+        let sx = d[kx] / d[kz];
+        let sy = d[ky] / d[kz];
+        let sz = d[kz].recip();
+
+        let a = self.positions[i0] - ray.origin;
+        let b = self.positions[i1] - ray.origin;
+        let c = self.positions[i2] - ray.origin;
+        let ax = a[kx] - sx * a[kz];
+        let ay = a[ky] - sy * a[kz];
+        let bx = b[kx] - sx * b[kz];
+        let by = b[ky] - sy * b[kz];
+        let cx = c[kx] - sx * c[kz];
+        let cy = c[ky] - sy * c[kz];
+
+        let u = cx * by - cy * bx;
+        let v = ax * cy - ay * cx;
+        let w = bx * ay - by * ax;
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+            return None;
+        }
+        let det = u + v + w;
+        if det == 0.0 {
+            return None;
+        }
+
+        let t_scaled = (u * a[kz] + v * b[kz] + w * c[kz]) * sz;
+        let t = t_scaled / det;
+        if t <= t0 || t >= t1 {
+            return None;
+        }
+        let (b0, b1, b2) = (u / det, v / det, w / det);
+
+        let p0 = self.positions[i0];
+        let geometric_normal = (self.positions[i1] - p0)
+            .cross(&(self.positions[i2] - p0))
+            .normalize();
+        let n = if self.normals.is_empty() {
+            geometric_normal
+        } else {
+            let n = self.normals[i0] * b0 + self.normals[i1] * b1 + self.normals[i2] * b2;
+            // 法線の無い頂点を含む場合は面法線を使う
+            if n.norm_squared() < EPS {
+                geometric_normal
+            } else {
+                n.normalize()
+            }
+        };
+        let (uv0, uv1, uv2) = if self.uvs.is_empty() {
+            ((0.0, 0.0), (1.0, 0.0), (0.0, 1.0))
+        } else {
+            (self.uvs[i0], self.uvs[i1], self.uvs[i2])
+        };
+        let u = uv0.0 * b0 + uv1.0 * b1 + uv2.0 * b2;
+        let v = uv0.1 * b0 + uv1.1 * b1 + uv2.1 * b2;
+
+        // 2辺のuvの差から位置のuとvについての微分を解く
+        let dp1 = self.positions[i1] - p0;
+        let dp2 = self.positions[i2] - p0;
+        let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+        let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+        let uv_det = du1 * dv2 - dv1 * du2;
+        let (dpdu, dpdv) = if uv_det.abs() < 1e-12 {
+            // uvが潰れている三角形は辺の向きを使う
+            (dp1, dp2)
+        } else {
+            (
+                (dp1 * dv2 - dp2 * dv1) / uv_det,
+                (dp2 * du1 - dp1 * du2) / uv_det,
+            )
+        };
+
+        let vertex_color = if self.colors.is_empty() {
+            None
+        } else {
+            Some(self.colors[i0] * b0 + self.colors[i1] * b1 + self.colors[i2] * b2)
+        };
+
+        Some(HitInfo {
+            vertex_color,
+            ..HitInfo::new(t, ray.at(t), n, Arc::clone(material), u, v).with_frame(dpdu, dpdv)
+        })
+    }
+}
+
+// MeshDataの1つの三角形
+pub struct Triangle {
+    data: Arc<MeshData>,
+    index: usize,
+    material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(p0: Float3, p1: Float3, p2: Float3, material: Arc<dyn Material>) -> Self {
+        let data = MeshData {
+            positions: vec![p0, p1, p2],
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            colors: Vec::new(),
+            indices: vec![[0, 1, 2]],
+        };
+        Self {
+            data: Arc::new(data),
+            index: 0,
+            material,
+        }
+    }
+
+    pub fn from_mesh(
+        data: Arc<MeshData>,
+        index: usize,
+        material: Arc<dyn Material>,
+    ) -> Result<Self> {
+        if index >= data.indices.len() {
+            return Err(Error::InvalidScene(format!(
+                "triangle {} is out of range ({} triangles)",
+                index,
+                data.indices.len()
+            )));
+        }
+        Ok(Self {
+            data,
+            index,
+            material,
+        })
+    }
+}
+
+impl Shape for Triangle {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        self.data
+            .hit_triangle(self.index, ray, t0, t1, &self.material)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.data.triangle_bounds(self.index))
+    }
+
+    fn export(&self, exporter: &mut SceneExporter) {
+        self.data
+            .export_triangles(&[self.index], "Triangle", &self.material, exporter);
+    }
+}
+
+// インデックス付き三角形メッシュ. 三角形はメッシュ内のBVHで探索する
+pub struct TriangleMesh {
+    data: Arc<MeshData>,
+    material: Arc<dyn Material>,
+    bvh: Bvh,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Float3>,
+        normals: Vec<Float3>,
+        uvs: Vec<(f64, f64)>,
+        indices: Vec<[usize; 3]>,
+        material: Arc<dyn Material>,
+    ) -> Result<Self> {
+        let data = MeshData::new(positions, normals, uvs, indices)?;
+        Ok(Self::from_data(Arc::new(data), material))
+    }
+
+    pub fn from_data(data: Arc<MeshData>, material: Arc<dyn Material>) -> Self {
+        let bounds: Vec<Aabb> = (0..data.indices.len())
+            .map(|i| data.triangle_bounds(i))
+            .collect();
+        Self {
+            data,
+            material,
+            bvh: Bvh::new(&bounds),
+        }
+    }
+
+    pub fn data(&self) -> &Arc<MeshData> {
+        &self.data
+    }
+
+    pub fn positions(&self) -> &[Float3] {
+        self.data.positions()
+    }
+
+    pub fn indices(&self) -> &[[usize; 3]] {
+        self.data.indices()
+    }
+
+    pub fn material(&self) -> &Arc<dyn Material> {
+        &self.material
+    }
+}
+
+impl Shape for TriangleMesh {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        self.bvh.hit(ray, t0, t1, |i, closest| {
+            self.data.hit_triangle(i, ray, t0, closest, &self.material)
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+
+    fn export(&self, exporter: &mut SceneExporter) {
+        let all: Vec<usize> = (0..self.data.indices.len()).collect();
+        self.data
+            .export_triangles(&all, "TriangleMesh", &self.material, exporter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Box::new(ColorTexture::new(float3::fill(
+            0.5,
+        )))))
+    }
+
+    // z = 0の正方形を対角線で2つに分けたもの
+    fn quad() -> MeshData {
+        MeshData::new(
+            vec![
+                float3::new(0.0, 0.0, 0.0),
+                float3::new(1.0, 0.0, 0.0),
+                float3::new(1.0, 1.0, 0.0),
+                float3::new(0.0, 1.0, 0.0),
+            ],
+            Vec::new(),
+            Vec::new(),
+            vec![[0, 1, 2], [0, 2, 3]],
+        )
+        .unwrap()
+    }
+
+    // 中心の頂点を共有する6つの三角形
+    fn fan() -> MeshData {
+        let mut positions = vec![Float3::zeros()];
+        positions.extend((0..6).map(|i| {
+            let a = i as f64 * PI / 3.0;
+            float3::new(a.cos(), a.sin(), 0.0)
+        }));
+        let indices = (0..6).map(|i| [0, 1 + i, 1 + (i + 1) % 6]).collect();
+        MeshData::new(positions, Vec::new(), Vec::new(), indices).unwrap()
+    }
+
+    fn hit_any(data: &MeshData, ray: &Ray) -> Option<HitInfo> {
+        let m = material();
+        (0..data.indices().len()).find_map(|i| data.hit_triangle(i, ray, 0.0, f64::MAX, &m))
+    }
+
+    // 決まった順に0..1の値を返す
+    fn sequence(count: usize) -> impl Iterator<Item = f64> {
+        (0..count).map(|i| (i as f64 * 0.618_033_988_749_895).fract())
+    }
+
+    #[test]
+    fn hits_inside_with_barycentric_uv() {
+        let data = quad();
+        let ray = Ray::new(float3::new(0.7, 0.2, 1.0), -Float3::z());
+        let hit = data
+            .hit_triangle(0, &ray, 0.0, f64::MAX, &material())
+            .unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-12);
+        assert!((hit.p - float3::new(0.7, 0.2, 0.0)).norm() < 1e-12);
+        assert!((hit.n - Float3::z()).norm() < 1e-12);
+        // uvの無いメッシュでは頂点1が(1, 0), 頂点2が(0, 1)
+        assert!((hit.u - 0.5).abs() < 1e-12 && (hit.v - 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn shared_edge_is_watertight() {
+        let data = quad();
+        let slopes: Vec<f64> = sequence(64).collect();
+        for (s, slope) in sequence(64).zip(slopes.iter().rev()) {
+            // 対角線上の点を斜めのレイで狙う
+            let target = float3::new(s, s, 0.0);
+            let direction = float3::new(slope - 0.5, 0.3 - slope, -1.0);
+            let ray = Ray::new(target - direction * 3.0, direction);
+            assert!(hit_any(&data, &ray).is_some(), "ray through {:?}", target);
+        }
+    }
+
+    #[test]
+    fn shared_vertex_is_watertight() {
+        let data = fan();
+        for s in sequence(64) {
+            let a = s * PI2;
+            let direction = float3::new(a.cos() * 0.7, a.sin() * 0.7, -1.0);
+            let ray = Ray::new(-direction * 2.5, direction);
+            assert!(hit_any(&data, &ray).is_some(), "direction {:?}", direction);
+        }
+    }
+
+    #[test]
+    fn misses() {
+        let data = quad();
+        let m = material();
+        // 外側を通る
+        let outside = Ray::new(float3::new(1.5, 0.5, 1.0), -Float3::z());
+        assert!(hit_any(&data, &outside).is_none());
+        // 面と平行
+        let parallel = Ray::new(float3::new(-1.0, 0.5, 0.0), Float3::x());
+        assert!(hit_any(&data, &parallel).is_none());
+        // 面から遠ざかる
+        let away = Ray::new(float3::new(0.7, 0.2, 1.0), Float3::z());
+        assert!(hit_any(&data, &away).is_none());
+        // t1より遠い
+        let ray = Ray::new(float3::new(0.7, 0.2, 1.0), -Float3::z());
+        assert!(data.hit_triangle(0, &ray, 0.0, 0.5, &m).is_none());
+    }
+
+    #[test]
+    fn hits_backface() {
+        let data = quad();
+        let ray = Ray::new(float3::new(0.7, 0.2, -2.0), Float3::z());
+        let hit = data
+            .hit_triangle(0, &ray, 0.0, f64::MAX, &material())
+            .unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-12);
+        // 法線は頂点の順で決まり、レイの向きでは変わらない
+        assert!((hit.n - Float3::z()).norm() < 1e-12);
+    }
+
+    #[test]
+    fn rejects_out_of_range_indices() {
+        let result = MeshData::new(
+            vec![Float3::zeros()],
+            Vec::new(),
+            Vec::new(),
+            vec![[0, 0, 1]],
+        );
+        assert!(matches!(result, Err(Error::InvalidScene(_))));
+    }
+}
@@ -0,0 +1,854 @@
+use crate::rayt::*;
+use roxmltree::Node;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Mitsuba 3 のXMLシーンの読み込み
+// 対応しているのは perspective/thinlensセンサー, sphere/rectangle/cube/obj/ply,
+// diffuse/conductor/dielectric BSDF, areaエミッタ, constantエミッタ
+// それ以外はできるだけ近似し、ImportedScene::warningsに記録する
+pub fn load_mitsuba(path: impl AsRef<Path>) -> Result<ImportedScene> {
+    let path = path.as_ref();
+    let base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut loader = MitsubaLoader::new(base_dir);
+    loader.include(path)?;
+    Ok(loader.finish())
+}
+
+// 名前付きの屈折率
+fn named_ior(name: &str) -> Option<f64> {
+    Some(match name {
+        "vacuum" => 1.0,
+        "helium" => 1.000036,
+        "hydrogen" => 1.000132,
+        "air" => 1.000277,
+        "carbon dioxide" => 1.00045,
+        "water" => 1.3330,
+        "acetone" => 1.36,
+        "ethanol" => 1.361,
+        "carbon tetrachloride" => 1.461,
+        "glycerol" => 1.4729,
+        "benzene" => 1.501,
+        "silicone oil" => 1.52045,
+        "bromine" => 1.661,
+        "water ice" => 1.31,
+        "fused quartz" => 1.458,
+        "pyrex" => 1.470,
+        "acrylic glass" => 1.49,
+        "polypropylene" => 1.49,
+        "bk7" => 1.5046,
+        "sodium chloride" => 1.544,
+        "amber" => 1.55,
+        "pet" => 1.5750,
+        "diamond" => 2.419,
+        _ => return None,
+    })
+}
+
+fn numbers(s: &str) -> Option<Vec<f64>> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.parse().ok())
+        .collect()
+}
+
+// rectangle, cube はMitsubaと同じく各軸[-1, 1]の範囲
+fn rectangle() -> MeshData {
+    let positions = vec![
+        float3::new(-1.0, -1.0, 0.0),
+        float3::new(1.0, -1.0, 0.0),
+        float3::new(1.0, 1.0, 0.0),
+        float3::new(-1.0, 1.0, 0.0),
+    ];
+    let uvs = vec![(0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)];
+    MeshData::new(positions, Vec::new(), uvs, vec![[0, 1, 2], [0, 2, 3]])
+        .expect("rectangle mesh is valid")
+}
+
+fn cube() -> MeshData {
+    // 頂点iの座標は x = bit0, y = bit1, z = bit2
+    let positions = (0..8)
+        .map(|i| {
+            let c = |bit: usize| if i & bit != 0 { 1.0 } else { -1.0 };
+            float3::new(c(1), c(2), c(4))
+        })
+        .collect();
+    let indices = vec![
+        [0, 2, 1],
+        [1, 2, 3],
+        [4, 5, 6],
+        [5, 7, 6],
+        [0, 1, 4],
+        [1, 5, 4],
+        [2, 6, 3],
+        [3, 6, 7],
+        [0, 4, 2],
+        [2, 4, 6],
+        [1, 3, 5],
+        [3, 7, 5],
+    ];
+    MeshData::new(positions, Vec::new(), Vec::new(), indices).expect("cube mesh is valid")
+}
+
+struct MitsubaLoader {
+    base_dir: PathBuf,
+    defaults: HashMap<String, String>,
+    bsdfs: HashMap<String, Option<Arc<dyn Material>>>,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    camera_params: Option<CameraParams>,
+    film: Film,
+    background: Float3,
+    environment: Option<EnvironmentLight>,
+    max_depth: Option<usize>,
+    world: ShapeList,
+    warnings: Vec<String>,
+    // エラーメッセージ用の読み込み中のファイル
+    path: PathBuf,
+}
+
+impl MitsubaLoader {
+    fn new(base_dir: PathBuf) -> Self {
+        Self {
+            base_dir,
+            defaults: HashMap::new(),
+            bsdfs: HashMap::new(),
+            textures: HashMap::new(),
+            camera_params: None,
+            film: Film {
+                width: 768,
+                height: 576,
+                spp: 4,
+                filename: None,
+            },
+            background: Float3::zeros(),
+            environment: None,
+            max_depth: None,
+            world: ShapeList::new(),
+            warnings: Vec::new(),
+            path: PathBuf::new(),
+        }
+    }
+
+    fn warn(&mut self, msg: String) {
+        if !self.warnings.contains(&msg) {
+            self.warnings.push(msg);
+        }
+    }
+
+    fn error(&self, node: Node, msg: impl std::fmt::Display) -> Error {
+        let line = node.document().text_pos_at(node.range().start).row;
+        Error::InvalidScene(format!("{}:{}: {}", self.path.display(), line, msg))
+    }
+
+    fn finish(mut self) -> ImportedScene {
+        let camera_params = self.camera_params.take().unwrap_or_else(|| {
+            self.warn("scene has no sensor; using a default camera".to_string());
+            camera_from_matrix(
+                &Mat4::identity(),
+                -1.0,
+                39.3077,
+                FovAxis::X,
+                self.film.width,
+                self.film.height,
+            )
+        });
+        ImportedScene {
+            world: self.world,
+            camera_params,
+            film: self.film,
+            background: self.background,
+            environment: self.environment,
+            max_depth: self.max_depth,
+            warnings: self.warnings,
+        }
+    }
+
+    fn include(&mut self, path: &Path) -> Result<()> {
+        let text = fs::read_to_string(path)?;
+        let doc = roxmltree::Document::parse(&text)
+            .map_err(|e| Error::InvalidScene(format!("{}: {}", path.display(), e)))?;
+        let parent = std::mem::replace(&mut self.path, path.to_path_buf());
+        let root = doc.root_element();
+        if root.tag_name().name() != "scene" {
+            return Err(self.error(root, "root element must be <scene>"));
+        }
+        for node in root.children().filter(Node::is_element) {
+            self.scene_element(node)?;
+        }
+        self.path = parent;
+        Ok(())
+    }
+
+    // $nameを<default>の値で置き換えた属性
+    fn attr(&self, node: Node, name: &str) -> Option<String> {
+        let value = node.attribute(name)?;
+        let mut result = String::new();
+        let mut rest = value;
+        while let Some(i) = rest.find('$') {
+            result.push_str(&rest[..i]);
+            let after = &rest[i + 1..];
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            match self.defaults.get(&after[..end]) {
+                Some(v) => result.push_str(v),
+                None => result.push_str(&rest[i..i + 1 + end]),
+            }
+            rest = &after[end..];
+        }
+        result.push_str(rest);
+        Some(result)
+    }
+
+    // name属性がnameの子要素 (プロパティ)
+    fn property<'a, 'input>(&self, node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+        node.children()
+            .filter(Node::is_element)
+            .find(|c| self.attr(*c, "name").as_deref() == Some(name))
+    }
+
+    fn value<T: std::str::FromStr>(&self, node: Node, name: &str, default: T) -> Result<T> {
+        match self.property(node, name) {
+            Some(p) => {
+                let value = self.attr(p, "value").unwrap_or_default();
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| self.error(p, format!("invalid value {:?} for {}", value, name)))
+            }
+            None => Ok(default),
+        }
+    }
+
+    fn string(&self, node: Node, name: &str) -> Option<String> {
+        self.property(node, name)
+            .and_then(|p| self.attr(p, "value"))
+    }
+
+    // value="x, y, z" または x=, y=, z= 属性
+    fn float3_attr(&self, node: Node, default: f64) -> Result<Float3> {
+        if let Some(value) = self.attr(node, "value") {
+            return match numbers(&value).as_deref() {
+                Some([x]) => Ok(float3::fill(*x)),
+                Some([x, y, z]) => Ok(float3::new(*x, *y, *z)),
+                _ => Err(self.error(node, format!("invalid vector {:?}", value))),
+            };
+        }
+        let mut v = float3::fill(default);
+        for (i, axis) in ["x", "y", "z"].iter().enumerate() {
+            if let Some(s) = self.attr(node, axis) {
+                v[i] = s
+                    .trim()
+                    .parse()
+                    .map_err(|_| self.error(node, format!("invalid number {:?}", s)))?;
+            }
+        }
+        Ok(v)
+    }
+
+    fn color(&mut self, node: Node, name: &str, default: Float3) -> Result<Float3> {
+        let p = match self.property(node, name) {
+            Some(p) => p,
+            None => return Ok(default),
+        };
+        let value = self.attr(p, "value").unwrap_or_default();
+        match p.tag_name().name() {
+            "rgb" | "float" => self.float3_attr(p, 0.0),
+            "spectrum" => {
+                if let Some(v) = numbers(&value) {
+                    if let [x] = v.as_slice() {
+                        return Ok(float3::fill(*x));
+                    }
+                }
+                // "波長:値, ..." の形式
+                let samples: Option<Vec<f64>> = value
+                    .split(',')
+                    .map(|s| s.split(':').nth(1).and_then(|v| v.trim().parse().ok()))
+                    .collect();
+                match samples {
+                    Some(s) if !s.is_empty() => {
+                        self.warn(format!(
+                            "spectrum {:?} was approximated by its average",
+                            name
+                        ));
+                        Ok(float3::fill(s.iter().sum::<f64>() / s.len() as f64))
+                    }
+                    _ => Err(self.error(p, format!("invalid spectrum {:?}", value))),
+                }
+            }
+            tag => {
+                self.warn(format!(
+                    "<{}> for {:?} is not supported and was replaced with a constant",
+                    tag, name
+                ));
+                Ok(default)
+            }
+        }
+    }
+
+    // 色またはテクスチャ
+    fn color_texture(
+        &mut self,
+        node: Node,
+        name: &str,
+        default: Float3,
+    ) -> Result<Box<dyn Texture>> {
+        if let Some(p) = self.property(node, name) {
+            match p.tag_name().name() {
+                "texture" => return Ok(Box::new(self.texture(p)?)),
+                "ref" => {
+                    let id = self.attr(p, "id").unwrap_or_default();
+                    return match self.textures.get(&id) {
+                        Some(t) => Ok(Box::new(Arc::clone(t))),
+                        None => Err(self.error(p, format!("unknown texture {:?}", id))),
+                    };
+                }
+                _ => {}
+            }
+        }
+        Ok(Box::new(ColorTexture::new(
+            self.color(node, name, default)?,
+        )))
+    }
+
+    fn texture(&mut self, node: Node) -> Result<Arc<dyn Texture>> {
+        let ty = self.attr(node, "type").unwrap_or_default();
+        Ok(match ty.as_str() {
+            "bitmap" => {
+                let file = self
+                    .base_dir
+                    .join(self.string(node, "filename").unwrap_or_default());
+                if self.property(node, "to_uv").is_some() {
+                    self.warn("bitmap to_uv is ignored".to_string());
+                }
+                // raw = trueなら色空間を変換しない
+                let color_space = match self.string(node, "raw").as_deref() {
+                    Some("true") => ColorSpace::Linear,
+                    _ => ColorSpace::Srgb,
+                };
+                let filter = match self.string(node, "filter_type").as_deref() {
+                    Some("nearest") => TextureFilter::Nearest,
+                    Some("bilinear") => TextureFilter::Bilinear,
+                    Some("ewa") => TextureFilter::Ewa,
+                    _ => TextureFilter::Trilinear,
+                };
+                let wrap = match self.string(node, "wrap_mode").as_deref() {
+                    Some("clamp") => WrapMode::Clamp,
+                    Some("mirror") => WrapMode::Mirror,
+                    _ => WrapMode::Repeat,
+                };
+                Arc::new(
+                    ImageTexture::open(&file.to_string_lossy(), (1.0, 1.0), color_space)?
+                        .with_filter(filter)
+                        .with_wrap(wrap),
+                )
+            }
+            _ => {
+                self.warn(format!(
+                    "texture {:?} is not supported and was replaced with a constant",
+                    ty
+                ));
+                Arc::new(ColorTexture::new(float3::fill(0.5)))
+            }
+        })
+    }
+
+    fn transform(&self, node: Node) -> Result<Mat4> {
+        let mut m = Mat4::identity();
+        for op in node.children().filter(Node::is_element) {
+            let op_matrix = match op.tag_name().name() {
+                "translate" => Mat4::new_translation(&self.float3_attr(op, 0.0)?),
+                "scale" => Mat4::new_nonuniform_scaling(&self.float3_attr(op, 1.0)?),
+                "rotate" => {
+                    let axis = na::Unit::try_new(self.float3_attr(op, 0.0)?, 0.0)
+                        .ok_or_else(|| self.error(op, "rotation axis is zero"))?;
+                    let angle: f64 = self
+                        .attr(op, "angle")
+                        .and_then(|a| a.trim().parse().ok())
+                        .ok_or_else(|| self.error(op, "missing angle"))?;
+                    na::Rotation3::from_axis_angle(&axis, angle.to_radians()).to_homogeneous()
+                }
+                "matrix" => {
+                    let value = self.attr(op, "value").unwrap_or_default();
+                    match numbers(&value) {
+                        Some(v) if v.len() == 16 => Mat4::from_row_slice(&v),
+                        Some(v) if v.len() == 9 => na::Matrix3::from_row_slice(&v).to_homogeneous(),
+                        _ => return Err(self.error(op, format!("invalid matrix {:?}", value))),
+                    }
+                }
+                "lookat" => {
+                    let point = |name: &str| -> Result<Option<Float3>> {
+                        match self.attr(op, name) {
+                            Some(s) => match numbers(&s).as_deref() {
+                                Some([x, y, z]) => Ok(Some(float3::new(*x, *y, *z))),
+                                _ => Err(self.error(op, format!("invalid {} {:?}", name, s))),
+                            },
+                            None => Ok(None),
+                        }
+                    };
+                    let origin = point("origin")?.unwrap_or_default();
+                    let target = point("target")?.unwrap_or_else(|| vector![0.0, 0.0, 1.0]);
+                    let dir = (target - origin)
+                        .try_normalize(0.0)
+                        .ok_or_else(|| self.error(op, "lookat origin equals target"))?;
+                    let up = point("up")?.unwrap_or_else(|| {
+                        // 視線に平行でない軸を選ぶ
+                        if dir.y.abs() < 0.9 {
+                            vector![0.0, 1.0, 0.0]
+                        } else {
+                            vector![0.0, 0.0, 1.0]
+                        }
+                    });
+                    let left = up.cross(&dir).try_normalize(0.0).ok_or_else(|| {
+                        self.error(op, "lookat up is parallel to the view direction")
+                    })?;
+                    let new_up = dir.cross(&left);
+                    let mut look = Mat4::identity();
+                    look.fixed_slice_mut::<3, 1>(0, 0).copy_from(&left);
+                    look.fixed_slice_mut::<3, 1>(0, 1).copy_from(&new_up);
+                    look.fixed_slice_mut::<3, 1>(0, 2).copy_from(&dir);
+                    look.fixed_slice_mut::<3, 1>(0, 3).copy_from(&origin);
+                    look
+                }
+                tag => return Err(self.error(op, format!("unknown transform <{}>", tag))),
+            };
+            m = op_matrix * m;
+        }
+        Ok(m)
+    }
+
+    // 空の明るさの分布はPreethamのモデルで近似する
+    fn sunsky(&mut self, node: Node) -> Result<()> {
+        self.warn("sunsky was approximated with the Preetham sky model".to_string());
+        let direction = match self.property(node, "sun_direction") {
+            Some(p) => self.float3_attr(p, 0.0)?,
+            None => {
+                self.warn("sunsky location and time are not supported".to_string());
+                float3::new(0.0, 1.0, -1.0)
+            }
+        };
+        let direction = self.to_world(node)?.transform_vector(&direction);
+        let sky_scale: f64 = self.value(node, "sky_scale", 1.0)?;
+        let sun_scale: f64 = self.value(node, "sun_scale", 1.0)?;
+        if sun_scale != sky_scale {
+            self.warn("sunsky sun_scale was replaced with sky_scale".to_string());
+        }
+        let sky = SunSky::new(direction)
+            .with_turbidity(self.value(node, "turbidity", 3.0)?)
+            .with_ground_albedo(self.color(node, "albedo", float3::fill(0.3))?)
+            .with_sun_size(self.value(node, "sun_aperture", SUN_ANGULAR_DIAMETER)?)
+            .with_intensity(sky_scale);
+        self.environment = Some(sky.environment());
+        Ok(())
+    }
+
+    fn to_world(&self, node: Node) -> Result<Mat4> {
+        match self.property(node, "to_world") {
+            Some(t) => self.transform(t),
+            None => Ok(Mat4::identity()),
+        }
+    }
+
+    fn scene_element(&mut self, node: Node) -> Result<()> {
+        match node.tag_name().name() {
+            "default" => {
+                let name = self.attr(node, "name").unwrap_or_default();
+                let value = self.attr(node, "value").unwrap_or_default();
+                self.defaults.entry(name).or_insert(value);
+            }
+            "include" => {
+                let file = self
+                    .base_dir
+                    .join(self.attr(node, "filename").unwrap_or_default());
+                self.include(&file)?;
+            }
+            "integrator" => {
+                // aovなどの中に入れ子になっている場合もある
+                let inner = node
+                    .descendants()
+                    .find(|n| self.attr(*n, "name").as_deref() == Some("max_depth"));
+                if let Some(inner) = inner.and_then(|n| n.parent()) {
+                    let depth: i64 = self.value(inner, "max_depth", -1)?;
+                    self.max_depth = usize::try_from(depth).ok();
+                }
+            }
+            "sensor" => self.sensor(node)?,
+            "bsdf" => {
+                let id = self.attr(node, "id").unwrap_or_default();
+                let bsdf = self.bsdf(node)?;
+                self.bsdfs.insert(id, bsdf);
+            }
+            "texture" => {
+                let id = self.attr(node, "id").unwrap_or_default();
+                let texture = self.texture(node)?;
+                self.textures.insert(id, texture);
+            }
+            "shape" => self.shape(node)?,
+            "emitter" => {
+                let ty = self.attr(node, "type").unwrap_or_default();
+                if ty == "constant" {
+                    let radiance = self.color(node, "radiance", float3::one())?;
+                    self.background += radiance;
+                } else if ty == "envmap" {
+                    let file = self
+                        .base_dir
+                        .join(self.string(node, "filename").unwrap_or_default());
+                    // Mitsubaの経度はこのレンダラと半周ずれている
+                    let half_turn = Quat::from_axis_angle(&Float3::y_axis(), PI);
+                    let rotation = rotation_of(&self.to_world(node)?) * half_turn;
+                    self.environment = Some(
+                        EnvironmentLight::new(&file.to_string_lossy())?
+                            .with_rotation(rotation)
+                            .with_intensity(self.value(node, "scale", 1.0)?),
+                    );
+                } else if ty == "sunsky" {
+                    self.sunsky(node)?;
+                } else {
+                    self.warn(format!("emitter {:?} is not supported", ty));
+                }
+            }
+            tag => self.warn(format!("<{}> is not supported and was ignored", tag)),
+        }
+        Ok(())
+    }
+
+    fn sensor(&mut self, node: Node) -> Result<()> {
+        let ty = self.attr(node, "type").unwrap_or_default();
+        match ty.as_str() {
+            "perspective" | "thinlens" => {}
+            _ => self.warn(format!(
+                "sensor {:?} is not supported; using perspective",
+                ty
+            )),
+        }
+        for child in node.children().filter(Node::is_element) {
+            match child.tag_name().name() {
+                "film" => {
+                    self.film.width = self.value(child, "width", 768)?;
+                    self.film.height = self.value(child, "height", 576)?;
+                    if self.property(child, "crop_width").is_some() {
+                        self.warn("film crop window is ignored".to_string());
+                    }
+                }
+                "sampler" => self.film.spp = self.value(child, "sample_count", 4)?,
+                _ => {}
+            }
+        }
+        let fov = match (
+            self.property(node, "fov"),
+            self.string(node, "focal_length"),
+        ) {
+            (Some(_), _) => self.value(node, "fov", 0.0)?,
+            // 35mm判の横幅に対する焦点距離
+            (None, Some(f)) => {
+                let f: f64 = f
+                    .trim_end_matches("mm")
+                    .parse()
+                    .map_err(|_| self.error(node, format!("invalid focal_length {:?}", f)))?;
+                (36.0 / (2.0 * f)).atan().to_degrees() * 2.0
+            }
+            (None, None) => 39.3077,
+        };
+        let fov_axis = match self.string(node, "fov_axis").as_deref() {
+            None | Some("x") => FovAxis::X,
+            Some("y") => FovAxis::Y,
+            Some("diagonal") => FovAxis::Diagonal,
+            Some("smaller") => FovAxis::Smaller,
+            Some("larger") => FovAxis::Larger,
+            Some(axis) => return Err(self.error(node, format!("invalid fov_axis {:?}", axis))),
+        };
+        // Mitsubaのカメラ空間は+xが画像の左
+        let to_world = self.to_world(node)?;
+        let mut camera_params = camera_from_matrix(
+            &to_world,
+            -1.0,
+            fov,
+            fov_axis,
+            self.film.width,
+            self.film.height,
+        );
+        if ty == "thinlens" {
+            camera_params.lens_radius = self.value(node, "aperture_radius", 0.0)?;
+            let focus_distance: f64 = self.value(node, "focus_distance", 0.0)?;
+            if focus_distance > 0.0 {
+                camera_params.focus_distance = Some(focus_distance);
+            }
+        }
+        self.camera_params = Some(camera_params);
+        Ok(())
+    }
+
+    // Noneはnull BSDF (形状を描画しない)
+    fn bsdf(&mut self, node: Node) -> Result<Option<Arc<dyn Material>>> {
+        if node.tag_name().name() == "ref" {
+            let id = self.attr(node, "id").unwrap_or_default();
+            return match self.bsdfs.get(&id) {
+                Some(bsdf) => Ok(bsdf.clone()),
+                None => Err(self.error(node, format!("unknown bsdf {:?}", id))),
+            };
+        }
+        let ty = self.attr(node, "type").unwrap_or_default();
+        let material: Arc<dyn Material> = match ty.as_str() {
+            "null" => return Ok(None),
+            "diffuse" => Arc::new(Lambertian::new(self.color_texture(
+                node,
+                "reflectance",
+                float3::fill(0.5),
+            )?)),
+            "conductor" | "roughconductor" => {
+                let default_alpha = if ty == "roughconductor" { 0.1 } else { 0.0 };
+                let alpha: f64 = self.value(node, "alpha", default_alpha)?;
+                let base = match self.string(node, "material") {
+                    Some(name) => named_metal(&name).unwrap_or_else(|| {
+                        self.warn(format!("unknown conductor {:?}; using a mirror", name));
+                        float3::one()
+                    }),
+                    None if self.property(node, "eta").is_some() => {
+                        let eta = self.color(node, "eta", float3::one())?;
+                        let k = self.color(node, "k", Float3::zeros())?;
+                        conductor_reflectance(eta, k)
+                    }
+                    None => float3::one(),
+                };
+                let specular = self.color(node, "specular_reflectance", float3::one())?;
+                Arc::new(Metal::new(
+                    Box::new(ColorTexture::new(base.component_mul(&specular))),
+                    alpha.clamp(0.0, 1.0),
+                ))
+            }
+            "dielectric" | "roughdielectric" | "thindielectric" => {
+                if ty != "dielectric" {
+                    self.warn(format!("{} was approximated as dielectric", ty));
+                }
+                let int_ior = self.ior(node, "int_ior", "bk7")?;
+                let ext_ior = self.ior(node, "ext_ior", "air")?;
+                Arc::new(Dielectric::new(int_ior / ext_ior))
+            }
+            "plastic" | "roughplastic" => {
+                self.warn(format!("{} was approximated as diffuse", ty));
+                Arc::new(Lambertian::new(self.color_texture(
+                    node,
+                    "diffuse_reflectance",
+                    float3::fill(0.5),
+                )?))
+            }
+            "principled" => {
+                self.warn("principled was approximated as diffuse or conductor".to_string());
+                let base_color = self.color_texture(node, "base_color", float3::fill(0.5))?;
+                let metallic: f64 = self.value(node, "metallic", 0.0)?;
+                if metallic >= 0.5 {
+                    let roughness: f64 = self.value(node, "roughness", 0.5)?;
+                    Arc::new(Metal::new(base_color, roughness.clamp(0.0, 1.0)))
+                } else {
+                    Arc::new(Lambertian::new(base_color))
+                }
+            }
+            "twosided" | "mask" | "bumpmap" | "normalmap" | "blendbsdf" => {
+                if ty != "twosided" {
+                    self.warn(format!("{} was replaced with its nested bsdf", ty));
+                }
+                let inner = node
+                    .children()
+                    .filter(Node::is_element)
+                    .find(|c| matches!(c.tag_name().name(), "bsdf" | "ref"))
+                    .ok_or_else(|| self.error(node, format!("{} has no nested bsdf", ty)))?;
+                return self.bsdf(inner);
+            }
+            _ => {
+                self.warn(format!("bsdf {:?} was approximated as diffuse", ty));
+                Arc::new(Lambertian::new(Box::new(ColorTexture::new(float3::fill(
+                    0.5,
+                )))))
+            }
+        };
+        Ok(Some(material))
+    }
+
+    fn ior(&self, node: Node, name: &str, default: &str) -> Result<f64> {
+        let value = self
+            .string(node, name)
+            .unwrap_or_else(|| default.to_string());
+        value
+            .trim()
+            .parse()
+            .ok()
+            .or_else(|| named_ior(&value.to_ascii_lowercase()))
+            .ok_or_else(|| self.error(node, format!("unknown {} {:?}", name, value)))
+    }
+
+    fn shape(&mut self, node: Node) -> Result<()> {
+        let ty = self.attr(node, "type").unwrap_or_default();
+        let mut material: Option<Arc<dyn Material>> = Some(Arc::new(Lambertian::new(Box::new(
+            ColorTexture::new(float3::fill(0.5)),
+        ))));
+        for child in node.children().filter(Node::is_element) {
+            match child.tag_name().name() {
+                "bsdf" => material = self.bsdf(child)?,
+                "ref" if child.attribute("name").is_none() => material = self.bsdf(child)?,
+                "emitter" => {
+                    let emitter_type = self.attr(child, "type").unwrap_or_default();
+                    if emitter_type != "area" {
+                        self.warn(format!(
+                            "emitter {:?} on a shape is not supported",
+                            emitter_type
+                        ));
+                    }
+                    let radiance = self.color(child, "radiance", float3::one())?;
+                    material = Some(Arc::new(DiffuseLight::new(
+                        Box::new(ColorTexture::new(radiance)),
+                        1.0,
+                    )));
+                }
+                _ => {}
+            }
+        }
+        let material = match material {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+
+        let to_world = self.to_world(node)?;
+        match ty.as_str() {
+            "sphere" => {
+                let center = match self.property(node, "center") {
+                    Some(p) => self.float3_attr(p, 0.0)?,
+                    None => Float3::zeros(),
+                };
+                let radius = self.value(node, "radius", 1.0)?;
+                let mut warnings = Vec::new();
+                let sphere = transformed_sphere(&to_world, center, radius, material, |msg| {
+                    warnings.push(msg)
+                });
+                warnings.into_iter().for_each(|msg| self.warn(msg));
+                self.world.push(Box::new(sphere));
+            }
+            "rectangle" => self.world.push(Box::new(transformed_mesh(
+                &to_world,
+                &rectangle(),
+                material,
+            ))),
+            "cube" => self
+                .world
+                .push(Box::new(transformed_mesh(&to_world, &cube(), material))),
+            "obj" | "ply" => {
+                let file = self
+                    .base_dir
+                    .join(self.string(node, "filename").unwrap_or_default());
+                if ty == "obj" {
+                    let model = ObjModel::load_with_material(&file, Arc::clone(&material))?;
+                    for group in model.groups {
+                        let mesh =
+                            transformed_mesh(&to_world, group.mesh.data(), Arc::clone(&material));
+                        self.world.push(Box::new(mesh));
+                    }
+                } else {
+                    let data = load_ply(&file)?;
+                    self.world
+                        .push(Box::new(transformed_mesh(&to_world, &data, material)));
+                }
+            }
+            _ => self.warn(format!("shape {:?} is not supported and was skipped", ty)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // テストごとのディレクトリにfilesを書いて、最初のファイルを読む
+    fn load(name: &str, files: &[(&str, &str)]) -> Result<ImportedScene> {
+        let dir =
+            std::env::temp_dir().join(format!("ayanami-mitsuba-{}-{}", name, std::process::id()));
+        for (file, text) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        let scene = load_mitsuba(dir.join(files[0].0));
+        fs::remove_dir_all(&dir).unwrap();
+        scene
+    }
+
+    fn scene(body: &str) -> String {
+        format!("<scene version=\"3.0.0\">\n{}\n</scene>\n", body)
+    }
+
+    fn hit_t(scene: &ImportedScene, origin: Float3, direction: Float3) -> Option<f64> {
+        scene
+            .world
+            .hit(&Ray::new(origin, direction), 0.001, f64::MAX)
+            .map(|hit| hit.t)
+    }
+
+    #[test]
+    fn camera_is_right_handed() {
+        let text = scene(
+            r#"<sensor type="perspective">
+                <float name="fov" value="45"/>
+                <transform name="to_world">
+                    <lookat origin="0, 0, 5" target="0, 0, 0" up="0, 1, 0"/>
+                </transform>
+            </sensor>"#,
+        );
+        let scene = load("camera", &[("scene.xml", &text)]).unwrap();
+        let params = &scene.camera_params;
+        assert!((params.origin - float3::new(0.0, 0.0, 5.0)).norm() < 1e-9);
+        assert!(((params.lookat - params.origin).normalize() + Float3::z()).norm() < 1e-9);
+        // Mitsubaは右手系なので、pbrtと同じ向きでも画像の右がワールドの+x
+        assert!(!params.mirror);
+        let camera = params.camera(1.0);
+        assert!(camera.ray(0.9, 0.5).direction.x > 0.0);
+        assert!(camera.ray(0.5, 0.9).direction.y > 0.0);
+    }
+
+    #[test]
+    fn matrix_is_row_major() {
+        let text = scene(
+            r#"<shape type="sphere">
+                <transform name="to_world">
+                    <matrix value="1 0 0 3  0 1 0 4  0 0 1 5  0 0 0 1"/>
+                </transform>
+            </shape>"#,
+        );
+        let scene = load("matrix", &[("scene.xml", &text)]).unwrap();
+        let t = hit_t(&scene, float3::new(3.0, 4.0, 10.0), -Float3::z()).unwrap();
+        assert!((t - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn include_is_relative_to_the_main_file() {
+        let main = scene(r#"<include filename="parts/a.xml"/>"#);
+        let a = scene(r#"<include filename="parts/b.xml"/>"#);
+        let b = scene(r#"<shape type="sphere"><float name="radius" value="2"/></shape>"#);
+        let scene = load(
+            "include",
+            &[
+                ("scene.xml", &main),
+                ("parts/a.xml", &a),
+                ("parts/b.xml", &b),
+            ],
+        )
+        .unwrap();
+        let t = hit_t(&scene, float3::new(0.0, 0.0, 10.0), -Float3::z()).unwrap();
+        assert!((t - 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn warns_on_unsupported_elements() {
+        let text = scene(
+            r#"<medium type="homogeneous"/>
+            <emitter type="spot"/>
+            <shape type="disk"/>"#,
+        );
+        let scene = load("warnings", &[("scene.xml", &text)]).unwrap();
+        let has = |text: &str| scene.warnings.iter().any(|w| w.contains(text));
+        assert!(has("<medium>"), "{:?}", scene.warnings);
+        assert!(has("\"spot\""), "{:?}", scene.warnings);
+        assert!(has("\"disk\""), "{:?}", scene.warnings);
+        // センサーが無ければ既定のカメラを使う
+        assert!(has("no sensor"), "{:?}", scene.warnings);
+    }
+}
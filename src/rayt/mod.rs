@@ -6,26 +6,93 @@ pub const EPS: f64 = 1e-6;
 use na::vector;
 use nalgebra as na;
 
+mod aabb;
+mod animation;
+mod bvh;
 mod camera;
-pub(crate) mod color;
-pub(crate) mod float3;
+pub mod color;
+mod environment;
+mod error;
+mod export;
+pub mod float3;
+mod gltf_import;
 mod material;
-pub(crate) mod math;
+pub mod math;
+mod mesh;
+mod mitsuba;
+mod noise;
+mod obj;
+mod pbrt;
+mod ply;
+mod procedural;
+mod projection;
 mod ray;
 mod render;
-mod texture;
-mod window;
+mod scene_graph;
+mod scene_import;
 mod shape;
+mod shape_builder;
+mod sky;
+mod texture;
+mod texture_cache;
+mod texture_ops;
 mod transform;
+#[cfg(feature = "window")]
+mod window;
+
+pub use self::aabb::Aabb;
+pub use self::animation::{
+    render_animation, write_apng, write_gif, AnimationSettings, CameraTrack, FrameStatus,
+    Interpolate, Interpolation, Track, TrackKey, TransformTrack,
+};
+pub use self::bvh::{Bvh, ShapeBvh};
+pub use self::camera::{Aperture, Camera, CameraParams, PhysicalCamera};
+pub use self::color::{degamma, f64_to_u8, float3_to_rgb, gamma, luminance, srgb_to_linear, white};
+pub use self::environment::{equirect_direction, trace_environment, EnvironmentLight, SunLight};
+pub use self::error::{Error, Result};
+pub use self::export::{ExportMesh, SceneExporter};
+pub use self::gltf_import::GltfScene;
+pub use self::material::{
+    BumpMap, Dielectric, DiffuseLight, Lambertian, Material, MaterialPreview, Metal, NormalMap,
+    ScatterInfo,
+};
+pub use self::math::{Float3, Mat4, Quat};
+pub use self::mesh::{MeshData, Triangle, TriangleMesh};
+pub use self::mitsuba::load_mitsuba;
+pub use self::noise::{Fractal, Noise, NoiseBasis, WorleyPoint};
+pub use self::obj::{load_mtl, triangulate, ObjGroup, ObjModel};
+pub use self::pbrt::load_pbrt;
+pub use self::ply::load_ply;
+pub use self::procedural::{ColorRamp, NoisePattern, NoiseTexture, VoronoiFeature};
+pub use self::projection::{
+    CubemapCamera, EquirectangularCamera, FisheyeCamera, OrthographicCamera, Projection,
+};
+pub use self::ray::{HitInfo, Ray, RayDifferential, SurfaceDifferentials};
+pub use self::render::{
+    backup, render, render_aa, render_aa_image, render_aa_with, render_image, render_with,
+    RenderSettings, Scene, SceneWithDepth,
+};
+pub use self::scene_graph::{NodeId, SceneGraph, SceneNode};
+pub use self::scene_import::{Film, ImportedScene};
+pub use self::shape::{Cube, MovingSphere, Rect, RectAxisType, Shape, ShapeList, Sphere};
+pub use self::shape_builder::{Empty, ShapeBuilder, WithMaterial, WithShape, WithTexture};
+pub use self::sky::{SunSky, SUN_ANGULAR_DIAMETER};
+pub use self::texture::{
+    CheckerTexture, ColorSpace, ColorTexture, ImageTexture, Texture, TextureFilter,
+    VertexColorTexture, WrapMode,
+};
+pub use self::texture_cache::TextureCache;
+pub use self::texture_ops::{
+    AddTexture, MixTexture, MultiplyTexture, ObjectSpaceTexture, RampTexture, ScaleTexture,
+    TriplanarTexture, UvTransform, UvTransformTexture,
+};
+pub use self::transform::{Affine, AnimatedTransform, Keyframe, Transform};
+#[cfg(feature = "window")]
+pub use self::window::draw_in_window;
 
-pub use self::camera::Camera;
-pub use self::color::*;
-pub use self::material::*;
-pub use self::math::{Float3, Quat};
-pub use self::ray::{HitInfo, Ray};
-pub use self::render::*;
-pub use self::texture::*;
-pub use self::window::*;
-pub use std::sync::Arc;
-pub use self::shape::*;
-pub use self::transform::*;
\ No newline at end of file
+// 以下はクレートの中だけで使う
+pub(crate) use self::scene_import::{
+    camera_from_matrix, conductor_reflectance, named_metal, rotation_of, transformed_mesh,
+    transformed_sphere, FovAxis,
+};
+pub(crate) use std::sync::Arc;
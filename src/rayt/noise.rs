@@ -0,0 +1,270 @@
+use crate::rayt::*;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+// 勾配ノイズの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseBasis {
+    // Ken PerlinのImproved Noise. 格子の8点から補間する
+    Perlin,
+    // 四面体の4点から補間する. 格子の向きの模様が出にくい
+    Simplex,
+}
+
+// オクターブを重ねるときの設定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fractal {
+    pub octaves: u32,
+    // オクターブごとに周波数を何倍にするか
+    pub lacunarity: f64,
+    // オクターブごとに振幅を何倍にするか
+    pub gain: f64,
+}
+
+impl Default for Fractal {
+    fn default() -> Self {
+        Self {
+            octaves: 5,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+}
+
+impl Fractal {
+    // 周波数と振幅を変えながらfを足し合わせる. 振幅の合計で割るので範囲はfと同じ
+    pub fn sum(&self, p: Float3, f: impl Fn(Float3) -> f64) -> f64 {
+        let mut sum = 0.0;
+        let mut norm = 0.0;
+        let mut freq = 1.0;
+        let mut amplitude = 1.0;
+        for _ in 0..self.octaves.max(1) {
+            sum += f(p * freq) * amplitude;
+            norm += amplitude;
+            freq *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        sum / norm
+    }
+}
+
+// Worleyノイズで一番近い特徴点と2番目に近い特徴点までの距離
+#[derive(Debug, Clone, Copy)]
+pub struct WorleyPoint {
+    pub f1: f64,
+    pub f2: f64,
+    // 一番近い特徴点のセルごとの乱数 (0..1)
+    pub cell: f64,
+}
+
+// 乱数の種から作るノイズ. 同じ種なら同じ模様になる
+#[derive(Debug, Clone)]
+pub struct Noise {
+    // 0..256の並べ替えを2回並べたもの
+    perm: Vec<usize>,
+    basis: NoiseBasis,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+// Improved Noiseの勾配 (立方体の辺の中点への12方向)
+const GRADIENTS: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0],
+    [-1.0, 1.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [-1.0, 0.0, 1.0],
+    [1.0, 0.0, -1.0],
+    [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0],
+    [0.0, -1.0, 1.0],
+    [0.0, 1.0, -1.0],
+    [0.0, -1.0, -1.0],
+];
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        let mut perm: Vec<usize> = (0..256).collect();
+        perm.shuffle(&mut StdRng::seed_from_u64(seed));
+        perm.extend_from_within(..);
+        Self {
+            perm,
+            basis: NoiseBasis::Perlin,
+        }
+    }
+
+    pub fn with_basis(mut self, basis: NoiseBasis) -> Self {
+        self.basis = basis;
+        self
+    }
+
+    pub fn basis(&self) -> NoiseBasis {
+        self.basis
+    }
+
+    // 格子点のハッシュ (0..256)
+    fn hash(&self, i: i64, j: i64, k: i64) -> usize {
+        let p = &self.perm;
+        p[p[p[(i & 255) as usize] + (j & 255) as usize] + (k & 255) as usize]
+    }
+
+    fn gradient(&self, hash: usize, d: Float3) -> f64 {
+        let g = GRADIENTS[hash % 12];
+        g[0] * d.x + g[1] * d.y + g[2] * d.z
+    }
+
+    // おおよそ-1..1
+    pub fn noise(&self, p: Float3) -> f64 {
+        match self.basis {
+            NoiseBasis::Perlin => self.perlin(p),
+            NoiseBasis::Simplex => self.simplex(p),
+        }
+    }
+
+    // fBm (fractal Brownian motion). おおよそ-1..1
+    pub fn fbm(&self, p: Float3, fractal: &Fractal) -> f64 {
+        fractal.sum(p, |q| self.noise(q))
+    }
+
+    // ノイズの絶対値を重ねたもの. 0..1で、谷が鋭くなる
+    pub fn turbulence(&self, p: Float3, fractal: &Fractal) -> f64 {
+        fractal.sum(p, |q| self.noise(q).abs())
+    }
+
+    fn perlin(&self, p: Float3) -> f64 {
+        let fade = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let cell = p.map(f64::floor);
+        let (i, j, k) = (cell.x as i64, cell.y as i64, cell.z as i64);
+        let d = p - cell;
+        let (u, v, w) = (fade(d.x), fade(d.y), fade(d.z));
+        let corner = |di: i64, dj: i64, dk: i64| {
+            let offset = float3::new(di as f64, dj as f64, dk as f64);
+            self.gradient(self.hash(i + di, j + dj, k + dk), d - offset)
+        };
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        lerp(
+            lerp(
+                lerp(corner(0, 0, 0), corner(1, 0, 0), u),
+                lerp(corner(0, 1, 0), corner(1, 1, 0), u),
+                v,
+            ),
+            lerp(
+                lerp(corner(0, 0, 1), corner(1, 0, 1), u),
+                lerp(corner(0, 1, 1), corner(1, 1, 1), u),
+                v,
+            ),
+            w,
+        )
+    }
+
+    fn simplex(&self, p: Float3) -> f64 {
+        const F3: f64 = 1.0 / 3.0;
+        const G3: f64 = 1.0 / 6.0;
+        // 単体の格子に歪めて、pを含む四面体を探す
+        let s = p.sum() * F3;
+        let cell = (p + float3::fill(s)).map(f64::floor);
+        let t = cell.sum() * G3;
+        let d0 = p - (cell - float3::fill(t));
+        // 四面体の頂点は座標の大きい軸の順に1ずつ進める
+        let mut order = [0, 1, 2];
+        order.sort_by(|&a, &b| d0[b].total_cmp(&d0[a]));
+        let mut offsets = [Float3::zeros(); 4];
+        for n in 1..4 {
+            offsets[n] = offsets[n - 1];
+            offsets[n][order[n - 1]] = 1.0;
+        }
+        let mut sum = 0.0;
+        for (n, offset) in offsets.iter().enumerate() {
+            let d = d0 - offset + float3::fill(n as f64 * G3);
+            let falloff = 0.6 - d.norm_squared();
+            if falloff > 0.0 {
+                let c = cell + offset;
+                let hash = self.hash(c.x as i64, c.y as i64, c.z as i64);
+                sum += falloff.powi(4) * self.gradient(hash, d);
+            }
+        }
+        // だいたい-1..1になるように
+        32.0 * sum
+    }
+
+    // セルごとに1つずつ置いた特徴点までの距離
+    pub fn worley(&self, p: Float3) -> WorleyPoint {
+        let cell = p.map(f64::floor);
+        let mut result = WorleyPoint {
+            f1: f64::MAX,
+            f2: f64::MAX,
+            cell: 0.0,
+        };
+        for dk in -1..=1 {
+            for dj in -1..=1 {
+                for di in -1..=1 {
+                    let c = cell + float3::new(di as f64, dj as f64, dk as f64);
+                    let h = self.hash(c.x as i64, c.y as i64, c.z as i64);
+                    // ハッシュをずらして軸ごとに別の乱数にする
+                    let jitter = |salt: usize| self.perm[h + salt] as f64 / 255.0;
+                    let feature = c + float3::new(jitter(0), jitter(1), jitter(2));
+                    let dist = (feature - p).norm();
+                    if dist < result.f1 {
+                        result.f2 = result.f1;
+                        result.f1 = dist;
+                        result.cell = jitter(3);
+                    } else if dist < result.f2 {
+                        result.f2 = dist;
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perlin_is_zero_at_lattice_points() {
+        for seed in 0..4 {
+            let noise = Noise::new(seed);
+            for p in [
+                Float3::zeros(),
+                float3::new(1.0, 2.0, 3.0),
+                float3::new(-7.0, 0.0, 12.0),
+                float3::new(300.0, -256.0, 5.0),
+            ] {
+                assert_eq!(noise.noise(p), 0.0);
+            }
+            // 格子点の間では0にならない点がある
+            assert!(noise.noise(float3::new(0.3, 0.6, 0.2)) != 0.0);
+        }
+    }
+
+    #[test]
+    fn fbm_is_deterministic() {
+        let fractal = Fractal::default();
+        let points = [
+            float3::new(0.3, 0.6, 0.2),
+            float3::new(-4.1, 2.7, 9.9),
+            float3::new(12.5, -0.4, 3.3),
+        ];
+        for basis in [NoiseBasis::Perlin, NoiseBasis::Simplex] {
+            let a = Noise::new(7).with_basis(basis);
+            let b = Noise::new(7).with_basis(basis);
+            let other = Noise::new(8).with_basis(basis);
+            for p in points {
+                assert_eq!(a.fbm(p, &fractal), b.fbm(p, &fractal));
+                assert!(a.fbm(p, &fractal).abs() <= 1.5);
+            }
+            // 種が違えば模様も違う
+            assert!(points
+                .iter()
+                .any(|p| a.fbm(*p, &fractal) != other.fbm(*p, &fractal)));
+        }
+    }
+}
@@ -0,0 +1,543 @@
+use crate::rayt::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Wavefront OBJ/MTL の読み込み
+// グループ(g/o)とマテリアル(usemtl)の組ごとに1つのTriangleMeshを作る
+pub struct ObjGroup {
+    pub name: String,
+    pub material_name: Option<String>,
+    pub mesh: TriangleMesh,
+}
+
+pub struct ObjModel {
+    pub groups: Vec<ObjGroup>,
+}
+
+impl ObjModel {
+    // mtllibで指定されたMTLファイルのマテリアルを使う
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        parse_obj(path.as_ref(), None)
+    }
+
+    // MTLを無視して全てのグループに同じマテリアルを使う
+    pub fn load_with_material(path: impl AsRef<Path>, material: Arc<dyn Material>) -> Result<Self> {
+        parse_obj(path.as_ref(), Some(material))
+    }
+
+    pub fn into_shape(self) -> Box<dyn Shape> {
+        let mut shapes = ShapeList::new();
+        for group in self.groups {
+            shapes.push(Box::new(group.mesh));
+        }
+        Box::new(shapes)
+    }
+}
+
+fn default_material() -> Arc<dyn Material> {
+    Arc::new(Lambertian::new(Box::new(ColorTexture::new(float3::fill(
+        0.8,
+    )))))
+}
+
+fn parse_error(path: &Path, line: usize, msg: impl std::fmt::Display) -> Error {
+    Error::InvalidScene(format!("{}:{}: {}", path.display(), line, msg))
+}
+
+fn parse_f64(token: Option<&str>, path: &Path, line: usize) -> Result<f64> {
+    let token = token.ok_or_else(|| parse_error(path, line, "missing number"))?;
+    token
+        .parse::<f64>()
+        .map_err(|_| parse_error(path, line, format!("invalid number {:?}", token)))
+}
+
+fn parse_float3<'a>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+    path: &Path,
+    line: usize,
+) -> Result<Float3> {
+    let x = parse_f64(tokens.next(), path, line)?;
+    let y = parse_f64(tokens.next(), path, line)?;
+    let z = parse_f64(tokens.next(), path, line)?;
+    Ok(float3::new(x, y, z))
+}
+
+// 1始まりの正のインデックスと、末尾からの負のインデックスを0始まりに直す
+fn resolve_index(token: &str, len: usize, path: &Path, line: usize) -> Result<usize> {
+    let i = token
+        .parse::<i64>()
+        .map_err(|_| parse_error(path, line, format!("invalid index {:?}", token)))?;
+    let resolved = if i > 0 { i - 1 } else { len as i64 + i };
+    if i == 0 || resolved < 0 || resolved >= len as i64 {
+        return Err(parse_error(
+            path,
+            line,
+            format!("index {} is out of range", i),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+type VertexKey = (usize, Option<usize>, Option<usize>);
+
+#[derive(Default)]
+struct MeshBuilder {
+    name: String,
+    material_name: Option<String>,
+    positions: Vec<Float3>,
+    normals: Vec<Option<Float3>>,
+    uvs: Vec<Option<(f64, f64)>>,
+    indices: Vec<[usize; 3]>,
+    vertices: HashMap<VertexKey, usize>,
+}
+
+impl MeshBuilder {
+    fn vertex(&mut self, key: VertexKey, data: &ObjData) -> usize {
+        if let Some(&i) = self.vertices.get(&key) {
+            return i;
+        }
+        let (p, t, n) = key;
+        let i = self.positions.len();
+        self.positions.push(data.positions[p]);
+        self.uvs.push(t.map(|t| data.uvs[t]));
+        self.normals.push(n.map(|n| data.normals[n]));
+        self.vertices.insert(key, i);
+        i
+    }
+
+    fn build(self, material: Arc<dyn Material>) -> Result<ObjGroup> {
+        // 一部の頂点だけに法線やuvがある場合は0で埋める (TriangleMesh側で面法線にフォールバックする)
+        let normals = if self.normals.iter().any(Option::is_some) {
+            self.normals
+                .iter()
+                .map(|n| n.unwrap_or_else(Float3::zeros))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let uvs = if self.uvs.iter().any(Option::is_some) {
+            self.uvs.iter().map(|t| t.unwrap_or((0.0, 0.0))).collect()
+        } else {
+            Vec::new()
+        };
+        Ok(ObjGroup {
+            name: self.name,
+            material_name: self.material_name,
+            mesh: TriangleMesh::new(self.positions, normals, uvs, self.indices, material)?,
+        })
+    }
+}
+
+#[derive(Default)]
+struct ObjData {
+    positions: Vec<Float3>,
+    normals: Vec<Float3>,
+    uvs: Vec<(f64, f64)>,
+}
+
+fn parse_obj(path: &Path, material: Option<Arc<dyn Material>>) -> Result<ObjModel> {
+    let source = fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut data = ObjData::default();
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut builders: Vec<MeshBuilder> = Vec::new();
+    let mut builder_index: HashMap<(String, Option<String>), usize> = HashMap::new();
+    let mut group = "default".to_string();
+    let mut material_name: Option<String> = None;
+
+    for (i, raw) in source.lines().enumerate() {
+        let line = i + 1;
+        let content = raw.split('#').next().unwrap_or("").trim();
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        match keyword {
+            "v" => data.positions.push(parse_float3(&mut tokens, path, line)?),
+            "vn" => data.normals.push(parse_float3(&mut tokens, path, line)?),
+            "vt" => {
+                let u = parse_f64(tokens.next(), path, line)?;
+                let v = tokens
+                    .next()
+                    .map_or(Ok(0.0), |t| parse_f64(Some(t), path, line))?;
+                // ImageTextureはv = 0が画像の上端なので反転する
+                data.uvs.push((u, 1.0 - v));
+            }
+            "g" | "o" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                group = if name.is_empty() {
+                    "default".to_string()
+                } else {
+                    name
+                };
+            }
+            "usemtl" => {
+                let name = tokens
+                    .next()
+                    .ok_or_else(|| parse_error(path, line, "usemtl without a name"))?;
+                if material.is_none() && !materials.contains_key(name) {
+                    return Err(parse_error(
+                        path,
+                        line,
+                        format!("unknown material {:?}", name),
+                    ));
+                }
+                material_name = Some(name.to_string());
+            }
+            "mtllib" if material.is_none() => {
+                for file in tokens {
+                    materials.extend(load_mtl(&dir.join(file))?);
+                }
+            }
+            "f" => {
+                let key = (group.clone(), material_name.clone());
+                let index = *builder_index.entry(key).or_insert_with(|| {
+                    builders.push(MeshBuilder {
+                        name: group.clone(),
+                        material_name: material_name.clone(),
+                        ..Default::default()
+                    });
+                    builders.len() - 1
+                });
+                let builder = &mut builders[index];
+
+                let mut polygon = Vec::new();
+                for token in tokens {
+                    let mut parts = token.split('/');
+                    let p = resolve_index(
+                        parts.next().unwrap_or(""),
+                        data.positions.len(),
+                        path,
+                        line,
+                    )?;
+                    let t = match parts.next() {
+                        Some("") | None => None,
+                        Some(t) => Some(resolve_index(t, data.uvs.len(), path, line)?),
+                    };
+                    let n = match parts.next() {
+                        Some("") | None => None,
+                        Some(n) => Some(resolve_index(n, data.normals.len(), path, line)?),
+                    };
+                    polygon.push(builder.vertex((p, t, n), &data));
+                }
+                if polygon.len() < 3 {
+                    return Err(parse_error(path, line, "face needs at least 3 vertices"));
+                }
+                let points: Vec<Float3> = polygon.iter().map(|&i| builder.positions[i]).collect();
+                for [a, b, c] in triangulate(&points) {
+                    builder.indices.push([polygon[a], polygon[b], polygon[c]]);
+                }
+            }
+            // スムージンググループ, 線, 点などは無視する
+            _ => {}
+        }
+    }
+
+    let groups = builders
+        .into_iter()
+        .map(|b| {
+            let m = match (&material, &b.material_name) {
+                (Some(m), _) => Arc::clone(m),
+                (None, Some(name)) => Arc::clone(&materials[name]),
+                (None, None) => default_material(),
+            };
+            b.build(m)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(ObjModel { groups })
+}
+
+// 多角形を耳切り法で三角形に分割する
+// 戻り値はpointsへのインデックスで、元の頂点の回り順を保つ
+pub fn triangulate(points: &[Float3]) -> Vec<[usize; 3]> {
+    let n = points.len();
+    if n < 3 {
+        return Vec::new();
+    }
+    if n == 3 {
+        return vec![[0, 1, 2]];
+    }
+    let fan = || (1..n - 1).map(|i| [0, i, i + 1]).collect::<Vec<_>>();
+
+    // Newellの方法で多角形の法線を求め、一番大きい軸を落として2次元に投影する
+    let mut normal = Float3::zeros();
+    for i in 0..n {
+        let a = points[i];
+        let b = points[(i + 1) % n];
+        normal += float3::new(
+            (a.y - b.y) * (a.z + b.z),
+            (a.z - b.z) * (a.x + b.x),
+            (a.x - b.x) * (a.y + b.y),
+        );
+    }
+    if normal.norm_squared() < EPS * EPS {
+        return fan();
+    }
+    let (ax, ay) = match normal.iamax() {
+        0 => (1, 2),
+        1 => (2, 0),
+        _ => (0, 1),
+    };
+    let sign = normal[normal.iamax()].signum();
+    let p2: Vec<(f64, f64)> = points.iter().map(|p| (p[ax], p[ay] * sign)).collect();
+    let cross = |o: usize, a: usize, b: usize| {
+        (p2[a].0 - p2[o].0) * (p2[b].1 - p2[o].1) - (p2[a].1 - p2[o].1) * (p2[b].0 - p2[o].0)
+    };
+    let inside = |p: usize, a: usize, b: usize, c: usize| {
+        cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+    };
+
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut triangles = Vec::with_capacity(n - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let a = remaining[(i + m - 1) % m];
+            let b = remaining[i];
+            let c = remaining[(i + 1) % m];
+            cross(a, b, c) > 0.0
+                && remaining
+                    .iter()
+                    .all(|&p| p == a || p == b || p == c || !inside(p, a, b, c))
+        });
+        match ear {
+            Some(i) => {
+                triangles.push([
+                    remaining[(i + m - 1) % m],
+                    remaining[i],
+                    remaining[(i + 1) % m],
+                ]);
+                remaining.remove(i);
+            }
+            // 自己交差などで耳が見つからなければ残りを扇形に分割する
+            None => {
+                for i in 1..m - 1 {
+                    triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+                }
+                return triangles;
+            }
+        }
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
+#[derive(Default)]
+struct MtlEntry {
+    kd: Option<Float3>,
+    ks: Float3,
+    ke: Float3,
+    ns: f64,
+    ni: Option<f64>,
+    d: f64,
+    illum: Option<u32>,
+    map_kd: Option<PathBuf>,
+}
+
+impl MtlEntry {
+    fn new() -> Self {
+        Self {
+            d: 1.0,
+            ..Default::default()
+        }
+    }
+
+    // MTLのパラメータを既存のマテリアルに割り当てる
+    // Ke -> DiffuseLight, 透過 -> Dielectric, illum 3/5/8 -> Metal, それ以外 -> Lambertian
+    fn to_material(&self) -> Result<Arc<dyn Material>> {
+        let albedo = || -> Result<Box<dyn Texture>> {
+            Ok(match &self.map_kd {
+                Some(path) => Box::new(ImageTexture::new(&path.to_string_lossy(), (1.0, 1.0))?),
+                None => Box::new(ColorTexture::new(
+                    self.kd.unwrap_or_else(|| float3::fill(0.8)),
+                )),
+            })
+        };
+        if self.ke.max() > 0.0 {
+            let intensity = self.ke.max();
+            return Ok(Arc::new(DiffuseLight::new(
+                Box::new(ColorTexture::new(self.ke / intensity)),
+                intensity,
+            )));
+        }
+        let transparent = self.d < 1.0 || matches!(self.illum, Some(4 | 6 | 7 | 9));
+        if transparent {
+            return Ok(Arc::new(Dielectric::new(self.ni.unwrap_or(1.5))));
+        }
+        if matches!(self.illum, Some(3 | 5 | 8)) {
+            let fuzz = (1.0 - self.ns / 1000.0).clamp(0.0, 1.0);
+            let texture: Box<dyn Texture> = if self.ks.max() > 0.0 {
+                Box::new(ColorTexture::new(self.ks))
+            } else {
+                albedo()?
+            };
+            return Ok(Arc::new(Metal::new(texture, fuzz)));
+        }
+        Ok(Arc::new(Lambertian::new(albedo()?)))
+    }
+}
+
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, Arc<dyn Material>>> {
+    let source = fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut entries: Vec<(String, MtlEntry)> = Vec::new();
+
+    for (i, raw) in source.lines().enumerate() {
+        let line = i + 1;
+        let content = raw.split('#').next().unwrap_or("").trim();
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            entries.push((name, MtlEntry::new()));
+            continue;
+        }
+        let (_, entry) = match entries.last_mut() {
+            Some(e) => e,
+            None => {
+                return Err(parse_error(
+                    path,
+                    line,
+                    format!("{} before newmtl", keyword),
+                ))
+            }
+        };
+        match keyword {
+            "Kd" => entry.kd = Some(parse_float3(&mut tokens, path, line)?),
+            "Ks" => entry.ks = parse_float3(&mut tokens, path, line)?,
+            "Ke" => entry.ke = parse_float3(&mut tokens, path, line)?,
+            "Ns" => entry.ns = parse_f64(tokens.next(), path, line)?,
+            "Ni" => entry.ni = Some(parse_f64(tokens.next(), path, line)?),
+            "d" => entry.d = parse_f64(tokens.next(), path, line)?,
+            "Tr" => entry.d = 1.0 - parse_f64(tokens.next(), path, line)?,
+            "illum" => entry.illum = Some(parse_f64(tokens.next(), path, line)? as u32),
+            "map_Kd" => {
+                // オプション(-s 等)は無視してファイル名だけ使う
+                let file = tokens
+                    .last()
+                    .ok_or_else(|| parse_error(path, line, "map_Kd without a file"))?;
+                entry.map_kd = Some(dir.join(file));
+            }
+            _ => {}
+        }
+    }
+
+    entries
+        .into_iter()
+        .map(|(name, entry)| Ok((name, entry.to_material()?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // テストごとに別のファイルに書く
+    fn write_obj(name: &str, source: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ayanami-obj-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("model.obj");
+        fs::write(&path, source).unwrap();
+        path
+    }
+
+    fn area(points: &[Float3], triangles: &[[usize; 3]]) -> f64 {
+        triangles
+            .iter()
+            .map(|&[a, b, c]| (points[b] - points[a]).cross(&(points[c] - points[a])).z * 0.5)
+            .sum()
+    }
+
+    #[test]
+    fn triangulates_concave_polygon() {
+        // L字型. 面積は3
+        let points: Vec<Float3> = [
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+        ]
+        .iter()
+        .map(|&(x, y)| float3::new(x, y, 0.0))
+        .collect();
+        let triangles = triangulate(&points);
+        assert_eq!(triangles.len(), 4);
+        // 回り順が保たれ、重なりもはみ出しも無ければ符号付き面積の和が一致する
+        for t in &triangles {
+            assert!(area(&points, &[*t]) > 0.0, "{:?}", t);
+        }
+        assert!((area(&points, &triangles) - 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn triangulates_clockwise_polygon() {
+        // 時計回りの凹四角形
+        let points: Vec<Float3> = [(0.0, 0.0), (1.0, 3.0), (2.0, 0.0), (1.0, 1.0)]
+            .iter()
+            .map(|&(x, y)| float3::new(x, y, 0.0))
+            .collect();
+        let triangles = triangulate(&points);
+        assert_eq!(triangles.len(), 2);
+        for t in &triangles {
+            assert!(area(&points, &[*t]) < 0.0, "{:?}", t);
+        }
+        assert!((area(&points, &triangles) + 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn resolves_negative_indices() {
+        let path = write_obj(
+            "negative",
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 5 5 5\nv 6 5 5\nv 5 6 5\nf -3 -2 -1\n",
+        );
+        let model = ObjModel::load(&path).unwrap();
+        let mesh = &model.groups[0].mesh;
+        assert_eq!(mesh.indices().len(), 1);
+        let [a, b, c] = mesh.indices()[0];
+        let positions = mesh.positions();
+        assert_eq!(positions[a], float3::new(5.0, 5.0, 5.0));
+        assert_eq!(positions[b], float3::new(6.0, 5.0, 5.0));
+        assert_eq!(positions[c], float3::new(5.0, 6.0, 5.0));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rejects_out_of_range_index() {
+        let path = write_obj("range", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 -4\n");
+        assert!(matches!(ObjModel::load(&path), Err(Error::InvalidScene(_))));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_usemtl() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl missing\nf 1 2 3\n";
+        let path = write_obj("usemtl", source);
+        match ObjModel::load(&path) {
+            Err(Error::InvalidScene(msg)) => assert!(msg.contains("missing"), "{}", msg),
+            _ => panic!("unknown material was accepted"),
+        }
+        // マテリアルを指定して読むときはusemtlを無視する
+        let material = default_material();
+        let model = ObjModel::load_with_material(&path, material).unwrap();
+        assert_eq!(model.groups[0].material_name.as_deref(), Some("missing"));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn reports_missing_mtllib() {
+        let source = "mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+        let path = write_obj("mtllib", source);
+        assert!(matches!(ObjModel::load(&path), Err(Error::Io(_))));
+        // マテリアルを指定すればMTLを読まない
+        assert!(ObjModel::load_with_material(&path, default_material()).is_ok());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
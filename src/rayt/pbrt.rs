@@ -0,0 +1,1024 @@
+use crate::rayt::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// pbrt-v4 形式のシーンの読み込み
+// 対応しているのは perspectiveカメラ, sphere/trianglemesh/bilinearmesh/plymesh,
+// diffuse/conductor/dielectric マテリアル, diffuse エリアライト, 一様なinfiniteライト
+// それ以外はできるだけ近似し、ImportedScene::warningsに記録する
+pub fn load_pbrt(path: impl AsRef<Path>) -> Result<ImportedScene> {
+    let path = path.as_ref();
+    let base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut loader = PbrtLoader::new(base_dir);
+    loader.include(path)?;
+    Ok(loader.finish())
+}
+
+fn parse_error(path: &Path, line: usize, msg: impl std::fmt::Display) -> Error {
+    Error::InvalidScene(format!("{}:{}: {}", path.display(), line, msg))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Open,
+    Close,
+}
+
+// (トークン, 行番号) の列にする. #から行末まではコメント
+fn tokenize(text: &str, path: &Path) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '[' => {
+                tokens.push((Token::Open, line));
+                chars.next();
+            }
+            ']' => {
+                tokens.push((Token::Close, line));
+                chars.next();
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => s.extend(chars.next()),
+                        Some('\n') | None => {
+                            return Err(parse_error(path, line, "unterminated string"))
+                        }
+                        Some(c) => s.push(c),
+                    }
+                }
+                tokens.push((Token::Str(s), line));
+            }
+            _ => {
+                let mut s = String::new();
+                while let Some(c) =
+                    chars.next_if(|&c| !c.is_whitespace() && !matches!(c, '[' | ']' | '"' | '#'))
+                {
+                    s.push(c);
+                }
+                let token =
+                    if c.is_ascii_alphabetic() {
+                        Token::Ident(s)
+                    } else {
+                        Token::Num(s.parse().map_err(|_| {
+                            parse_error(path, line, format!("invalid number {:?}", s))
+                        })?)
+                    };
+                tokens.push((token, line));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Num(f64),
+    // boolもtrue/falseの文字列として持つ
+    Str(String),
+}
+
+// "型 名前" 値 の組
+#[derive(Debug, Clone)]
+struct Param {
+    ty: String,
+    name: String,
+    values: Vec<Value>,
+}
+
+impl Param {
+    fn floats(&self) -> Vec<f64> {
+        self.values
+            .iter()
+            .filter_map(|v| match v {
+                Value::Num(x) => Some(*x),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn string(&self) -> Option<&str> {
+        self.values.iter().find_map(|v| match v {
+            Value::Str(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    fn strings(&self) -> Vec<&str> {
+        self.values
+            .iter()
+            .filter_map(|v| match v {
+                Value::Str(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+struct Directive {
+    name: String,
+    line: usize,
+    numbers: Vec<f64>,
+    strings: Vec<String>,
+    params: Vec<Param>,
+}
+
+impl Directive {
+    fn param(&self, name: &str) -> Option<&Param> {
+        self.params.iter().find(|p| p.name == name)
+    }
+
+    fn float(&self, name: &str, default: f64) -> f64 {
+        self.param(name)
+            .and_then(|p| p.floats().first().copied())
+            .unwrap_or(default)
+    }
+
+    fn floats(&self, name: &str) -> Vec<f64> {
+        self.param(name).map(Param::floats).unwrap_or_default()
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        self.param(name).and_then(Param::string)
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.param(name).is_some()
+    }
+}
+
+// 引数の前に付く文字列の数 (Shape "sphere" など)
+fn leading_strings(directive: &str) -> usize {
+    match directive {
+        "Texture" => 3,
+        "MediumInterface" => 2,
+        "Option" | "AttributeBegin" | "AttributeEnd" | "WorldBegin" | "WorldEnd"
+        | "TransformBegin" | "TransformEnd" | "ObjectEnd" | "ReverseOrientation" | "Identity"
+        | "Translate" | "Scale" | "Rotate" | "LookAt" | "Transform" | "ConcatTransform"
+        | "TransformTimes" | "ActiveTransform" => 0,
+        _ => 1,
+    }
+}
+
+fn parse_directives(tokens: &[(Token, usize)], path: &Path) -> Result<Vec<Directive>> {
+    let mut directives = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let (token, line) = &tokens[i];
+        let line = *line;
+        let name = match token {
+            Token::Ident(name) => name.clone(),
+            t => return Err(parse_error(path, line, format!("unexpected {:?}", t))),
+        };
+        i += 1;
+        let mut directive = Directive {
+            name,
+            line,
+            numbers: Vec::new(),
+            strings: Vec::new(),
+            params: Vec::new(),
+        };
+        if directive.name == "ActiveTransform" {
+            if let Some((Token::Ident(s), _)) = tokens.get(i) {
+                directive.strings.push(s.clone());
+                i += 1;
+            }
+        }
+        // 位置引数の数値 (配列の括弧は無視する)
+        while let Some((t @ (Token::Num(_) | Token::Open | Token::Close), _)) = tokens.get(i) {
+            if let Token::Num(x) = t {
+                directive.numbers.push(*x);
+            }
+            i += 1;
+        }
+        for _ in 0..leading_strings(&directive.name) {
+            match tokens.get(i) {
+                Some((Token::Str(s), _)) => {
+                    directive.strings.push(s.clone());
+                    i += 1;
+                }
+                _ => break,
+            }
+        }
+        // パラメータリスト
+        while let Some((Token::Str(decl), line)) = tokens.get(i) {
+            let mut words = decl.split_whitespace();
+            let (ty, name) = match (words.next(), words.next()) {
+                (Some(ty), Some(name)) => (ty.to_string(), name.to_string()),
+                _ => {
+                    return Err(parse_error(
+                        path,
+                        *line,
+                        format!("invalid parameter declaration {:?}", decl),
+                    ))
+                }
+            };
+            i += 1;
+            let value = |t: &Token| match t {
+                Token::Num(x) => Some(Value::Num(*x)),
+                Token::Str(s) => Some(Value::Str(s.clone())),
+                Token::Ident(s) if s == "true" || s == "false" => Some(Value::Str(s.clone())),
+                _ => None,
+            };
+            let mut values = Vec::new();
+            match tokens.get(i) {
+                Some((Token::Open, _)) => {
+                    i += 1;
+                    while let Some((t, line)) = tokens.get(i) {
+                        i += 1;
+                        if *t == Token::Close {
+                            break;
+                        }
+                        values.push(value(t).ok_or_else(|| {
+                            parse_error(path, *line, format!("unexpected {:?} in {:?}", t, decl))
+                        })?);
+                    }
+                }
+                Some((t, line)) => {
+                    values.push(value(t).ok_or_else(|| {
+                        parse_error(path, *line, format!("missing value for {:?}", decl))
+                    })?);
+                    i += 1;
+                }
+                None => {
+                    return Err(parse_error(
+                        path,
+                        *line,
+                        format!("missing value for {:?}", decl),
+                    ))
+                }
+            }
+            directive.params.push(Param { ty, name, values });
+        }
+        directives.push(directive);
+    }
+    Ok(directives)
+}
+
+// AttributeBegin/AttributeEndで保存される状態
+#[derive(Clone)]
+struct GraphicsState {
+    ctm: Mat4,
+    // Noneはinterface (形状を描画しない)
+    material: Option<Arc<dyn Material>>,
+    area_light: Option<Arc<dyn Material>>,
+}
+
+struct PbrtLoader {
+    base_dir: PathBuf,
+    state: GraphicsState,
+    attribute_stack: Vec<GraphicsState>,
+    transform_stack: Vec<Mat4>,
+    coordinate_systems: HashMap<String, Mat4>,
+    named_materials: HashMap<String, Option<Arc<dyn Material>>>,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    // ObjectBegin中は形状を読み飛ばす
+    in_object: bool,
+    camera_to_world: Mat4,
+    fov: f64,
+    lens_radius: f64,
+    focus_distance: f64,
+    film: Film,
+    background: Float3,
+    environment: Option<EnvironmentLight>,
+    max_depth: Option<usize>,
+    world: ShapeList,
+    warnings: Vec<String>,
+}
+
+fn lambertian(color: Float3) -> Arc<dyn Material> {
+    Arc::new(Lambertian::new(Box::new(ColorTexture::new(color))))
+}
+
+// 名前付きのガラスの屈折率 (d線)
+fn named_glass(name: &str) -> Option<f64> {
+    Some(match name {
+        "glass-BK7" => 1.5168,
+        "glass-BAF10" => 1.6700,
+        "glass-FK51A" => 1.4866,
+        "glass-LASF9" => 1.8503,
+        "glass-F5" => 1.6034,
+        "glass-F10" => 1.6200,
+        "glass-F11" => 1.6209,
+        _ => return None,
+    })
+}
+
+impl PbrtLoader {
+    fn new(base_dir: PathBuf) -> Self {
+        Self {
+            base_dir,
+            state: GraphicsState {
+                ctm: Mat4::identity(),
+                material: Some(lambertian(float3::fill(0.5))),
+                area_light: None,
+            },
+            attribute_stack: Vec::new(),
+            transform_stack: Vec::new(),
+            coordinate_systems: HashMap::new(),
+            named_materials: HashMap::new(),
+            textures: HashMap::new(),
+            in_object: false,
+            camera_to_world: Mat4::identity(),
+            fov: 90.0,
+            lens_radius: 0.0,
+            focus_distance: 1e6,
+            film: Film {
+                width: 1280,
+                height: 720,
+                spp: 16,
+                filename: None,
+            },
+            background: Float3::zeros(),
+            environment: None,
+            max_depth: None,
+            world: ShapeList::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn warn(&mut self, msg: String) {
+        if !self.warnings.contains(&msg) {
+            self.warnings.push(msg);
+        }
+    }
+
+    fn finish(self) -> ImportedScene {
+        let camera_params = CameraParams {
+            lens_radius: self.lens_radius,
+            focus_distance: Some(self.focus_distance),
+            ..camera_from_matrix(
+                &self.camera_to_world,
+                1.0,
+                self.fov,
+                FovAxis::Smaller,
+                self.film.width,
+                self.film.height,
+            )
+        };
+        ImportedScene {
+            world: self.world,
+            camera_params,
+            film: self.film,
+            background: self.background,
+            environment: self.environment,
+            max_depth: self.max_depth,
+            warnings: self.warnings,
+        }
+    }
+
+    fn include(&mut self, path: &Path) -> Result<()> {
+        let text = fs::read_to_string(path)?;
+        let tokens = tokenize(&text, path)?;
+        for directive in parse_directives(&tokens, path)? {
+            self.directive(&directive, path)?;
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, d: &Directive, path: &Path) -> Result<()> {
+        let error = |msg: String| parse_error(path, d.line, msg);
+        let name = || d.strings.first().cloned().unwrap_or_default();
+        match d.name.as_str() {
+            "Identity" => self.state.ctm = Mat4::identity(),
+            "Translate" | "Scale" | "Rotate" | "LookAt" | "Transform" | "ConcatTransform" => {
+                let m = transform_matrix(&d.name, &d.numbers)
+                    .ok_or_else(|| error(format!("invalid arguments for {}", d.name)))?;
+                if d.name == "Transform" {
+                    self.state.ctm = m;
+                } else {
+                    self.state.ctm *= m;
+                }
+            }
+            "CoordinateSystem" => {
+                self.coordinate_systems.insert(name(), self.state.ctm);
+            }
+            "CoordSysTransform" => match self.coordinate_systems.get(&name()) {
+                Some(m) => self.state.ctm = *m,
+                None => self.warn(format!("unknown coordinate system {:?}", name())),
+            },
+            "TransformBegin" => self.transform_stack.push(self.state.ctm),
+            "TransformEnd" => {
+                self.state.ctm = self
+                    .transform_stack
+                    .pop()
+                    .ok_or_else(|| error("unmatched TransformEnd".to_string()))?;
+            }
+            "AttributeBegin" => self.attribute_stack.push(self.state.clone()),
+            "AttributeEnd" => {
+                self.state = self
+                    .attribute_stack
+                    .pop()
+                    .ok_or_else(|| error("unmatched AttributeEnd".to_string()))?;
+            }
+            "WorldBegin" => {
+                self.state.ctm = Mat4::identity();
+                self.coordinate_systems
+                    .insert("world".to_string(), Mat4::identity());
+            }
+            "Camera" => {
+                // CTMはカメラ空間からワールドへの逆行列
+                self.camera_to_world = self
+                    .state
+                    .ctm
+                    .try_inverse()
+                    .ok_or_else(|| error("camera transform is not invertible".to_string()))?;
+                self.coordinate_systems
+                    .insert("camera".to_string(), self.camera_to_world);
+                if name() != "perspective" {
+                    self.warn(format!(
+                        "camera {:?} is not supported; using perspective",
+                        name()
+                    ));
+                }
+                self.fov = d.float("fov", 90.0);
+                self.lens_radius = d.float("lensradius", 0.0);
+                self.focus_distance = d.float("focaldistance", 1e6);
+                if !(self.focus_distance.is_finite() && self.focus_distance > 0.0) {
+                    self.warn(format!(
+                        "focaldistance {} is not positive; using the default",
+                        self.focus_distance
+                    ));
+                    self.focus_distance = 1e6;
+                }
+                if d.has("screenwindow") || d.has("frameaspectratio") {
+                    self.warn("screenwindow and frameaspectratio are ignored".to_string());
+                }
+            }
+            "Film" => {
+                self.film.width = d.float("xresolution", 1280.0) as u32;
+                self.film.height = d.float("yresolution", 720.0) as u32;
+                self.film.filename = d.string("filename").map(str::to_string);
+                if d.has("cropwindow") || d.has("pixelbounds") {
+                    self.warn("film crop window is ignored".to_string());
+                }
+            }
+            "Sampler" => self.film.spp = d.float("pixelsamples", 16.0) as usize,
+            "Integrator" => self.max_depth = Some(d.float("maxdepth", 5.0) as usize),
+            "Include" | "Import" => {
+                let file = self.base_dir.join(name());
+                self.include(&file)?;
+            }
+            "Texture" => self.texture(d)?,
+            "Material" => self.state.material = self.material(&name(), d),
+            "MakeNamedMaterial" => {
+                let ty = d.string("type").unwrap_or("").to_string();
+                let material = self.material(&ty, d);
+                self.named_materials.insert(name(), material);
+            }
+            "NamedMaterial" => match self.named_materials.get(&name()) {
+                Some(m) => self.state.material = m.clone(),
+                None => return Err(error(format!("unknown material {:?}", name()))),
+            },
+            "AreaLightSource" => {
+                if name() != "diffuse" {
+                    self.warn(format!("area light {:?} is not supported", name()));
+                }
+                if d.has("filename") || d.has("power") {
+                    self.warn("area light filename and power are ignored".to_string());
+                }
+                let l = self.spectrum(d.param("L"), float3::one());
+                self.state.area_light = Some(Arc::new(DiffuseLight::new(
+                    Box::new(ColorTexture::new(l)),
+                    d.float("scale", 1.0),
+                )));
+            }
+            "LightSource" => {
+                if name() == "infinite" && d.has("filename") {
+                    self.environment_map(d)?;
+                } else if name() == "infinite" {
+                    let l = self.spectrum(d.param("L"), float3::one());
+                    self.background += l * d.float("scale", 1.0);
+                } else {
+                    self.warn(format!("light source {:?} is not supported", name()));
+                }
+            }
+            "Shape" => {
+                if self.in_object {
+                    return Ok(());
+                }
+                let material = match (&self.state.area_light, &self.state.material) {
+                    (Some(light), _) => Arc::clone(light),
+                    (None, Some(material)) => Arc::clone(material),
+                    (None, None) => return Ok(()),
+                };
+                self.shape(&name(), d, material).map_err(|e| match e {
+                    Error::InvalidScene(msg) => error(msg),
+                    e => e,
+                })?;
+            }
+            "ObjectBegin" => {
+                self.warn("object instancing is not supported; instances were skipped".to_string());
+                self.attribute_stack.push(self.state.clone());
+                self.in_object = true;
+            }
+            "ObjectEnd" => {
+                self.in_object = false;
+                self.state = self
+                    .attribute_stack
+                    .pop()
+                    .ok_or_else(|| error("unmatched ObjectEnd".to_string()))?;
+            }
+            "ObjectInstance" => {}
+            // 画像に影響しないもの
+            "WorldEnd" | "Accelerator" | "Option" | "ColorSpace" | "ReverseOrientation" => {}
+            _ => self.warn(format!("{} is not supported and was ignored", d.name)),
+        }
+        Ok(())
+    }
+
+    // 色の値. 分光分布は近似する
+    fn spectrum(&mut self, param: Option<&Param>, default: Float3) -> Float3 {
+        let param = match param {
+            Some(p) => p,
+            None => return default,
+        };
+        let values = param.floats();
+        match param.ty.as_str() {
+            "rgb" | "color" if values.len() >= 3 => float3::new(values[0], values[1], values[2]),
+            "float" if !values.is_empty() => float3::fill(values[0]),
+            "spectrum" if values.len() >= 2 => {
+                self.warn(format!(
+                    "spectrum {:?} was approximated by its average",
+                    param.name
+                ));
+                let samples: Vec<f64> = values.iter().skip(1).step_by(2).copied().collect();
+                float3::fill(samples.iter().sum::<f64>() / samples.len() as f64)
+            }
+            _ => {
+                self.warn(format!(
+                    "{} {:?} is not supported and was replaced with a constant",
+                    param.ty, param.name
+                ));
+                default
+            }
+        }
+    }
+
+    // 色またはテクスチャ
+    fn color_texture(&mut self, d: &Directive, name: &str, default: Float3) -> Box<dyn Texture> {
+        if let Some(param) = d.param(name).filter(|p| p.ty == "texture") {
+            let texture_name = param.string().unwrap_or("");
+            if let Some(texture) = self.textures.get(texture_name) {
+                return Box::new(Arc::clone(texture));
+            }
+            self.warn(format!("unknown texture {:?}", texture_name));
+            return Box::new(ColorTexture::new(default));
+        }
+        Box::new(ColorTexture::new(self.spectrum(d.param(name), default)))
+    }
+
+    // infiniteのfilename. pbrt-v3の緯度経度の画像 (zが上) として読む
+    fn environment_map(&mut self, d: &Directive) -> Result<()> {
+        let file = self.base_dir.join(d.string("filename").unwrap_or(""));
+        let texture = ImageTexture::new(&file.to_string_lossy(), (1.0, 1.0))?;
+        let (width, height) = texture.size();
+        if width == height {
+            self.warn("equal-area environment maps are not supported and were ignored".to_string());
+            return Ok(());
+        }
+        // pbrtの経度は逆回りなので左右反転し, zが上になるように向ける
+        let z_up = Quat::from_matrix(&na::Matrix3::new(
+            0.0, 0.0, 1.0, //
+            1.0, 0.0, 0.0, //
+            0.0, 1.0, 0.0,
+        ));
+        let l = self.spectrum(d.param("L"), float3::one());
+        if l != float3::one() {
+            self.warn("infinite light L was ignored for an environment map".to_string());
+        }
+        self.environment = Some(
+            EnvironmentLight::from_texture(texture)
+                .with_flip(true)
+                .with_rotation(rotation_of(&self.state.ctm) * z_up)
+                .with_intensity(d.float("scale", 1.0)),
+        );
+        Ok(())
+    }
+
+    fn texture(&mut self, d: &Directive) -> Result<()> {
+        let name = d.strings.first().cloned().unwrap_or_default();
+        let class = d.strings.get(2).map(String::as_str).unwrap_or("");
+        let texture: Arc<dyn Texture> = match class {
+            "imagemap" => {
+                let file = self.base_dir.join(d.string("filename").unwrap_or(""));
+                let scale = (d.float("uscale", 1.0), d.float("vscale", 1.0));
+                // pbrt-v3の"gamma"とv4の"encoding"
+                let color_space = match (d.string("gamma"), d.string("encoding")) {
+                    (Some("false"), _) | (_, Some("linear")) => ColorSpace::Linear,
+                    _ => ColorSpace::Srgb,
+                };
+                // pbrt-v4の"filter"とpbrt-v3の"trilinear". v3の既定はEWA
+                let filter = match (d.string("filter"), d.string("trilinear")) {
+                    (Some("point"), _) => TextureFilter::Nearest,
+                    (Some("bilinear"), _) => TextureFilter::Bilinear,
+                    (Some("trilinear"), _) | (None, Some("true")) => TextureFilter::Trilinear,
+                    _ => TextureFilter::Ewa,
+                };
+                let wrap = match d.string("wrap") {
+                    Some("clamp") => WrapMode::Clamp,
+                    Some("black") => {
+                        self.warn("imagemap wrap \"black\" was replaced with clamp".to_string());
+                        WrapMode::Clamp
+                    }
+                    _ => WrapMode::Repeat,
+                };
+                Arc::new(
+                    ImageTexture::open(&file.to_string_lossy(), scale, color_space)?
+                        .with_filter(filter)
+                        .with_wrap(wrap),
+                )
+            }
+            "fbm" | "wrinkled" | "marble" => {
+                let fractal = Fractal {
+                    octaves: d.float("octaves", 8.0).max(1.0) as u32,
+                    lacunarity: 2.0,
+                    gain: d.float("roughness", 0.5),
+                };
+                let texture = match class {
+                    "fbm" => NoiseTexture::fbm(1.0),
+                    "wrinkled" => NoiseTexture::turbulence(1.0),
+                    _ => {
+                        self.warn("marble texture was approximated".to_string());
+                        let distortion = d.float("variation", 0.2) * 10.0;
+                        NoiseTexture::new(
+                            NoisePattern::Marble { distortion },
+                            d.float("scale", 1.0),
+                        )
+                    }
+                };
+                Arc::new(texture.with_fractal(fractal))
+            }
+            "constant" => {
+                let value = self.spectrum(d.param("value"), float3::one());
+                Arc::new(ColorTexture::new(value))
+            }
+            "scale" => {
+                let scale = self.spectrum(d.param("scale"), float3::one());
+                let tex = self.spectrum(d.param("tex"), float3::one());
+                self.warn("scale texture was approximated by a constant".to_string());
+                Arc::new(ColorTexture::new(scale.component_mul(&tex)))
+            }
+            _ => {
+                self.warn(format!(
+                    "texture {:?} is not supported and was replaced with a constant",
+                    class
+                ));
+                Arc::new(ColorTexture::new(float3::fill(0.5)))
+            }
+        };
+        self.textures.insert(name, texture);
+        Ok(())
+    }
+
+    fn material(&mut self, ty: &str, d: &Directive) -> Option<Arc<dyn Material>> {
+        let roughness = || {
+            let r = d.float("roughness", 0.0);
+            let ur = d.float("uroughness", r);
+            let vr = d.float("vroughness", r);
+            ((ur + vr) * 0.5).clamp(0.0, 1.0)
+        };
+        Some(match ty {
+            "" | "interface" | "none" => return None,
+            "diffuse" | "matte" => Arc::new(Lambertian::new(self.color_texture(
+                d,
+                if d.has("Kd") { "Kd" } else { "reflectance" },
+                float3::fill(0.5),
+            ))),
+            "conductor" | "metal" => {
+                let albedo = if d.has("reflectance") {
+                    self.color_texture(d, "reflectance", float3::one())
+                } else {
+                    Box::new(ColorTexture::new(self.conductor(d)))
+                };
+                Arc::new(Metal::new(albedo, roughness()))
+            }
+            "dielectric" | "thindielectric" | "glass" => {
+                if ty == "thindielectric" {
+                    self.warn("thindielectric was approximated as dielectric".to_string());
+                }
+                if roughness() > 0.0 {
+                    self.warn("rough dielectrics were rendered as smooth".to_string());
+                }
+                let eta = match d.param("eta").or_else(|| d.param("index")) {
+                    Some(p) if p.ty == "spectrum" && p.string().is_some() => {
+                        let name = p.string().unwrap_or("");
+                        named_glass(name).unwrap_or_else(|| {
+                            self.warn(format!("unknown spectrum {:?}; using eta 1.5", name));
+                            1.5
+                        })
+                    }
+                    Some(p) => p.floats().first().copied().unwrap_or(1.5),
+                    None => 1.5,
+                };
+                Arc::new(Dielectric::new(eta))
+            }
+            "coateddiffuse" | "plastic" | "diffusetransmission" | "substrate" => {
+                self.warn(format!("material {:?} was approximated as diffuse", ty));
+                let name = if d.has("Kd") { "Kd" } else { "reflectance" };
+                Arc::new(Lambertian::new(self.color_texture(
+                    d,
+                    name,
+                    float3::fill(0.5),
+                )))
+            }
+            "coatedconductor" => {
+                self.warn("coatedconductor was approximated as conductor".to_string());
+                Arc::new(Metal::new(
+                    Box::new(ColorTexture::new(self.conductor(d))),
+                    roughness(),
+                ))
+            }
+            "mix" => {
+                self.warn("mix material was replaced with its first material".to_string());
+                let first = d
+                    .param("materials")
+                    .and_then(|p| p.strings().first().map(|s| s.to_string()));
+                match first.and_then(|name| self.named_materials.get(&name).cloned()) {
+                    Some(m) => return m,
+                    None => lambertian(float3::fill(0.5)),
+                }
+            }
+            _ => {
+                self.warn(format!("material {:?} was approximated as diffuse", ty));
+                lambertian(float3::fill(0.5))
+            }
+        })
+    }
+
+    // 導体の反射率. 既定は銅
+    fn conductor(&mut self, d: &Directive) -> Float3 {
+        let eta = d.param("eta").or_else(|| d.param("conductor.eta"));
+        let k = d.param("k").or_else(|| d.param("conductor.k"));
+        match (eta, k) {
+            (Some(eta), Some(k)) if eta.ty == "rgb" && k.ty == "rgb" => {
+                let eta = self.spectrum(Some(eta), float3::one());
+                let k = self.spectrum(Some(k), Float3::zeros());
+                conductor_reflectance(eta, k)
+            }
+            (Some(eta), _) if eta.string().is_some() => {
+                // "metal-Au-eta" のような名前
+                let name = eta.string().unwrap_or("");
+                let metal = name.split('-').nth(1).unwrap_or("");
+                named_metal(metal).unwrap_or_else(|| {
+                    self.warn(format!("unknown spectrum {:?}; using copper", name));
+                    named_metal("Cu").unwrap_or_else(float3::one)
+                })
+            }
+            (None, None) => named_metal("Cu").unwrap_or_else(float3::one),
+            _ => {
+                self.warn("conductor eta/k were approximated by copper".to_string());
+                named_metal("Cu").unwrap_or_else(float3::one)
+            }
+        }
+    }
+
+    fn shape(&mut self, ty: &str, d: &Directive, material: Arc<dyn Material>) -> Result<()> {
+        if d.has("alpha") {
+            self.warn("shape alpha is ignored".to_string());
+        }
+        let ctm = self.state.ctm;
+        match ty {
+            "sphere" => {
+                if d.has("zmin") || d.has("zmax") || d.has("phimax") {
+                    self.warn("partial spheres were rendered as full spheres".to_string());
+                }
+                let mut warnings = Vec::new();
+                let sphere = transformed_sphere(
+                    &ctm,
+                    Float3::zeros(),
+                    d.float("radius", 1.0),
+                    material,
+                    |msg| warnings.push(msg),
+                );
+                warnings.into_iter().for_each(|msg| self.warn(msg));
+                self.world.push(Box::new(sphere));
+            }
+            "trianglemesh" | "bilinearmesh" | "loopsubdiv" => {
+                if ty == "loopsubdiv" {
+                    self.warn("loopsubdiv meshes were rendered without subdivision".to_string());
+                }
+                let data = polygon_mesh(ty, d)?;
+                self.world
+                    .push(Box::new(transformed_mesh(&ctm, &data, material)));
+            }
+            "plymesh" => {
+                if d.has("displacement") {
+                    self.warn("plymesh displacement is ignored".to_string());
+                }
+                let file = self.base_dir.join(d.string("filename").unwrap_or(""));
+                let data = load_ply(file)?;
+                self.world
+                    .push(Box::new(transformed_mesh(&ctm, &data, material)));
+            }
+            _ => self.warn(format!("shape {:?} is not supported and was skipped", ty)),
+        }
+        Ok(())
+    }
+}
+
+fn to_float3s(values: &[f64]) -> Vec<Float3> {
+    values
+        .chunks_exact(3)
+        .map(|c| float3::new(c[0], c[1], c[2]))
+        .collect()
+}
+
+// trianglemesh, bilinearmesh, loopsubdiv のパラメータからメッシュを作る
+fn polygon_mesh(ty: &str, d: &Directive) -> Result<MeshData> {
+    let positions = to_float3s(&d.floats("P"));
+    let normals = to_float3s(&d.floats("N"));
+    let uv_name = if d.has("uv") { "uv" } else { "st" };
+    // pbrtはv = 0が画像の下端なので反転する
+    let uvs: Vec<(f64, f64)> = d
+        .floats(uv_name)
+        .chunks_exact(2)
+        .map(|c| (c[0], 1.0 - c[1]))
+        .collect();
+    let vertices_per_face = if ty == "bilinearmesh" { 4 } else { 3 };
+    let mut raw: Vec<usize> = d.floats("indices").iter().map(|&i| i as usize).collect();
+    if raw.is_empty() && positions.len() == vertices_per_face {
+        raw = (0..vertices_per_face).collect();
+    }
+    if !raw.len().is_multiple_of(vertices_per_face) {
+        return Err(Error::InvalidScene(format!(
+            "{} has {} indices, which is not a multiple of {}",
+            ty,
+            raw.len(),
+            vertices_per_face
+        )));
+    }
+    let indices = if ty == "bilinearmesh" {
+        // 頂点の順序は p00, p10, p01, p11
+        raw.chunks_exact(4)
+            .flat_map(|q| [[q[0], q[1], q[3]], [q[0], q[3], q[2]]])
+            .collect()
+    } else {
+        raw.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect()
+    };
+    MeshData::new(positions, normals, uvs, indices)
+}
+
+// 変換の命令を行列にする. Transform/ConcatTransformは列優先の16要素
+fn transform_matrix(name: &str, n: &[f64]) -> Option<Mat4> {
+    let v = |i: usize| vector![n[i], n[i + 1], n[i + 2]];
+    Some(match (name, n.len()) {
+        ("Translate", 3) => Mat4::new_translation(&v(0)),
+        ("Scale", 3) => Mat4::new_nonuniform_scaling(&v(0)),
+        ("Rotate", 4) => {
+            let axis = na::Unit::try_new(v(1), 0.0)?;
+            na::Rotation3::from_axis_angle(&axis, n[0].to_radians()).to_homogeneous()
+        }
+        ("LookAt", 9) => {
+            let (eye, look, up) = (v(0), v(3), v(6));
+            let dir = (look - eye).try_normalize(0.0)?;
+            let right = up.normalize().cross(&dir).try_normalize(0.0)?;
+            let new_up = dir.cross(&right);
+            let mut world_from_camera = Mat4::identity();
+            world_from_camera
+                .fixed_slice_mut::<3, 1>(0, 0)
+                .copy_from(&right);
+            world_from_camera
+                .fixed_slice_mut::<3, 1>(0, 1)
+                .copy_from(&new_up);
+            world_from_camera
+                .fixed_slice_mut::<3, 1>(0, 2)
+                .copy_from(&dir);
+            world_from_camera
+                .fixed_slice_mut::<3, 1>(0, 3)
+                .copy_from(&eye);
+            world_from_camera.try_inverse()?
+        }
+        ("Transform" | "ConcatTransform", 16) => Mat4::from_column_slice(n),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // テストごとのディレクトリにfilesを書いて、最初のファイルを読む
+    fn load(name: &str, files: &[(&str, &str)]) -> Result<ImportedScene> {
+        let dir =
+            std::env::temp_dir().join(format!("ayanami-pbrt-{}-{}", name, std::process::id()));
+        for (file, text) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        let scene = load_pbrt(dir.join(files[0].0));
+        fs::remove_dir_all(&dir).unwrap();
+        scene
+    }
+
+    fn hit_t(scene: &ImportedScene, origin: Float3, direction: Float3) -> Option<f64> {
+        scene
+            .world
+            .hit(&Ray::new(origin, direction), 0.001, f64::MAX)
+            .map(|hit| hit.t)
+    }
+
+    #[test]
+    fn camera_is_left_handed() {
+        let scene = load(
+            "camera",
+            &[(
+                "scene.pbrt",
+                "LookAt 0 0 5  0 0 0  0 1 0\nCamera \"perspective\" \"float fov\" [45]\nWorldBegin\n",
+            )],
+        )
+        .unwrap();
+        let params = &scene.camera_params;
+        assert!((params.origin - float3::new(0.0, 0.0, 5.0)).norm() < 1e-9);
+        assert!(((params.lookat - params.origin).normalize() + Float3::z()).norm() < 1e-9);
+        assert!((params.vup - Float3::y()).norm() < 1e-9);
+        // pbrtは左手系なので、+zから原点を見ると画像の右がワールドの-x
+        assert!(params.mirror);
+        let camera = params.camera(1.0);
+        assert!(camera.ray(0.9, 0.5).direction.x < 0.0);
+        assert!(camera.ray(0.5, 0.9).direction.y > 0.0);
+    }
+
+    #[test]
+    fn transform_is_column_major() {
+        let m = transform_matrix(
+            "Transform",
+            &[
+                1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 3.0, 4.0, 5.0, 1.0,
+            ],
+        )
+        .unwrap();
+        let p = m.transform_point(&na::Point3::origin()).coords;
+        assert_eq!(p, float3::new(3.0, 4.0, 5.0));
+
+        let scene = load(
+            "transform",
+            &[(
+                "scene.pbrt",
+                "WorldBegin\nTransform [1 0 0 0  0 1 0 0  0 0 1 0  3 4 5 1]\nShape \"sphere\" \"float radius\" [1]\n",
+            )],
+        )
+        .unwrap();
+        let t = hit_t(&scene, float3::new(3.0, 4.0, 10.0), -Float3::z()).unwrap();
+        assert!((t - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn include_is_relative_to_the_main_file() {
+        // pbrtと同じく、入れ子のIncludeも最初のファイルのディレクトリから探す
+        let scene = load(
+            "include",
+            &[
+                ("scene.pbrt", "WorldBegin\nInclude \"parts/a.pbrt\"\n"),
+                ("parts/a.pbrt", "Include \"parts/b.pbrt\"\n"),
+                ("parts/b.pbrt", "Shape \"sphere\" \"float radius\" [2]\n"),
+            ],
+        )
+        .unwrap();
+        let t = hit_t(&scene, float3::new(0.0, 0.0, 10.0), -Float3::z()).unwrap();
+        assert!((t - 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn missing_include_is_an_error() {
+        let result = load(
+            "missing",
+            &[("scene.pbrt", "WorldBegin\nInclude \"missing.pbrt\"\n")],
+        );
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn warns_on_unsupported_directives() {
+        let scene = load(
+            "warnings",
+            &[(
+                "scene.pbrt",
+                "Camera \"orthographic\"\nMakeNamedMedium \"fog\" \"string type\" \"homogeneous\"\nWorldBegin\nLightSource \"spot\"\n",
+            )],
+        )
+        .unwrap();
+        let has = |text: &str| scene.warnings.iter().any(|w| w.contains(text));
+        assert!(has("orthographic"), "{:?}", scene.warnings);
+        assert!(has("MakeNamedMedium"), "{:?}", scene.warnings);
+        assert!(has("spot"), "{:?}", scene.warnings);
+    }
+}
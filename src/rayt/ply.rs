@@ -0,0 +1,415 @@
+use crate::rayt::*;
+use std::fs;
+use std::path::Path;
+
+// Stanford PLY (ascii, binary_little_endian, binary_big_endian) の読み込み
+// vertexのx,y,z と faceのvertex_indices以外は任意
+// 法線(nx,ny,nz), uv(u,v / s,t / texture_u,texture_v), 頂点カラー(red,green,blue)を読む
+// 整数の頂点カラーはsRGB, 浮動小数点数の頂点カラーはリニアな値とみなす
+pub fn load_ply(path: impl AsRef<Path>) -> Result<MeshData> {
+    let path = path.as_ref();
+    let bytes = fs::read(path)?;
+    parse_ply(&bytes).map_err(|msg| Error::InvalidScene(format!("{}: {}", path.display(), msg)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PlyType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "char" | "int8" => PlyType::Int8,
+            "uchar" | "uint8" => PlyType::UInt8,
+            "short" | "int16" => PlyType::Int16,
+            "ushort" | "uint16" => PlyType::UInt16,
+            "int" | "int32" => PlyType::Int32,
+            "uint" | "uint32" => PlyType::UInt32,
+            "float" | "float32" => PlyType::Float32,
+            "double" | "float64" => PlyType::Float64,
+            _ => return Err(format!("unknown property type {:?}", name)),
+        })
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            PlyType::Int8 | PlyType::UInt8 => 1,
+            PlyType::Int16 | PlyType::UInt16 => 2,
+            PlyType::Int32 | PlyType::UInt32 | PlyType::Float32 => 4,
+            PlyType::Float64 => 8,
+        }
+    }
+
+    // 整数の色は0-255 (ushortなら0-65535) を0-1に正規化する
+    fn color_scale(&self) -> f64 {
+        match self {
+            PlyType::UInt8 | PlyType::Int8 => 255.0f64.recip(),
+            PlyType::UInt16 | PlyType::Int16 => 65535.0f64.recip(),
+            _ => 1.0,
+        }
+    }
+}
+
+enum PlyProperty {
+    Scalar(String, PlyType),
+    List(String, PlyType, PlyType),
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+// 本体から値を1つずつ取り出す
+struct PlyReader<'a> {
+    format: PlyFormat,
+    body: &'a [u8],
+    offset: usize,
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> PlyReader<'a> {
+    fn new(format: PlyFormat, body: &'a [u8]) -> Result<Self, String> {
+        let text = if format == PlyFormat::Ascii {
+            std::str::from_utf8(body).map_err(|_| "ascii body is not valid utf-8".to_string())?
+        } else {
+            ""
+        };
+        Ok(Self {
+            format,
+            body,
+            offset: 0,
+            tokens: text.split_ascii_whitespace(),
+        })
+    }
+
+    fn read(&mut self, ty: PlyType) -> Result<f64, String> {
+        if self.format == PlyFormat::Ascii {
+            let token = self.tokens.next().ok_or("unexpected end of file")?;
+            return token
+                .parse::<f64>()
+                .map_err(|_| format!("invalid number {:?}", token));
+        }
+
+        let size = ty.size();
+        let bytes = self
+            .body
+            .get(self.offset..self.offset + size)
+            .ok_or("unexpected end of file")?;
+        self.offset += size;
+        let mut buf = [0u8; 8];
+        buf[..size].copy_from_slice(bytes);
+        if self.format == PlyFormat::BinaryBigEndian {
+            buf[..size].reverse();
+        }
+        Ok(match ty {
+            PlyType::Int8 => buf[0] as i8 as f64,
+            PlyType::UInt8 => buf[0] as f64,
+            PlyType::Int16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+            PlyType::UInt16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+            PlyType::Int32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            PlyType::UInt32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            PlyType::Float32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            PlyType::Float64 => f64::from_le_bytes(buf),
+        })
+    }
+}
+
+fn parse_header(bytes: &[u8]) -> Result<(PlyFormat, Vec<PlyElement>, usize), String> {
+    const END: &[u8] = b"end_header";
+    let end = bytes
+        .windows(END.len())
+        .position(|w| w == END)
+        .ok_or("missing end_header")?;
+    // end_headerの行末 (\n か \r\n) の次から本体
+    let body_start = bytes[end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |i| end + i + 1);
+    let header =
+        std::str::from_utf8(&bytes[..end]).map_err(|_| "header is not valid utf-8".to_string())?;
+
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err("not a ply file".to_string());
+    }
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", f, _] => {
+                format = Some(match *f {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return Err(format!("unknown format {:?}", f)),
+                })
+            }
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("invalid element count {:?}", count))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_ty, item_ty, name] => elements
+                .last_mut()
+                .ok_or("property before element")?
+                .properties
+                .push(PlyProperty::List(
+                    name.to_string(),
+                    PlyType::parse(count_ty)?,
+                    PlyType::parse(item_ty)?,
+                )),
+            ["property", ty, name] => elements
+                .last_mut()
+                .ok_or("property before element")?
+                .properties
+                .push(PlyProperty::Scalar(name.to_string(), PlyType::parse(ty)?)),
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(format!("unknown header line {:?}", line)),
+        }
+    }
+    let format = format.ok_or("missing format")?;
+    Ok((format, elements, body_start))
+}
+
+fn parse_ply(bytes: &[u8]) -> Result<MeshData, String> {
+    let (format, elements, body_start) = parse_header(bytes)?;
+    let mut reader = PlyReader::new(format, &bytes[body_start..])?;
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut indices: Vec<[usize; 3]> = Vec::new();
+
+    for element in &elements {
+        for _ in 0..element.count {
+            let mut p = [0.0; 3];
+            let mut n = [0.0; 3];
+            let mut uv = [0.0; 2];
+            let mut c = [0.0; 3];
+            let (mut has_n, mut has_uv, mut has_c) = (false, false, false);
+            let mut srgb = false;
+            let mut face: Vec<usize> = Vec::new();
+
+            for property in &element.properties {
+                match property {
+                    PlyProperty::Scalar(name, ty) => {
+                        let x = reader.read(*ty)?;
+                        if matches!(name.as_str(), "red" | "r" | "green" | "g" | "blue" | "b") {
+                            srgb |= ty.color_scale() != 1.0;
+                        }
+                        match name.as_str() {
+                            "x" => p[0] = x,
+                            "y" => p[1] = x,
+                            "z" => p[2] = x,
+                            "nx" => (n[0], has_n) = (x, true),
+                            "ny" => (n[1], has_n) = (x, true),
+                            "nz" => (n[2], has_n) = (x, true),
+                            "u" | "s" | "texture_u" | "texture_s" => (uv[0], has_uv) = (x, true),
+                            "v" | "t" | "texture_v" | "texture_t" => (uv[1], has_uv) = (x, true),
+                            "red" | "r" => (c[0], has_c) = (x * ty.color_scale(), true),
+                            "green" | "g" => (c[1], has_c) = (x * ty.color_scale(), true),
+                            "blue" | "b" => (c[2], has_c) = (x * ty.color_scale(), true),
+                            _ => {}
+                        }
+                    }
+                    PlyProperty::List(name, count_ty, item_ty) => {
+                        let count = reader.read(*count_ty)? as usize;
+                        let is_face_indices = element.name == "face"
+                            && (name == "vertex_indices" || name == "vertex_index");
+                        for _ in 0..count {
+                            let x = reader.read(*item_ty)?;
+                            if is_face_indices {
+                                if x < 0.0 {
+                                    return Err(format!("negative vertex index {}", x));
+                                }
+                                face.push(x as usize);
+                            }
+                        }
+                    }
+                }
+            }
+
+            match element.name.as_str() {
+                "vertex" => {
+                    positions.push(float3::new(p[0], p[1], p[2]));
+                    if has_n {
+                        normals.push(float3::new(n[0], n[1], n[2]));
+                    }
+                    if has_uv {
+                        // ImageTextureはv = 0が画像の上端なので反転する
+                        uvs.push((uv[0], 1.0 - uv[1]));
+                    }
+                    if has_c {
+                        let c = float3::new(c[0], c[1], c[2]);
+                        colors.push(if srgb {
+                            ColorSpace::Srgb.to_linear(c)
+                        } else {
+                            c
+                        });
+                    }
+                }
+                "face" => {
+                    if let Some(&i) = face.iter().find(|&&i| i >= positions.len()) {
+                        return Err(format!("vertex index {} is out of range", i));
+                    }
+                    let points: Vec<Float3> = face.iter().map(|&i| positions[i]).collect();
+                    for [a, b, c] in triangulate(&points) {
+                        indices.push([face[a], face[b], face[c]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    MeshData::new(positions, normals, uvs, indices)
+        .and_then(|data| data.with_colors(colors))
+        .map_err(|e| match e {
+            Error::InvalidScene(msg) => msg,
+            e => e.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+                          element face 1\nproperty list uchar int vertex_indices\nend_header\n";
+
+    // z = 0の正方形を1つの四角形で表したもの
+    fn binary(little_endian: bool) -> Vec<u8> {
+        let format = if little_endian {
+            "binary_little_endian"
+        } else {
+            "binary_big_endian"
+        };
+        let mut bytes = format!("ply\nformat {} 1.0\n{}", format, HEADER).into_bytes();
+        let f32_bytes = |x: f32| {
+            if little_endian {
+                x.to_le_bytes()
+            } else {
+                x.to_be_bytes()
+            }
+        };
+        for (x, y) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+            for v in [x, y, 0.0] {
+                bytes.extend(f32_bytes(v));
+            }
+        }
+        bytes.push(4);
+        for i in 0..4i32 {
+            bytes.extend(if little_endian {
+                i.to_le_bytes()
+            } else {
+                i.to_be_bytes()
+            });
+        }
+        bytes
+    }
+
+    fn assert_square(data: &MeshData) {
+        assert_eq!(data.positions().len(), 4);
+        assert_eq!(data.positions()[2], float3::new(1.0, 1.0, 0.0));
+        // 四角形は2つの三角形になる
+        assert_eq!(data.indices().len(), 2);
+        let area: f64 = data
+            .indices()
+            .iter()
+            .map(|&[a, b, c]| {
+                let p = data.positions();
+                (p[b] - p[a]).cross(&(p[c] - p[a])).z * 0.5
+            })
+            .sum();
+        assert!((area - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn parses_ascii() {
+        let text = format!(
+            "ply\nformat ascii 1.0\ncomment square\n{}0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n",
+            HEADER
+        );
+        assert_square(&parse_ply(text.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn parses_binary_little_endian() {
+        assert_square(&parse_ply(&binary(true)).unwrap());
+    }
+
+    #[test]
+    fn parses_binary_big_endian() {
+        assert_square(&parse_ply(&binary(false)).unwrap());
+    }
+
+    #[test]
+    fn triangulates_polygons() {
+        // 凹んだ五角形と三角形
+        let text = "ply\nformat ascii 1.0\nelement vertex 5\nproperty float x\nproperty float y\n\
+                    property float z\nelement face 2\nproperty list uchar uint vertex_indices\n\
+                    end_header\n0 0 0\n2 0 0\n2 2 0\n1 1 0\n0 2 0\n5 0 1 2 3 4\n3 0 1 2\n";
+        let data = parse_ply(text.as_bytes()).unwrap();
+        assert_eq!(data.indices().len(), 3 + 1);
+    }
+
+    #[test]
+    fn rejects_out_of_range_index() {
+        let text = format!(
+            "ply\nformat ascii 1.0\n{}0 0 0\n1 0 0\n1 1 0\n0 1 0\n3 0 1 4\n",
+            HEADER
+        );
+        let err = parse_ply(text.as_bytes()).err().unwrap();
+        assert!(err.contains("out of range"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_truncated_binary() {
+        let bytes = binary(true);
+        for len in [bytes.len() - 1, bytes.len() - 17] {
+            let err = parse_ply(&bytes[..len]).err().unwrap();
+            assert!(err.contains("unexpected end of file"), "{}", err);
+        }
+        let text = format!("ply\nformat ascii 1.0\n{}0 0 0\n1 0 0\n", HEADER);
+        assert!(parse_ply(text.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn linearises_integer_colors() {
+        let text = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+                    property float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\n\
+                    end_header\n0 0 0 255 128 0\n1 0 0 0 0 0\n0 1 0 0 0 0\n";
+        let data = parse_ply(text.as_bytes()).unwrap();
+        let expected = ColorSpace::Srgb.to_linear(float3::new(1.0, 128.0 / 255.0, 0.0));
+        assert!((data.colors()[0] - expected).norm() < 1e-12);
+        assert!(data.colors()[0].y < 0.25);
+
+        // 浮動小数点数の色はそのまま
+        let text = text
+            .replace("uchar", "float")
+            .replace("255 128 0", "1 0.5 0");
+        let data = parse_ply(text.as_bytes()).unwrap();
+        assert_eq!(data.colors()[0], float3::new(1.0, 0.5, 0.0));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::rayt::*;
+
+// 0..1の値を色にするグラデーション. 区切りの間は線形に補間する
+#[derive(Debug, Clone)]
+pub struct ColorRamp {
+    stops: Vec<(f64, Float3)>,
+}
+
+impl ColorRamp {
+    // 0がstart, 1がendのグラデーション
+    pub fn new(start: Float3, end: Float3) -> Self {
+        Self {
+            stops: vec![(0.0, start), (1.0, end)],
+        }
+    }
+
+    pub fn grayscale() -> Self {
+        Self::new(Float3::zeros(), float3::one())
+    }
+
+    // 区切りを追加する. 同じ位置の区切りがあれば置き換える
+    pub fn stop(mut self, t: f64, color: Float3) -> Self {
+        let i = self.stops.partition_point(|s| s.0 < t);
+        match self.stops.get(i) {
+            Some(s) if s.0 == t => self.stops[i] = (t, color),
+            _ => self.stops.insert(i, (t, color)),
+        }
+        self
+    }
+
+    pub fn sample(&self, t: f64) -> Float3 {
+        let i = self.stops.partition_point(|s| s.0 <= t);
+        if i == 0 {
+            return self.stops[0].1;
+        }
+        if i == self.stops.len() {
+            return self.stops[i - 1].1;
+        }
+        let ((t0, c0), (t1, c1)) = (self.stops[i - 1], self.stops[i]);
+        c0.lerp(&c1, (t - t0) / (t1 - t0))
+    }
+}
+
+// Voronoi模様で使う値
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoronoiFeature {
+    // 一番近い点までの距離. 点の周りが暗い
+    F1,
+    // 2番目に近い点までの距離
+    F2,
+    // F2 - F1. セルの境目が暗い線になる
+    Edge,
+    // セルごとに一様な乱数. 石畳のようになる
+    Cell,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoisePattern {
+    Fbm,
+    Turbulence,
+    // x方向の縞を乱流でゆがめる. distortionは縞をずらす量
+    Marble { distortion: f64 },
+    // y軸の周りの年輪. distortionは年輪をゆがめる量
+    Wood { distortion: f64 },
+    Voronoi { feature: VoronoiFeature },
+}
+
+// ノイズから作る模様. 位置pをfreq倍してノイズを取り、0..1の値をColorRampで色にする
+// uvを使わないので、形状の表面ではなく空間に模様がある (形状を動かすと模様の中を動く)
+pub struct NoiseTexture {
+    noise: Noise,
+    pattern: NoisePattern,
+    freq: f64,
+    fractal: Fractal,
+    ramp: ColorRamp,
+}
+
+impl NoiseTexture {
+    pub fn new(pattern: NoisePattern, freq: f64) -> Self {
+        let ramp = match pattern {
+            NoisePattern::Marble { .. } => ColorRamp::new(float3::fill(0.9), float3::fill(0.15))
+                .stop(0.85, float3::new(0.75, 0.75, 0.8)),
+            NoisePattern::Wood { .. } => {
+                ColorRamp::new(float3::new(0.6, 0.4, 0.2), float3::new(0.3, 0.15, 0.05))
+            }
+            _ => ColorRamp::grayscale(),
+        };
+        Self {
+            noise: Noise::default(),
+            pattern,
+            freq,
+            fractal: Fractal::default(),
+            ramp,
+        }
+    }
+
+    pub fn fbm(freq: f64) -> Self {
+        Self::new(NoisePattern::Fbm, freq)
+    }
+
+    pub fn turbulence(freq: f64) -> Self {
+        Self::new(NoisePattern::Turbulence, freq)
+    }
+
+    pub fn marble(freq: f64) -> Self {
+        Self::new(NoisePattern::Marble { distortion: 4.0 }, freq)
+    }
+
+    pub fn wood(freq: f64) -> Self {
+        Self::new(NoisePattern::Wood { distortion: 0.3 }, freq)
+    }
+
+    pub fn voronoi(freq: f64, feature: VoronoiFeature) -> Self {
+        let texture = Self::new(NoisePattern::Voronoi { feature }, freq);
+        // 重ねると細かいセルで形が崩れるので、既定は1オクターブ
+        texture.with_octaves(1)
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.noise = Noise::new(seed).with_basis(self.noise.basis());
+        self
+    }
+
+    pub fn with_basis(mut self, basis: NoiseBasis) -> Self {
+        self.noise = self.noise.with_basis(basis);
+        self
+    }
+
+    pub fn with_octaves(mut self, octaves: u32) -> Self {
+        self.fractal.octaves = octaves;
+        self
+    }
+
+    pub fn with_fractal(mut self, fractal: Fractal) -> Self {
+        self.fractal = fractal;
+        self
+    }
+
+    pub fn with_ramp(mut self, ramp: ColorRamp) -> Self {
+        self.ramp = ramp;
+        self
+    }
+
+    // ColorRampに渡す前の0..1の値
+    pub fn intensity(&self, p: Float3) -> f64 {
+        let q = p * self.freq;
+        let t = match self.pattern {
+            NoisePattern::Fbm => 0.5 * (self.noise.fbm(q, &self.fractal) + 1.0),
+            NoisePattern::Turbulence => self.noise.turbulence(q, &self.fractal),
+            NoisePattern::Marble { distortion } => {
+                let turbulence = self.noise.turbulence(q, &self.fractal);
+                0.5 * ((q.x + distortion * turbulence) * PI).sin() + 0.5
+            }
+            NoisePattern::Wood { distortion } => {
+                let r =
+                    (q.x * q.x + q.z * q.z).sqrt() + distortion * self.noise.fbm(q, &self.fractal);
+                r.rem_euclid(1.0)
+            }
+            NoisePattern::Voronoi { feature } => self.fractal.sum(q, |q| {
+                let w = self.noise.worley(q);
+                match feature {
+                    VoronoiFeature::F1 => w.f1,
+                    VoronoiFeature::F2 => w.f2,
+                    VoronoiFeature::Edge => w.f2 - w.f1,
+                    VoronoiFeature::Cell => w.cell,
+                }
+            }),
+        };
+        t.clamp(0.0, 1.0)
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: Float3) -> Float3 {
+        self.ramp.sample(self.intensity(p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_ramp_interpolates_and_clamps() {
+        let red = float3::new(1.0, 0.0, 0.0);
+        let ramp = ColorRamp::grayscale().stop(0.5, red);
+        assert_eq!(ramp.sample(-1.0), Float3::zeros());
+        assert_eq!(ramp.sample(2.0), float3::one());
+        assert_eq!(ramp.sample(0.5), red);
+        assert!((ramp.sample(0.25) - float3::new(0.5, 0.0, 0.0)).norm() < 1e-12);
+        // 同じ位置の区切りは置き換える
+        let ramp = ramp.stop(0.5, Float3::zeros());
+        assert_eq!(ramp.sample(0.5), Float3::zeros());
+    }
+
+    #[test]
+    fn patterns_are_deterministic_and_in_range() {
+        let patterns = |seed| {
+            [
+                NoiseTexture::fbm(2.0),
+                NoiseTexture::turbulence(2.0),
+                NoiseTexture::marble(2.0),
+                NoiseTexture::wood(2.0),
+                NoiseTexture::voronoi(2.0, VoronoiFeature::Edge),
+                NoiseTexture::voronoi(2.0, VoronoiFeature::Cell),
+            ]
+            .map(|texture| texture.with_seed(seed))
+        };
+        // 同じ種で作り直しても同じ値になる
+        for (a, b) in patterns(3).iter().zip(patterns(3).iter()) {
+            for i in 0..50 {
+                let p = float3::new(i as f64 * 0.37, -(i as f64) * 0.21, i as f64 * 0.13);
+                let t = a.intensity(p);
+                assert!((0.0..=1.0).contains(&t));
+                assert_eq!(t, b.intensity(p));
+            }
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use crate::rayt::*;
+
+// 画像上の位置からレイを作るカメラ. u, vは[0, 1]で、(0, 0)が左下
+// Noneは画像の外 (魚眼の円の外など) で、その画素は黒になる
+pub trait Projection: Sync + Send {
+    fn generate_ray(&self, u: f64, v: f64) -> Option<Ray>;
+
+    // du, dvは隣の画素までの差. 隣の画素を通るレイを微分として持つレイを作る
+    // generate_rayが乱数を使うなら、同じ乱数で3本のレイを作るように実装する
+    fn generate_ray_differential(&self, u: f64, v: f64, du: f64, dv: f64) -> Option<Ray> {
+        let ray = self.generate_ray(u, v)?;
+        match (self.generate_ray(u + du, v), self.generate_ray(u, v + dv)) {
+            (Some(rx), Some(ry)) => Some(ray.with_differential(RayDifferential {
+                rx_origin: rx.origin,
+                rx_direction: rx.direction,
+                ry_origin: ry.origin,
+                ry_direction: ry.direction,
+            })),
+            _ => Some(ray),
+        }
+    }
+}
+
+impl Projection for Camera {
+    fn generate_ray(&self, u: f64, v: f64) -> Option<Ray> {
+        Some(self.ray(u, v))
+    }
+
+    fn generate_ray_differential(&self, u: f64, v: f64, du: f64, dv: f64) -> Option<Ray> {
+        Some(self.ray_differential(u, v, du, dv))
+    }
+}
+
+// シャッターが開いている間の一様な時刻
+fn shutter_time(open: f64, close: f64) -> f64 {
+    math::random_limit(open, close)
+}
+
+// lookatへの向き, 右, 上の正規直交基底
+fn lookat_basis(origin: Float3, lookat: Float3, vup: Float3) -> (Float3, Float3, Float3) {
+    let forward = (lookat - origin).normalize();
+    let right = forward.cross(&vup).normalize();
+    let up = right.cross(&forward);
+    (forward, right, up)
+}
+
+// 平行投影. 図面のように遠近感の無い画像になる
+#[derive(Debug, Clone)]
+pub struct OrthographicCamera {
+    pub origin: Float3, // 画面の左下
+    pub u: Float3,      // left bottom -> right bottom
+    pub v: Float3,      // left bottom -> left up
+    pub direction: Float3,
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+}
+
+impl OrthographicCamera {
+    // heightは画面の高さ (ワールド座標の単位). originが画面の中心になる
+    pub fn from_lookat(
+        origin: Float3,
+        lookat: Float3,
+        vup: Float3,
+        height: f64,
+        aspect: f64,
+    ) -> Self {
+        let (forward, right, up) = lookat_basis(origin, lookat, vup);
+        let u = right * height * aspect;
+        let v = up * height;
+        Self {
+            origin: origin - (u + v) * 0.5,
+            u,
+            v,
+            direction: forward,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    // Camera::with_shutterと同じく、この間の一様な時刻のレイを作る
+    pub fn with_shutter(self, open: f64, close: f64) -> Self {
+        Self {
+            shutter_open: open,
+            shutter_close: close,
+            ..self
+        }
+    }
+}
+
+impl Projection for OrthographicCamera {
+    fn generate_ray(&self, u: f64, v: f64) -> Option<Ray> {
+        Some(Ray::with_time(
+            self.origin + self.u * u + self.v * v,
+            self.direction,
+            shutter_time(self.shutter_open, self.shutter_close),
+        ))
+    }
+}
+
+// 等距離射影の魚眼. 中心からの距離が光軸からの角度に比例する
+// 画面の高さに内接する円がfovになり、円の外は黒
+#[derive(Debug, Clone)]
+pub struct FisheyeCamera {
+    pub origin: Float3,
+    pub forward: Float3,
+    pub right: Float3,
+    pub up: Float3,
+    // 度数法. 180で半球が写る
+    pub fov: f64,
+    pub aspect: f64,
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+}
+
+impl FisheyeCamera {
+    pub fn from_lookat(origin: Float3, lookat: Float3, vup: Float3, fov: f64, aspect: f64) -> Self {
+        let (forward, right, up) = lookat_basis(origin, lookat, vup);
+        Self {
+            origin,
+            forward,
+            right,
+            up,
+            fov,
+            aspect,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    // Camera::with_shutterと同じく、この間の一様な時刻のレイを作る
+    pub fn with_shutter(self, open: f64, close: f64) -> Self {
+        Self {
+            shutter_open: open,
+            shutter_close: close,
+            ..self
+        }
+    }
+}
+
+impl Projection for FisheyeCamera {
+    fn generate_ray(&self, u: f64, v: f64) -> Option<Ray> {
+        let x = (u * 2.0 - 1.0) * self.aspect;
+        let y = v * 2.0 - 1.0;
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+        let theta = r * self.fov.to_radians() * 0.5;
+        let phi = y.atan2(x);
+        let direction = self.forward * theta.cos()
+            + (self.right * phi.cos() + self.up * phi.sin()) * theta.sin();
+        Some(Ray::with_time(
+            self.origin,
+            direction,
+            shutter_time(self.shutter_open, self.shutter_close),
+        ))
+    }
+}
+
+// 全天球の正距円筒図法 (2:1の画像). 横が経度, 縦が緯度で、画像の中心がlookatの方向
+#[derive(Debug, Clone)]
+pub struct EquirectangularCamera {
+    pub origin: Float3,
+    pub forward: Float3,
+    pub right: Float3,
+    pub up: Float3,
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+}
+
+impl EquirectangularCamera {
+    pub fn from_lookat(origin: Float3, lookat: Float3, vup: Float3) -> Self {
+        let (forward, right, up) = lookat_basis(origin, lookat, vup);
+        Self {
+            origin,
+            forward,
+            right,
+            up,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    // Camera::with_shutterと同じく、この間の一様な時刻のレイを作る
+    pub fn with_shutter(self, open: f64, close: f64) -> Self {
+        Self {
+            shutter_open: open,
+            shutter_close: close,
+            ..self
+        }
+    }
+}
+
+impl Projection for EquirectangularCamera {
+    fn generate_ray(&self, u: f64, v: f64) -> Option<Ray> {
+        let longitude = (u - 0.5) * PI2;
+        let latitude = (v - 0.5) * PI;
+        let direction = (self.forward * longitude.cos() + self.right * longitude.sin())
+            * latitude.cos()
+            + self.up * latitude.sin();
+        Some(Ray::with_time(
+            self.origin,
+            direction,
+            shutter_time(self.shutter_open, self.shutter_close),
+        ))
+    }
+}
+
+// 画角90度の6面を3x2 (1面が正方形なので3:2の画像) に並べたキューブマップ
+// 上の段が 右, 左, 上, 下の段が 下, 前, 後 (前がlookatの方向)
+#[derive(Debug, Clone)]
+pub struct CubemapCamera {
+    pub origin: Float3,
+    pub forward: Float3,
+    pub right: Float3,
+    pub up: Float3,
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+}
+
+impl CubemapCamera {
+    pub fn from_lookat(origin: Float3, lookat: Float3, vup: Float3) -> Self {
+        let (forward, right, up) = lookat_basis(origin, lookat, vup);
+        Self {
+            origin,
+            forward,
+            right,
+            up,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    // Camera::with_shutterと同じく、この間の一様な時刻のレイを作る
+    pub fn with_shutter(self, open: f64, close: f64) -> Self {
+        Self {
+            shutter_open: open,
+            shutter_close: close,
+            ..self
+        }
+    }
+
+    // 面の番号ごとの (向き, 画像の右, 画像の上). 内側から見て正立する
+    fn face(&self, index: usize) -> (Float3, Float3, Float3) {
+        let (f, r, u) = (self.forward, self.right, self.up);
+        match index {
+            0 => (r, -f, u),
+            1 => (-r, f, u),
+            2 => (u, r, -f),
+            3 => (-u, r, f),
+            4 => (f, r, u),
+            _ => (-f, -r, u),
+        }
+    }
+}
+
+impl Projection for CubemapCamera {
+    fn generate_ray(&self, u: f64, v: f64) -> Option<Ray> {
+        let x = (u * 3.0).clamp(0.0, 3.0 - EPS);
+        let y = ((1.0 - v) * 2.0).clamp(0.0, 2.0 - EPS);
+        let (column, row) = (x.floor(), y.floor());
+        let (direction, right, up) = self.face(row as usize * 3 + column as usize);
+        // 面の中での位置を[-1, 1]にする. yは下向きなので反転
+        let s = (x - column) * 2.0 - 1.0;
+        let t = 1.0 - (y - row) * 2.0;
+        Some(Ray::with_time(
+            self.origin,
+            direction + right * s + up * t,
+            shutter_time(self.shutter_open, self.shutter_close),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // -zを向いたカメラ. 右が+x, 上が+y
+    const ORIGIN: Float3 = Float3::new(1.0, 2.0, 3.0);
+
+    fn lookat() -> Float3 {
+        ORIGIN - Float3::z()
+    }
+
+    fn direction(p: &dyn Projection, u: f64, v: f64) -> Float3 {
+        p.generate_ray(u, v).unwrap().direction.normalize()
+    }
+
+    fn assert_near(a: Float3, b: Float3) {
+        assert!((a - b).norm() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let camera = OrthographicCamera::from_lookat(ORIGIN, lookat(), Float3::y(), 2.0, 2.0);
+        for (u, v, offset) in [
+            (0.5, 0.5, Float3::zeros()),
+            (0.0, 0.0, float3::new(-2.0, -1.0, 0.0)),
+            (1.0, 1.0, float3::new(2.0, 1.0, 0.0)),
+            (1.0, 0.0, float3::new(2.0, -1.0, 0.0)),
+        ] {
+            let ray = camera.generate_ray(u, v).unwrap();
+            assert_near(ray.origin, ORIGIN + offset);
+            assert_near(ray.direction, -Float3::z());
+        }
+    }
+
+    #[test]
+    fn fisheye_directions() {
+        let camera = FisheyeCamera::from_lookat(ORIGIN, lookat(), Float3::y(), 180.0, 1.0);
+        assert_near(direction(&camera, 0.5, 0.5), -Float3::z());
+        // 円の縁は光軸から90度
+        assert_near(direction(&camera, 1.0, 0.5), Float3::x());
+        assert_near(direction(&camera, 0.0, 0.5), -Float3::x());
+        assert_near(direction(&camera, 0.5, 1.0), Float3::y());
+        // 角は円の外
+        assert!(camera.generate_ray(1.0, 1.0).is_none());
+
+        let camera = FisheyeCamera::from_lookat(ORIGIN, lookat(), Float3::y(), 90.0, 1.0);
+        let d = direction(&camera, 1.0, 0.5);
+        assert_near(d, float3::new(1.0, 0.0, -1.0).normalize());
+    }
+
+    #[test]
+    fn equirectangular_directions() {
+        let camera = EquirectangularCamera::from_lookat(ORIGIN, lookat(), Float3::y());
+        assert_near(direction(&camera, 0.5, 0.5), -Float3::z());
+        assert_near(direction(&camera, 0.75, 0.5), Float3::x());
+        assert_near(direction(&camera, 0.25, 0.5), -Float3::x());
+        assert_near(direction(&camera, 0.0, 0.5), Float3::z());
+        assert_near(direction(&camera, 1.0, 0.5), Float3::z());
+        assert_near(direction(&camera, 0.5, 1.0), Float3::y());
+        assert_near(direction(&camera, 0.5, 0.0), -Float3::y());
+    }
+
+    #[test]
+    fn cubemap_faces_point_along_their_axes() {
+        let camera = CubemapCamera::from_lookat(ORIGIN, lookat(), Float3::y());
+        // 上の段が 右, 左, 上, 下の段が 下, 前, 後
+        let faces = [
+            (0, 0, Float3::x()),
+            (1, 0, -Float3::x()),
+            (2, 0, Float3::y()),
+            (0, 1, -Float3::y()),
+            (1, 1, -Float3::z()),
+            (2, 1, Float3::z()),
+        ];
+        for (column, row, axis) in faces {
+            let u = (column as f64 + 0.5) / 3.0;
+            let v = 1.0 - (row as f64 + 0.5) / 2.0;
+            assert_near(direction(&camera, u, v), axis);
+        }
+        // 前の面の右端と右の面の左端は同じ方向
+        let front_right = direction(&camera, 2.0 / 3.0 - 1e-12, 0.25);
+        let right_left = direction(&camera, 1e-12, 0.75);
+        assert!((front_right - right_left).norm() < 1e-6);
+        assert_near(front_right, float3::new(1.0, 0.0, -1.0).normalize());
+    }
+}
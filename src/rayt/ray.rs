@@ -4,11 +4,54 @@ use crate::rayt::*;
 pub struct Ray {
     pub origin: Float3,
     pub direction: Float3,
+    // シャッターが開いている間の時刻. 動く形状はこの時刻の位置で交差判定する
+    pub time: f64,
+    // カメラから出たレイのみSome. テクスチャのフィルタの大きさを決める
+    pub differential: Option<RayDifferential>,
+}
+
+// 画面上で隣の画素 (x, y方向) を通るレイ
+#[derive(Debug, Clone, Copy)]
+pub struct RayDifferential {
+    pub rx_origin: Float3,
+    pub rx_direction: Float3,
+    pub ry_origin: Float3,
+    pub ry_direction: Float3,
 }
 
 impl Ray {
     pub fn new(origin: Float3, direction: Float3) -> Self {
-        Self { origin, direction }
+        Self::with_time(origin, direction, 0.0)
+    }
+
+    pub fn with_time(origin: Float3, direction: Float3, time: f64) -> Self {
+        Self {
+            origin,
+            direction,
+            time,
+            differential: None,
+        }
+    }
+
+    pub fn with_differential(self, differential: RayDifferential) -> Self {
+        Self {
+            differential: Some(differential),
+            ..self
+        }
+    }
+
+    // 隣のレイとの差をs倍する. 1画素に何本もレイを飛ばすときはフィルタを小さくする
+    pub fn scale_differential(self, s: f64) -> Self {
+        let differential = self.differential.map(|d| RayDifferential {
+            rx_origin: self.origin + (d.rx_origin - self.origin) * s,
+            rx_direction: self.direction + (d.rx_direction - self.direction) * s,
+            ry_origin: self.origin + (d.ry_origin - self.origin) * s,
+            ry_direction: self.direction + (d.ry_direction - self.direction) * s,
+        });
+        Self {
+            differential,
+            ..self
+        }
     }
 
     pub fn at(&self, t: f64) -> Float3 {
@@ -16,6 +59,7 @@ impl Ray {
     }
 }
 
+#[derive(Clone)]
 pub struct HitInfo {
     pub t: f64,
     pub p: Float3,
@@ -23,10 +67,140 @@ pub struct HitInfo {
     pub m: Arc<dyn Material>,
     pub u: f64,
     pub v: f64,
+    // 接空間. tangentはuが増える向き, bitangentはvが増える向きで、nと直交する単位ベクトル
+    // uvを持たない形状ではnから適当に決めた向きになる
+    pub tangent: Float3,
+    pub bitangent: Float3,
+    // 正規化していないuvについての位置の微分. uvを持たない形状ではゼロ
+    pub dpdu: Float3,
+    pub dpdv: Float3,
+    // 画面上の位置についての微分. 微分を持つレイの交差のみSome
+    pub differentials: Option<SurfaceDifferentials>,
+    // 一番内側の形状の座標系での位置と法線. Transformで動かしても変わらない
+    pub object_p: Float3,
+    pub object_n: Float3,
+    // 頂点カラーを持つメッシュのみSome
+    pub vertex_color: Option<Float3>,
+    // SceneGraphから作った形状のみSome. どのノードに当たったか
+    pub node: Option<NodeId>,
 }
 
 impl HitInfo {
     pub fn new(t: f64, p: Float3, n: Float3, m: Arc<dyn Material>, u: f64, v: f64) -> Self {
-        HitInfo { t, p, n, m, u, v }
+        let (tangent, bitangent) = math::orthonormal_basis(&n);
+        HitInfo {
+            t,
+            p,
+            n,
+            m,
+            u,
+            v,
+            tangent,
+            bitangent,
+            dpdu: Float3::zeros(),
+            dpdv: Float3::zeros(),
+            differentials: None,
+            object_p: p,
+            object_n: n,
+            vertex_color: None,
+            node: None,
+        }
     }
+
+    // uとvについての位置の微分から接空間を決める (nに対してグラム・シュミットで直交化する)
+    // 潰れていて向きが決まらなければnから適当に決める
+    pub fn with_frame(self, dpdu: Float3, dpdv: Float3) -> Self {
+        let n = self.n;
+        let hit = HitInfo { dpdu, dpdv, ..self };
+        let tangent = match (dpdu - n * n.dot(&dpdu)).try_normalize(EPS) {
+            Some(t) => t,
+            None => {
+                let (tangent, bitangent) = math::orthonormal_basis(&n);
+                return HitInfo {
+                    tangent,
+                    bitangent,
+                    ..hit
+                };
+            }
+        };
+        let bitangent = (dpdv - n * n.dot(&dpdv) - tangent * tangent.dot(&dpdv))
+            .try_normalize(EPS)
+            // vの向きが分からなければ右手系にする
+            .unwrap_or_else(|| n.cross(&tangent));
+        HitInfo {
+            tangent,
+            bitangent,
+            ..hit
+        }
+    }
+
+    // 隣の画素のレイと接平面の交点から、画面上の位置についての位置とuvの微分を求める
+    // 既に求めてあるか、レイが微分を持たなければそのまま
+    pub fn with_differentials(self, ray: &Ray) -> Self {
+        let d = match ray.differential {
+            Some(d) if self.differentials.is_none() => d,
+            _ => return self,
+        };
+        let n = self.n;
+        let plane = n.dot(&self.p);
+        let on_plane = |origin: Float3, direction: Float3| {
+            let t = (plane - n.dot(&origin)) / n.dot(&direction);
+            t.is_finite().then(|| origin + direction * t)
+        };
+        let (px, py) = match (
+            on_plane(d.rx_origin, d.rx_direction),
+            on_plane(d.ry_origin, d.ry_direction),
+        ) {
+            (Some(px), Some(py)) => (px, py),
+            _ => return self,
+        };
+        let dpdx = px - self.p;
+        let dpdy = py - self.p;
+
+        // dpdx = dpdu * dudx + dpdv * dvdx を最小二乗法で解く
+        let (dpdu, dpdv) = (self.dpdu, self.dpdv);
+        let ata00 = dpdu.dot(&dpdu);
+        let ata01 = dpdu.dot(&dpdv);
+        let ata11 = dpdv.dot(&dpdv);
+        let inv_det = (ata00 * ata11 - ata01 * ata01).recip();
+        let inv_det = if inv_det.is_finite() { inv_det } else { 0.0 };
+        let solve = |dp: Float3| {
+            let (b0, b1) = (dpdu.dot(&dp), dpdv.dot(&dp));
+            let finite = |x: f64| {
+                if x.is_finite() {
+                    x.clamp(-1e8, 1e8)
+                } else {
+                    0.0
+                }
+            };
+            (
+                finite((ata11 * b0 - ata01 * b1) * inv_det),
+                finite((ata00 * b1 - ata01 * b0) * inv_det),
+            )
+        };
+        let (dudx, dvdx) = solve(dpdx);
+        let (dudy, dvdy) = solve(dpdy);
+        HitInfo {
+            differentials: Some(SurfaceDifferentials {
+                dpdx,
+                dpdy,
+                dudx,
+                dvdx,
+                dudy,
+                dvdy,
+            }),
+            ..self
+        }
+    }
+}
+
+// 画面上で1画素動いたときの交点の位置とuvの変化
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceDifferentials {
+    pub dpdx: Float3,
+    pub dpdy: Float3,
+    pub dudx: f64,
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64,
 }
@@ -11,17 +11,79 @@ const IMAGE_HEIGHT: u32 = 200;
 const SAMPLES_PER_PIXEL: usize = 10;
 const GAMMA_FACTOR: f64 = 2.2;
 const MAX_RAY_BOUNCE_DEPTH: usize = 50;
+const MIN_DIFFERENTIAL_SCALE: f64 = 0.125;
 
-pub fn backup() {
-    let output_path = Path::new(OUTPUT_FILE_NAME);
+// 出力先やガンマなど、シーンに依存しないレンダリング設定
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    pub output_file: String,
+    pub backup_file: String,
+    pub gamma: f64,
+    pub max_depth: usize,
+    // レンダリング後にウィンドウで結果を表示する (window feature が必要)
+    pub preview: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            output_file: OUTPUT_FILE_NAME.to_string(),
+            backup_file: BACKUP_FILE_NAME.to_string(),
+            gamma: GAMMA_FACTOR,
+            max_depth: MAX_RAY_BOUNCE_DEPTH,
+            preview: true,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.output_file.is_empty() {
+            return Err(Error::InvalidSettings("output_file is empty".to_string()));
+        }
+        if !(self.gamma.is_finite() && self.gamma > 0.0) {
+            return Err(Error::InvalidSettings(format!(
+                "gamma must be positive, got {}",
+                self.gamma
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub fn backup(settings: &RenderSettings) -> Result<()> {
+    let output_path = Path::new(&settings.output_file);
     if output_path.exists() {
-        println!("backup {:?} -> {:?}", OUTPUT_FILE_NAME, BACKUP_FILE_NAME);
-        fs::rename(OUTPUT_FILE_NAME, BACKUP_FILE_NAME).unwrap();
+        println!(
+            "backup {:?} -> {:?}",
+            settings.output_file, settings.backup_file
+        );
+        fs::rename(&settings.output_file, &settings.backup_file)?;
     }
+    Ok(())
+}
+
+// 1ピクセル幅だとuvの計算で0除算になる
+// 隣の画素までのu, vの差
+fn pixel_size(width: u32, height: u32) -> (f64, f64) {
+    (1.0 / (width - 1) as f64, 1.0 / (height - 1) as f64)
+}
+
+fn validate_image_size(width: u32, height: u32, spp: usize) -> Result<()> {
+    if width < 2 || height < 2 {
+        return Err(Error::InvalidSettings(format!(
+            "image must be at least 2x2, got {}x{}",
+            width, height
+        )));
+    }
+    if spp == 0 {
+        return Err(Error::InvalidSettings("spp must be positive".to_string()));
+    }
+    Ok(())
 }
 
 pub trait Scene {
-    fn camera(&self) -> Camera;
+    fn camera(&self) -> Box<dyn Projection>;
     fn trace(&self, ray: Ray) -> Float3;
     fn width(&self) -> u32 {
         IMAGE_WIDTH
@@ -35,10 +97,14 @@ pub trait Scene {
     fn spp(&self) -> usize {
         SAMPLES_PER_PIXEL
     }
+    // traceの結果に掛ける係数. PhysicalCamera::exposureなど
+    fn exposure(&self) -> f64 {
+        1.0
+    }
 }
 
 pub trait SceneWithDepth {
-    fn camera(&self) -> Camera;
+    fn camera(&self) -> Box<dyn Projection>;
     fn trace(&self, ray: Ray, depth: usize) -> Float3;
     fn width(&self) -> u32 {
         IMAGE_WIDTH
@@ -52,13 +118,17 @@ pub trait SceneWithDepth {
     fn spp(&self) -> usize {
         SAMPLES_PER_PIXEL
     }
+    // traceの結果に掛ける係数. PhysicalCamera::exposureなど
+    fn exposure(&self) -> f64 {
+        1.0
+    }
 }
 
 // Syncはpar_iter_mut().for_eachに必要
-pub fn render(scene: impl Scene + Sync) {
-    backup();
-
+pub fn render_image(scene: &(impl Scene + Sync)) -> Result<RgbImage> {
+    validate_image_size(scene.width(), scene.height(), 1)?;
     let camera = scene.camera();
+    let (du, dv) = pixel_size(scene.width(), scene.height());
     let mut img = RgbImage::new(scene.width(), scene.height());
     img.enumerate_pixels_mut()
         .collect::<Vec<(u32, u32, &mut Rgb<u8>)>>()
@@ -66,44 +136,82 @@ pub fn render(scene: impl Scene + Sync) {
         .for_each(|(x, y, pixel)| {
             let u = *x as f64 / (scene.width() - 1) as f64;
             let v = (scene.height() - *y - 1) as f64 / (scene.height() - 1) as f64;
-            let ray = camera.ray(u, v);
-            let rgb = color::float3_to_rgb(scene.trace(ray));
+            let color = camera
+                .generate_ray_differential(u, v, du, dv)
+                .map_or(Float3::zeros(), |ray| scene.trace(ray) * scene.exposure());
+            let rgb = color::float3_to_rgb(color);
             pixel[0] = rgb[0];
             pixel[1] = rgb[1];
             pixel[2] = rgb[2];
         });
-
-    img.save(OUTPUT_FILE_NAME).unwrap();
-    draw_in_window(BACKUP_FILE_NAME, img).unwrap();
+    Ok(img)
 }
 
-pub fn render_aa(scene: impl SceneWithDepth + Sync) {
-    backup();
-
+pub fn render_aa_image(
+    scene: &(impl SceneWithDepth + Sync),
+    settings: &RenderSettings,
+) -> Result<RgbImage> {
+    settings.validate()?;
+    validate_image_size(scene.width(), scene.height(), scene.spp())?;
     let camera = scene.camera();
+    let (du, dv) = pixel_size(scene.width(), scene.height());
+    // 1画素に何本もレイを飛ばすので、テクスチャのフィルタを小さくする
+    let scale = (scene.spp() as f64)
+        .sqrt()
+        .recip()
+        .max(MIN_DIFFERENTIAL_SCALE);
     let mut img = RgbImage::new(scene.width(), scene.height());
     img.enumerate_pixels_mut()
         .collect::<Vec<(u32, u32, &mut Rgb<u8>)>>()
         .par_iter_mut()
         .for_each(|(x, y, pixel)| {
-            let mut pixel_color = (0..scene.spp())
-                .into_iter()
-                .fold(Float3::zeros(), |acc, _| {
-                    let rx = rand::random::<f64>();
-                    let ry = rand::random::<f64>();
-                    let u = (*x as f64 + rx) / (scene.width() - 1) as f64;
-                    let v = ((scene.height() - *y - 1) as f64 + ry) / (scene.height() - 1) as f64;
-                    let ray = camera.ray(u, v);
-                    acc + scene.trace(ray, MAX_RAY_BOUNCE_DEPTH)
-                });
-
-            pixel_color /= scene.spp() as f64;
-            let rgb = color::float3_to_rgb(color::degamma(pixel_color, GAMMA_FACTOR));
+            let mut pixel_color = (0..scene.spp()).fold(Float3::zeros(), |acc, _| {
+                let rx = rand::random::<f64>();
+                let ry = rand::random::<f64>();
+                let u = (*x as f64 + rx) / (scene.width() - 1) as f64;
+                let v = ((scene.height() - *y - 1) as f64 + ry) / (scene.height() - 1) as f64;
+                match camera.generate_ray_differential(u, v, du, dv) {
+                    Some(ray) => {
+                        acc + scene.trace(ray.scale_differential(scale), settings.max_depth)
+                    }
+                    None => acc,
+                }
+            });
+
+            pixel_color *= scene.exposure() / scene.spp() as f64;
+            let rgb = color::float3_to_rgb(color::degamma(pixel_color, settings.gamma));
             pixel[0] = rgb[0];
             pixel[1] = rgb[1];
             pixel[2] = rgb[2];
         });
+    Ok(img)
+}
+
+pub fn render(scene: impl Scene + Sync) -> Result<()> {
+    render_with(scene, &RenderSettings::default())
+}
 
-    img.save(OUTPUT_FILE_NAME).unwrap();
-    draw_in_window(BACKUP_FILE_NAME, img).unwrap();
+pub fn render_with(scene: impl Scene + Sync, settings: &RenderSettings) -> Result<()> {
+    settings.validate()?;
+    let img = render_image(&scene)?;
+    save(img, settings)
+}
+
+pub fn render_aa(scene: impl SceneWithDepth + Sync) -> Result<()> {
+    render_aa_with(scene, &RenderSettings::default())
+}
+
+pub fn render_aa_with(scene: impl SceneWithDepth + Sync, settings: &RenderSettings) -> Result<()> {
+    let img = render_aa_image(&scene, settings)?;
+    save(img, settings)
+}
+
+fn save(img: RgbImage, settings: &RenderSettings) -> Result<()> {
+    backup(settings)?;
+    img.save(&settings.output_file)?;
+    #[cfg(feature = "window")]
+    if settings.preview {
+        draw_in_window(&settings.backup_file, img)?;
+    }
+    Ok(())
 }
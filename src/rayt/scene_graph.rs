@@ -0,0 +1,319 @@
+use crate::rayt::*;
+
+// SceneGraph内のノードの番号
+pub type NodeId = usize;
+
+// シーングラフのノード. 名前, ローカルの変換, 子ノードを持ち、形状を一つ付けられる
+pub struct SceneNode {
+    pub name: String,
+    // 親の座標系に対する変換
+    pub transform: Affine,
+    pub shape: Option<Arc<dyn Shape>>,
+    // Someなら形状のマテリアルを置き換える. 子ノードにも引き継がれる
+    pub material: Option<Arc<dyn Material>>,
+    // falseなら子ノードも含めてレンダリングしない
+    pub visible: bool,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl SceneNode {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            transform: Affine::identity(),
+            shape: None,
+            material: None,
+            visible: true,
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_transform(mut self, transform: Affine) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_shape(mut self, shape: impl Shape + 'static) -> Self {
+        self.shape = Some(Arc::new(shape));
+        self
+    }
+
+    pub fn with_material(mut self, material: Arc<dyn Material>) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn with_visible(mut self, visible: bool) -> Self {
+        self.visible = visible;
+        self
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+// 名前付きノードの木. ノードは番号で参照し、0番がルート
+// build()で可視なノードの形状をワールド座標に配置したBVHにする
+pub struct SceneGraph {
+    nodes: Vec<SceneNode>,
+}
+
+impl Default for SceneGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![SceneNode::new("root")],
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        0
+    }
+
+    // parentの子としてnodeを追加する. parentが無いノードならエラー
+    pub fn add(&mut self, parent: NodeId, mut node: SceneNode) -> Result<NodeId> {
+        if parent >= self.nodes.len() {
+            return Err(Error::InvalidScene(format!(
+                "cannot add {:?} to a missing parent node {}",
+                node.name, parent
+            )));
+        }
+        let id = self.nodes.len();
+        node.parent = Some(parent);
+        node.children.clear();
+        self.nodes.push(node);
+        self.nodes[parent].children.push(id);
+        Ok(id)
+    }
+
+    // 以下のidを取る関数は、このグラフに無いノードならNoneを返す
+    pub fn node(&self, id: NodeId) -> Option<&SceneNode> {
+        self.nodes.get(id)
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> Option<&mut SceneNode> {
+        self.nodes.get_mut(id)
+    }
+
+    // 名前が一致する最初のノード
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+
+    // "root/car/wheel"のようなルートからの名前の列
+    pub fn path(&self, id: NodeId) -> Option<String> {
+        let mut names = vec![self.node(id)?.name.as_str()];
+        let mut current = id;
+        while let Some(parent) = self.nodes[current].parent {
+            names.push(&self.nodes[parent].name);
+            current = parent;
+        }
+        names.reverse();
+        Some(names.join("/"))
+    }
+
+    // ノードの座標系 -> ワールド座標系
+    pub fn world_transform(&self, id: NodeId) -> Option<Affine> {
+        let mut affine = self.node(id)?.transform;
+        let mut current = id;
+        while let Some(parent) = self.nodes[current].parent {
+            affine = affine.then(&self.nodes[parent].transform);
+            current = parent;
+        }
+        Some(affine)
+    }
+
+    // 親を遡って可視かどうか
+    pub fn is_visible(&self, id: NodeId) -> Option<bool> {
+        self.node(id)?;
+        let mut current = Some(id);
+        while let Some(id) = current {
+            if !self.nodes[id].visible {
+                return Some(false);
+            }
+            current = self.nodes[id].parent;
+        }
+        Some(true)
+    }
+
+    // 当たった形状のノード. SceneGraphから作った形状でなければNone
+    pub fn hit_node(&self, hit: &HitInfo) -> Option<&SceneNode> {
+        hit.node.and_then(|id| self.nodes.get(id))
+    }
+
+    // 可視なノードの形状をワールド座標に配置して一つのBVHにまとめる
+    // グラフを変更したら作り直す
+    pub fn build(&self) -> ShapeBvh {
+        let mut shapes: Vec<Box<dyn Shape>> = Vec::new();
+        self.flatten(self.root(), &Affine::identity(), None, &mut shapes);
+        ShapeBvh::new(shapes)
+    }
+
+    fn flatten(
+        &self,
+        id: NodeId,
+        parent_affine: &Affine,
+        parent_material: Option<&Arc<dyn Material>>,
+        shapes: &mut Vec<Box<dyn Shape>>,
+    ) {
+        let node = &self.nodes[id];
+        if !node.visible {
+            return;
+        }
+        let affine = node.transform.then(parent_affine);
+        let material = node.material.as_ref().or(parent_material);
+        if let Some(shape) = &node.shape {
+            let shape = Transform::from_affine(Box::new(shape.clone()), affine);
+            shapes.push(Box::new(NodeShape {
+                shape,
+                node: id,
+                material: material.cloned(),
+            }));
+        }
+        for &child in &node.children {
+            self.flatten(child, &affine, material, shapes);
+        }
+    }
+}
+
+// 当たった形状にノードの番号とマテリアルを付ける
+struct NodeShape {
+    shape: Transform,
+    node: NodeId,
+    material: Option<Arc<dyn Material>>,
+}
+
+impl Shape for NodeShape {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let mut hit = self.shape.hit(ray, t0, t1)?;
+        hit.node = Some(self.node);
+        if let Some(material) = &self.material {
+            hit.m = material.clone();
+        }
+        Some(hit)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.shape.bounds()
+    }
+
+    fn export(&self, exporter: &mut SceneExporter) {
+        // 書き出しではマテリアルの置き換えは反映しない
+        self.shape.export(exporter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_rejects_missing_parent() {
+        let mut graph = SceneGraph::new();
+        let child = graph.add(graph.root(), SceneNode::new("child")).unwrap();
+        assert_eq!(graph.path(child).unwrap(), "root/child");
+        assert!(matches!(
+            graph.add(child + 1, SceneNode::new("orphan")),
+            Err(Error::InvalidScene(_))
+        ));
+        assert!(graph.find("orphan").is_none());
+    }
+
+    fn lambertian(color: Float3) -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Box::new(ColorTexture::new(color))))
+    }
+
+    // root -> a (x方向に2倍) -> b (yに1平行移動) -> leaf (単位球, zに-5平行移動)
+    fn nested() -> (SceneGraph, [NodeId; 3]) {
+        let mut graph = SceneGraph::new();
+        let a = graph
+            .add(
+                graph.root(),
+                SceneNode::new("a").with_transform(Affine::scaling(float3::new(2.0, 1.0, 1.0))),
+            )
+            .unwrap();
+        let b = graph
+            .add(
+                a,
+                SceneNode::new("b").with_transform(Affine::translation(float3::new(0.0, 1.0, 0.0))),
+            )
+            .unwrap();
+        let leaf = graph
+            .add(
+                b,
+                SceneNode::new("leaf")
+                    .with_transform(Affine::translation(float3::new(0.0, 0.0, -5.0)))
+                    .with_shape(Sphere::new(
+                        Float3::zeros(),
+                        1.0,
+                        lambertian(float3::fill(0.5)),
+                    )),
+            )
+            .unwrap();
+        (graph, [a, b, leaf])
+    }
+
+    #[test]
+    fn world_transform_composes_through_parents() {
+        let (graph, [a, b, leaf]) = nested();
+        assert_eq!(graph.path(leaf).unwrap(), "root/a/b/leaf");
+        // 子の変換を先に、親の変換を後に適用する
+        let p = graph
+            .world_transform(leaf)
+            .unwrap()
+            .point(&float3::new(1.0, 0.0, 0.0));
+        assert!((p - float3::new(2.0, 1.0, -5.0)).norm() < 1e-12);
+        let p = graph.world_transform(b).unwrap().point(&Float3::zeros());
+        assert!((p - float3::new(0.0, 1.0, 0.0)).norm() < 1e-12);
+        let p = graph.world_transform(a).unwrap().point(&Float3::x());
+        assert!((p - float3::new(2.0, 0.0, 0.0)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn invisibility_is_inherited() {
+        let (mut graph, [a, b, leaf]) = nested();
+        graph.node_mut(b).unwrap().visible = false;
+        assert_eq!(graph.is_visible(a), Some(true));
+        assert_eq!(graph.is_visible(b), Some(false));
+        assert_eq!(graph.is_visible(leaf), Some(false));
+        let ray = Ray::new(float3::new(0.0, 1.0, 0.0), -Float3::z());
+        assert!(graph.build().hit(&ray, 0.001, f64::MAX).is_none());
+    }
+
+    #[test]
+    fn hit_reports_leaf_and_inherited_material() {
+        let (mut graph, [a, _, leaf]) = nested();
+        let red = lambertian(float3::new(1.0, 0.0, 0.0));
+        graph.node_mut(a).unwrap().material = Some(red.clone());
+        let ray = Ray::new(float3::new(0.0, 1.0, 0.0), -Float3::z());
+        let hit = graph.build().hit(&ray, 0.001, f64::MAX).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-9);
+        assert_eq!(hit.node, Some(leaf));
+        assert_eq!(graph.hit_node(&hit).unwrap().name, "leaf");
+        // 祖先のマテリアルが形状のマテリアルを置き換える
+        assert!(Arc::ptr_eq(&hit.m, &red));
+    }
+
+    #[test]
+    fn foreign_node_ids_are_none() {
+        let (mut graph, _) = nested();
+        let foreign = 100;
+        assert!(graph.node(foreign).is_none());
+        assert!(graph.node_mut(foreign).is_none());
+        assert!(graph.path(foreign).is_none());
+        assert!(graph.world_transform(foreign).is_none());
+        assert!(graph.is_visible(foreign).is_none());
+    }
+}
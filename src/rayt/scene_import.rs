@@ -0,0 +1,198 @@
+use crate::rayt::*;
+use std::path::Path;
+
+// 読み込んだシーンの出力解像度とサンプル数
+#[derive(Debug, Clone)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub spp: usize,
+    // シーンファイルに書かれた出力ファイル名
+    pub filename: Option<String>,
+}
+
+// pbrt-v4 / Mitsuba 3 のシーンファイルから読み込んだシーン
+// 他のレンダラと同じ条件でレンダリングして結果を比較するためのもの
+pub struct ImportedScene {
+    pub world: ShapeList,
+    pub camera_params: CameraParams,
+    pub film: Film,
+    // 一様な環境光 (pbrtのinfinite, Mitsubaのconstant)
+    pub background: Float3,
+    // 画像や空の環境光 (pbrtのinfiniteのfilename, Mitsubaのenvmapとsunsky). あればbackgroundの代わりに使う
+    pub environment: Option<EnvironmentLight>,
+    pub max_depth: Option<usize>,
+    // 非対応の機能や近似したマテリアルについての警告
+    pub warnings: Vec<String>,
+}
+
+impl ImportedScene {
+    // 拡張子で形式を判定する (.pbrt または .xml)
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("pbrt") => load_pbrt(path),
+            Some("xml") => load_mitsuba(path),
+            _ => Err(Error::InvalidScene(format!(
+                "{}: unknown scene format (expected .pbrt or .xml)",
+                path.display()
+            ))),
+        }
+    }
+}
+
+impl SceneWithDepth for ImportedScene {
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(self.camera_params.camera(self.aspect()))
+    }
+
+    fn trace(&self, ray: Ray, depth: usize) -> Float3 {
+        if let Some(environment) = &self.environment {
+            return trace_environment(&self.world, environment, ray, depth);
+        }
+        if let Some(hit) = self.world.hit(&ray, 0.001, f64::MAX) {
+            let emitted = hit.m.emited(&ray, &hit);
+            let scatter_result = if depth > 0 {
+                hit.m.scatter(&ray, &hit)
+            } else {
+                None
+            };
+            if let Some(scatter_info) = scatter_result {
+                emitted
+                    + self
+                        .trace(scatter_info.ray, depth - 1)
+                        .component_mul(&scatter_info.albedo)
+            } else {
+                emitted
+            }
+        } else {
+            self.background
+        }
+    }
+
+    fn width(&self) -> u32 {
+        self.film.width
+    }
+
+    fn height(&self) -> u32 {
+        self.film.height
+    }
+
+    fn spp(&self) -> usize {
+        self.film.spp
+    }
+}
+
+// 画角の基準になる軸
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum FovAxis {
+    X,
+    Y,
+    Diagonal,
+    Smaller,
+    Larger,
+}
+
+// camera_to_worldはカメラ空間 (+zが前, +yが上) からワールドへの行列
+// right_signはカメラ空間で画像の右になるx軸の向き (pbrtは+1, Mitsubaは-1)
+pub(crate) fn camera_from_matrix(
+    camera_to_world: &Mat4,
+    right_sign: f64,
+    fov: f64,
+    fov_axis: FovAxis,
+    width: u32,
+    height: u32,
+) -> CameraParams {
+    let m = camera_to_world;
+    let origin = m.transform_point(&na::Point3::origin()).coords;
+    let forward = m.transform_vector(&vector![0.0, 0.0, 1.0]).normalize();
+    let up = m.transform_vector(&vector![0.0, 1.0, 0.0]).normalize();
+    let right = m.transform_vector(&vector![right_sign, 0.0, 0.0]);
+    let aspect = width as f64 / height as f64;
+
+    // Camera::from_lookatは画像の右がforward x upになる
+    let mirror = right.dot(&forward.cross(&up)) < 0.0;
+
+    let half = (fov.to_radians() * 0.5).tan();
+    let diagonal = (1.0 + aspect * aspect).sqrt();
+    let half_v = match fov_axis {
+        FovAxis::Y => half,
+        FovAxis::X => half / aspect,
+        FovAxis::Diagonal => half / diagonal,
+        FovAxis::Smaller if aspect >= 1.0 => half,
+        FovAxis::Smaller => half / aspect,
+        FovAxis::Larger if aspect >= 1.0 => half / aspect,
+        FovAxis::Larger => half,
+    };
+
+    CameraParams {
+        name: None,
+        origin,
+        lookat: origin + forward,
+        vup: up,
+        vfov: (half_v.atan() * 2.0).to_degrees(),
+        aspect: Some(aspect),
+        mirror,
+        lens_radius: 0.0,
+        focus_distance: None,
+    }
+}
+
+// 行列の回転の部分. 拡大や剪断は取り除く
+pub(crate) fn rotation_of(m: &Mat4) -> Quat {
+    Quat::from_matrix(&m.fixed_slice::<3, 3>(0, 0).into_owned())
+}
+
+// 行列で配置した球. 拡大が一様でなければ平均の半径で近似しwarnを呼ぶ
+pub(crate) fn transformed_sphere(
+    m: &Mat4,
+    center: Float3,
+    radius: f64,
+    material: Arc<dyn Material>,
+    mut warn: impl FnMut(String),
+) -> Sphere {
+    let c = m.transform_point(&na::Point3::from(center)).coords;
+    let scales: Vec<f64> = (0..3).map(|i| m.fixed_slice::<3, 1>(0, i).norm()).collect();
+    let (min, max) = scales
+        .iter()
+        .fold((f64::MAX, 0.0f64), |(lo, hi), &s| (lo.min(s), hi.max(s)));
+    if max - min > EPS * max {
+        warn("non-uniformly scaled sphere was approximated with a uniform scale".to_string());
+    }
+    let scale = scales.iter().sum::<f64>() / 3.0;
+    Sphere::new(c, radius * scale, material)
+}
+
+// 行列で配置したメッシュ. 頂点はワールド座標に焼き込む
+pub(crate) fn transformed_mesh(
+    m: &Mat4,
+    data: &MeshData,
+    material: Arc<dyn Material>,
+) -> TriangleMesh {
+    TriangleMesh::from_data(Arc::new(data.transformed(m)), material)
+}
+
+// 導体の複素屈折率 (rgbごと) から垂直入射の反射率を求める
+pub(crate) fn conductor_reflectance(eta: Float3, k: Float3) -> Float3 {
+    Float3::from_fn(|i, _| {
+        let (n, k) = (eta[i], k[i]);
+        ((n - 1.0).powi(2) + k * k) / ((n + 1.0).powi(2) + k * k)
+    })
+}
+
+// 名前付きの金属の垂直入射の反射率 (sRGB, 線形)
+pub(crate) fn named_metal(name: &str) -> Option<Float3> {
+    Some(match name.to_ascii_lowercase().as_str() {
+        "au" => float3::new(1.0, 0.78, 0.34),
+        "ag" => float3::new(0.97, 0.96, 0.91),
+        "cu" | "cuzn" => float3::new(0.96, 0.64, 0.54),
+        "al" => float3::new(0.91, 0.92, 0.92),
+        "cr" => float3::new(0.55, 0.56, 0.55),
+        "fe" => float3::new(0.56, 0.57, 0.58),
+        "ni" => float3::new(0.66, 0.61, 0.53),
+        "ti" => float3::new(0.54, 0.50, 0.45),
+        "pt" => float3::new(0.67, 0.64, 0.59),
+        "none" => float3::one(),
+        _ => return None,
+    })
+}
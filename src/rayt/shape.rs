@@ -1,7 +1,31 @@
 use crate::rayt::*;
 
-pub trait Shape: Sync {
+pub trait Shape: Sync + Send {
     fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo>;
+    // 境界箱. 無限に広がる形状や境界が分からない形状はNone
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+    // ポリゴンにしてexporterに追加する
+    fn export(&self, exporter: &mut SceneExporter) {
+        exporter.warn(format!(
+            "{} cannot be exported and was skipped",
+            std::any::type_name::<Self>()
+        ));
+    }
+}
+
+// 同じ形状を複数の場所にインスタンス配置する
+impl<T: Shape + ?Sized> Shape for Arc<T> {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        (**self).hit(ray, t0, t1)
+    }
+    fn bounds(&self) -> Option<Aabb> {
+        (**self).bounds()
+    }
+    fn export(&self, exporter: &mut SceneExporter) {
+        (**self).export(exporter)
+    }
 }
 
 pub struct Sphere {
@@ -19,6 +43,51 @@ impl Sphere {
         }
     }
 
+    // 中心を動かして交差判定する. MovingSphereと共通
+    fn hit_at(&self, center: Float3, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let oc = ray.origin - center;
+        let d = ray.direction;
+        let a = d.norm_squared();
+        let b = 2.0 * d.dot(&oc);
+        let c = oc.norm_squared() - self.radius * self.radius;
+        let d = b * b - 4.0 * a * c;
+        if d > 0.0 {
+            let d_sqrt = d.sqrt();
+            for t in [(-b - d_sqrt) / (2.0 * a), (-b + d_sqrt) / (2.0 * a)] {
+                if t0 < t && t < t1 {
+                    return Some(self.hit_info(center, ray, t));
+                }
+            }
+        }
+        None
+    }
+
+    fn hit_info(&self, center: Float3, ray: &Ray, t: f64) -> HitInfo {
+        let p = ray.at(t);
+        // 半径が負の球は法線が内向きになる
+        let n = (p - center) / self.radius;
+        // uvは中心から見た向きで決める
+        let local = (p - center) / self.radius.abs();
+        let (u, v) = Self::uv(local);
+        // uはphi = atan2(z, x)が減る向きに, vは南極から北極へ増える
+        let phi = local.z.atan2(local.x);
+        let theta = local.y.clamp(-1.0, 1.0).asin();
+        // uは一周で1, vは南極から北極までで1なので、長さはその弧の長さ
+        let r = self.radius.abs();
+        let dpdu = vector![phi.sin(), 0.0, -phi.cos()] * (PI2 * r * theta.cos());
+        let dpdv = vector![
+            -theta.sin() * phi.cos(),
+            theta.cos(),
+            -theta.sin() * phi.sin()
+        ] * (PI * r);
+        HitInfo {
+            // 中心を原点にする (動く球でも模様が一緒に動く)
+            object_p: p - center,
+            ..HitInfo::new(t, p, n, Arc::clone(&self.material), u, v).with_frame(dpdu, dpdv)
+        }
+    }
+
+    // pは中心から見た単位ベクトル
     pub fn uv(p: Float3) -> (f64, f64) {
         let phi = p.z.atan2(p.x);
         let theta = p.y.asin();
@@ -29,38 +98,117 @@ impl Sphere {
     }
 }
 
+// 書き出すときの球の分割数
+const SPHERE_SEGMENTS: usize = 32;
+const SPHERE_RINGS: usize = 16;
+
 impl Shape for Sphere {
     fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
-        let oc = ray.origin - self.center;
-        let d = ray.direction;
-        let a = d.norm_squared();
-        let b = 2.0 * d.dot(&oc);
-        let c = oc.norm_squared() - self.radius * self.radius;
-        let d = b * b - 4.0 * a * c;
-        if d > 0.0 {
-            let d_sqrt = d.sqrt();
-            let t = (-b - d_sqrt) / (2.0 * a);
-            if t0 < t && t < t1 {
-                let p = ray.at(t);
-                let n = (p - self.center) / self.radius;
-                let (u, v) = Self::uv(p);
-                let hit = HitInfo::new(t, p, n, Arc::clone(&self.material), u, v);
-                return Some(hit);
-            }
+        self.hit_at(self.center, ray, t0, t1)
+    }
 
-            let t = (-b + d_sqrt) / (2.0 * a);
-            if t0 < t && t < t1 {
-                let p = ray.at(t);
-                let n = (p - self.center) / self.radius;
-                let (u, v) = Self::uv(p);
-                let hit = HitInfo::new(t, p, n, Arc::clone(&self.material), u, v);
-                return Some(hit);
+    fn bounds(&self) -> Option<Aabb> {
+        let r = float3::fill(self.radius.abs());
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+
+    // 経緯線で分割する. 極は縮退した四角形ではなく三角形にする
+    fn export(&self, exporter: &mut SceneExporter) {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        for ring in 0..=SPHERE_RINGS {
+            let theta = PI * ring as f64 / SPHERE_RINGS as f64 - PI * 0.5;
+            for segment in 0..=SPHERE_SEGMENTS {
+                let phi = PI2 * segment as f64 / SPHERE_SEGMENTS as f64 - PI;
+                let n = vector![
+                    theta.cos() * phi.cos(),
+                    theta.sin(),
+                    theta.cos() * phi.sin()
+                ];
+                positions.push(self.center + n * self.radius);
+                normals.push(n);
+                uvs.push(Self::uv(n));
             }
         }
-        None
+        let index = |ring: usize, segment: usize| ring * (SPHERE_SEGMENTS + 1) + segment;
+        let mut faces = Vec::new();
+        for ring in 0..SPHERE_RINGS {
+            for segment in 0..SPHERE_SEGMENTS {
+                let a = index(ring, segment);
+                let b = index(ring, segment + 1);
+                let c = index(ring + 1, segment + 1);
+                let d = index(ring + 1, segment);
+                if ring == 0 {
+                    faces.push(vec![a, d, c]);
+                } else if ring == SPHERE_RINGS - 1 {
+                    faces.push(vec![a, d, b]);
+                } else {
+                    faces.push(vec![a, d, c, b]);
+                }
+            }
+        }
+        exporter.add_polygons("Sphere", positions, normals, uvs, faces, &self.material);
+    }
+}
+
+// 時刻time0からtime1の間に中心がcenter0からcenter1へ等速で動く球
+// 範囲の外の時刻では端の位置に止まる
+pub struct MovingSphere {
+    sphere: Sphere,
+    center1: Float3,
+    time0: f64,
+    time1: f64,
+}
+
+impl MovingSphere {
+    pub fn new(
+        center0: Float3,
+        center1: Float3,
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            sphere: Sphere::new(center0, radius, material),
+            center1,
+            time0,
+            time1,
+        }
+    }
+
+    pub fn center(&self, time: f64) -> Float3 {
+        let t = if self.time1 > self.time0 {
+            ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        self.sphere.center.lerp(&self.center1, t)
     }
 }
 
+impl Shape for MovingSphere {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        self.sphere.hit_at(self.center(ray.time), ray, t0, t1)
+    }
+
+    // 動く範囲全体を覆う
+    fn bounds(&self) -> Option<Aabb> {
+        let r = float3::fill(self.sphere.radius.abs());
+        Some(
+            Aabb::new(self.sphere.center - r, self.sphere.center + r)
+                .union(&Aabb::new(self.center1 - r, self.center1 + r)),
+        )
+    }
+
+    // time0の位置で書き出す
+    fn export(&self, exporter: &mut SceneExporter) {
+        self.sphere.export(exporter);
+    }
+}
+
+#[derive(Default)]
 pub struct ShapeList {
     pub objects: Vec<Box<dyn Shape>>,
 }
@@ -89,7 +237,20 @@ impl Shape for ShapeList {
             }
         }
 
-        hit_info
+        // 一番近い交点だけ画面上の微分を求める
+        hit_info.map(|hit| hit.with_differentials(ray))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.objects.iter().try_fold(Aabb::empty(), |acc, object| {
+            object.bounds().map(|b| acc.union(&b))
+        })
+    }
+
+    fn export(&self, exporter: &mut SceneExporter) {
+        for object in &self.objects {
+            object.export(exporter);
+        }
     }
 }
 
@@ -164,165 +325,74 @@ impl Shape for Rect {
             return None;
         }
 
-        Some(HitInfo::new(
-            t,
-            ray.at(t),
-            axis.into_inner(),
-            Arc::clone(&self.material),
-            (x - self.x0) / (self.x1 - self.x0),
-            (y - self.y0) / (self.y1 - self.y0),
-        ))
-    }
-}
-
-pub struct ShapeBuilder {
-    texture: Option<Box<dyn Texture>>,
-    material: Option<Arc<dyn Material>>,
-    shape: Option<Box<dyn Shape>>,
-    transform: Option<Transform>,
-}
-
-impl ShapeBuilder {
-    pub fn new() -> Self {
-        Self {
-            texture: None,
-            material: None,
-            shape: None,
-            transform: None,
-        }
-    }
-
-    // texture
-    pub fn color_texture(mut self, color: Float3) -> Self {
-        self.texture = Some(Box::new(ColorTexture::new(color)));
-        self
-    }
-
-    pub fn image_texture(mut self, path: &str, scale: (f64, f64)) -> Self {
-        self.texture = Some(Box::new(ImageTexture::new(path, scale)));
-        self
-    }
-
-    // material
-    pub fn material(mut self, material: Arc<dyn Material>) -> Self {
-        self.material = Some(Arc::clone(&material));
-        self
-    }
-
-    pub fn diffuse_light(mut self, intensity: f64) -> Self {
-        self.material = Some(Arc::new(DiffuseLight::new(
-            self.texture.unwrap(),
-            intensity,
-        )));
-        self.texture = None;
-        self
-    }
-
-    pub fn lambertian(mut self) -> Self {
-        self.material = Some(Arc::new(Lambertian::new(self.texture.unwrap())));
-        self.texture = None;
-        self
-    }
-
-    pub fn metal(mut self, fuzz: f64) -> Self {
-        self.material = Some(Arc::new(Metal::new(self.texture.unwrap(), fuzz)));
-        self.texture = None;
-        self
-    }
-
-    pub fn dielectric(mut self, ri: f64) -> Self {
-        self.material = Some(Arc::new(Dielectric::new(ri)));
-        self
-    }
-
-    pub fn sphere(mut self, center: Float3, radius: f64) -> Self {
-        self.shape = Some(Box::new(Sphere::new(
-            center,
-            radius,
-            self.material.unwrap(),
-        )));
-        self.material = None;
-        self
-    }
-
-    pub fn cube(mut self) -> Self {
-        self.shape = Some(Box::new(Cube::new(self.material.unwrap())));
-        self.material = None;
-        self
-    }
-
-    pub fn rect_xy(mut self, x0: f64, x1: f64, y0: f64, y1: f64, k: f64) -> Self {
-        self.shape = Some(Box::new(Rect::new(
-            x0,
-            x1,
-            y0,
-            y1,
-            k,
-            RectAxisType::XY,
-            self.material.unwrap(),
-        )));
-        self.material = None;
-        self
-    }
-    pub fn rect_xz(mut self, x0: f64, x1: f64, y0: f64, y1: f64, k: f64) -> Self {
-        self.shape = Some(Box::new(Rect::new(
-            x0,
-            x1,
-            y0,
-            y1,
-            k,
-            RectAxisType::XZ,
-            self.material.unwrap(),
-        )));
-        self.material = None;
-        self
-    }
-    pub fn rect_yz(mut self, x0: f64, x1: f64, y0: f64, y1: f64, k: f64) -> Self {
-        self.shape = Some(Box::new(Rect::new(
-            x0,
-            x1,
-            y0,
-            y1,
-            k,
-            RectAxisType::YZ,
-            self.material.unwrap(),
-        )));
-        self.material = None;
-        self
-    }
-
-    pub fn build_transform(mut self) -> Self {
-        self.transform = Some(Transform::new(self.shape.unwrap()));
-        self.shape = None;
-        self
-    }
-
-    pub fn translate(mut self, position: Float3) -> Self {
-        if let Some(ref mut t) = self.transform {
-            t.translate_mut(position);
-        }
-        self
-    }
-
-    pub fn rotate(mut self, rotation: Quat) -> Self {
-        if let Some(ref mut t) = self.transform {
-            t.rotate_mut(rotation);
-        }
-        self
+        // uは長方形の1つ目の軸, vは2つ目の軸の向きに増える
+        let (dpdu, dpdv) = match self.axis {
+            RectAxisType::XY => (Float3::x(), Float3::y()),
+            RectAxisType::XZ => (Float3::x(), Float3::z()),
+            RectAxisType::YZ => (Float3::y(), Float3::z()),
+        };
+        let (dpdu, dpdv) = (dpdu * (self.x1 - self.x0), dpdv * (self.y1 - self.y0));
+        Some(
+            HitInfo::new(
+                t,
+                ray.at(t),
+                axis.into_inner(),
+                Arc::clone(&self.material),
+                (x - self.x0) / (self.x1 - self.x0),
+                (y - self.y0) / (self.y1 - self.y0),
+            )
+            .with_frame(dpdu, dpdv),
+        )
     }
 
-    pub fn scale(mut self, scale: Float3) -> Self {
-        if let Some(ref mut t) = self.transform {
-            t.scale_mut(scale);
-        }
-        self
+    fn bounds(&self) -> Option<Aabb> {
+        let (a, b) = match self.axis {
+            RectAxisType::XY => (
+                vector![self.x0, self.y0, self.k],
+                vector![self.x1, self.y1, self.k],
+            ),
+            RectAxisType::XZ => (
+                vector![self.x0, self.k, self.y0],
+                vector![self.x1, self.k, self.y1],
+            ),
+            RectAxisType::YZ => (
+                vector![self.k, self.x0, self.y0],
+                vector![self.k, self.x1, self.y1],
+            ),
+        };
+        Some(Aabb::from_points([&a, &b]))
     }
 
-    pub fn build(self) -> Box<dyn Shape> {
-        if let Some(t) = self.transform {
-            return Box::new(t);
-        }
-        self.shape.unwrap()
+    // 表側(法線の向き)から見て反時計回りの四角形
+    fn export(&self, exporter: &mut SceneExporter) {
+        let k = self.k;
+        let corners = [
+            (self.x0, self.y0, 0.0, 0.0),
+            (self.x1, self.y0, 1.0, 0.0),
+            (self.x1, self.y1, 1.0, 1.0),
+            (self.x0, self.y1, 0.0, 1.0),
+        ];
+        let positions = corners
+            .iter()
+            .map(|&(a, b, _, _)| match self.axis {
+                RectAxisType::XY => vector![a, b, k],
+                RectAxisType::XZ => vector![a, k, b],
+                RectAxisType::YZ => vector![k, a, b],
+            })
+            .collect();
+        let (normal, face) = match self.axis {
+            RectAxisType::XY => (Float3::z(), vec![0, 1, 2, 3]),
+            RectAxisType::XZ => (Float3::y(), vec![3, 2, 1, 0]),
+            RectAxisType::YZ => (Float3::x(), vec![0, 1, 2, 3]),
+        };
+        exporter.add_polygons(
+            "Rect",
+            positions,
+            vec![normal; 4],
+            corners.iter().map(|&(_, _, u, v)| (u, v)).collect(),
+            vec![face],
+            &self.material,
+        );
     }
 }
 
@@ -332,47 +402,31 @@ pub struct Cube {
 
 impl Cube {
     pub fn new(material: Arc<dyn Material>) -> Self {
+        let face = |axis: RectAxisType| -> Box<dyn Shape> {
+            Box::new(Rect::new(
+                -0.5,
+                0.5,
+                -0.5,
+                0.5,
+                0.5,
+                axis,
+                Arc::clone(&material),
+            ))
+        };
+        // 反対側の面. 回転軸は面に平行な軸にする
+        let flip = |shape: Box<dyn Shape>, axis: na::Unit<Float3>| -> Box<dyn Shape> {
+            let mut t = Transform::new(shape);
+            t.rotate_mut(Quat::from_axis_angle(&axis, PI));
+            Box::new(t)
+        };
+
         let mut shapes = ShapeList::new();
-        shapes.push(
-            ShapeBuilder::new()
-                .material(Arc::clone(&material))
-                .rect_xy(-0.5, 0.5, -0.5, 0.5, 0.5)
-                .build(),
-        );
-        shapes.push(
-            ShapeBuilder::new()
-                .material(Arc::clone(&material))
-                .rect_xy(-0.5, 0.5, -0.5, 0.5, 0.5)
-                .build_transform()
-                .rotate(Quat::from_axis_angle(&Float3::y_axis(), PI))
-                .build(),
-        );
-        shapes.push(
-            ShapeBuilder::new()
-                .material(Arc::clone(&material))
-                .rect_yz(-0.5, 0.5, -0.5, 0.5, 0.5)
-                .build(),
-        );
-        shapes.push(
-            ShapeBuilder::new()
-                .material(Arc::clone(&material))
-                .rect_yz(-0.5, 0.5, -0.5, 0.5, 0.5)
-                .build_transform()
-                .rotate(Quat::from_axis_angle(&Float3::y_axis(), PI))
-                .build(),
-        );
-        shapes.push(
-            ShapeBuilder::new()
-                .material(Arc::clone(&material))
-                .rect_xz(-0.5, 0.5, -0.5, 0.5, 0.5)
-                .build(),
-        );
-        shapes.push(
-            ShapeBuilder::new()
-                .material(Arc::clone(&material))
-                .rect_xz(-0.5, 0.5, -0.5, 0.5, 0.5)
-                .build(),
-        );
+        shapes.push(face(RectAxisType::XY));
+        shapes.push(flip(face(RectAxisType::XY), Float3::y_axis()));
+        shapes.push(face(RectAxisType::YZ));
+        shapes.push(flip(face(RectAxisType::YZ), Float3::y_axis()));
+        shapes.push(face(RectAxisType::XZ));
+        shapes.push(flip(face(RectAxisType::XZ), Float3::x_axis()));
 
         Cube { shapes }
     }
@@ -382,4 +436,12 @@ impl Shape for Cube {
     fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
         self.shapes.hit(ray, t0, t1)
     }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.shapes.bounds()
+    }
+
+    fn export(&self, exporter: &mut SceneExporter) {
+        self.shapes.export(exporter);
+    }
 }
@@ -0,0 +1,181 @@
+use crate::rayt::*;
+
+// ShapeBuilderの状態
+// texture -> material -> shape -> transform の順にしか呼べないように型で表す
+pub struct Empty;
+
+#[derive(Clone)]
+pub struct WithTexture(Arc<dyn Texture>);
+
+#[derive(Clone)]
+pub struct WithMaterial(Arc<dyn Material>);
+
+pub struct WithShape {
+    shape: Box<dyn Shape>,
+    transform: Option<Affine>,
+}
+
+// WithTexture, WithMaterialの状態はcloneして複数のshapeで使い回せる
+#[derive(Clone)]
+pub struct ShapeBuilder<S> {
+    state: S,
+}
+
+impl ShapeBuilder<Empty> {
+    pub fn new() -> Self {
+        Self { state: Empty }
+    }
+
+    // texture
+    pub fn texture(self, texture: Arc<dyn Texture>) -> ShapeBuilder<WithTexture> {
+        ShapeBuilder {
+            state: WithTexture(texture),
+        }
+    }
+
+    pub fn color_texture(self, color: Float3) -> ShapeBuilder<WithTexture> {
+        self.texture(Arc::new(ColorTexture::new(color)))
+    }
+
+    pub fn image_texture(self, path: &str, scale: (f64, f64)) -> Result<ShapeBuilder<WithTexture>> {
+        Ok(self.texture(Arc::new(ImageTexture::new(path, scale)?)))
+    }
+
+    // material
+    pub fn material(self, material: Arc<dyn Material>) -> ShapeBuilder<WithMaterial> {
+        ShapeBuilder {
+            state: WithMaterial(material),
+        }
+    }
+
+    pub fn dielectric(self, ri: f64) -> ShapeBuilder<WithMaterial> {
+        self.material(Arc::new(Dielectric::new(ri)))
+    }
+}
+
+impl Default for ShapeBuilder<Empty> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShapeBuilder<WithTexture> {
+    fn texture_box(&self) -> Box<dyn Texture> {
+        Box::new(Arc::clone(&self.state.0))
+    }
+
+    fn material(self, material: Arc<dyn Material>) -> ShapeBuilder<WithMaterial> {
+        ShapeBuilder {
+            state: WithMaterial(material),
+        }
+    }
+
+    pub fn diffuse_light(self, intensity: f64) -> ShapeBuilder<WithMaterial> {
+        let emit = self.texture_box();
+        self.material(Arc::new(DiffuseLight::new(emit, intensity)))
+    }
+
+    pub fn lambertian(self) -> ShapeBuilder<WithMaterial> {
+        let albedo = self.texture_box();
+        self.material(Arc::new(Lambertian::new(albedo)))
+    }
+
+    pub fn metal(self, fuzz: f64) -> ShapeBuilder<WithMaterial> {
+        let albedo = self.texture_box();
+        self.material(Arc::new(Metal::new(albedo, fuzz)))
+    }
+}
+
+impl ShapeBuilder<WithMaterial> {
+    fn material_arc(&self) -> Arc<dyn Material> {
+        Arc::clone(&self.state.0)
+    }
+
+    // 接空間のノーマルマップで法線を変える
+    pub fn normal_map(self, map: Arc<dyn Texture>, strength: f64) -> Self {
+        let material = Arc::new(NormalMap::new(self.material_arc(), Box::new(map), strength));
+        ShapeBuilder {
+            state: WithMaterial(material),
+        }
+    }
+
+    // 高さのテクスチャで法線を変える
+    pub fn bump_map(self, height: Arc<dyn Texture>, scale: f64) -> Self {
+        let material = Arc::new(BumpMap::new(self.material_arc(), Box::new(height), scale));
+        ShapeBuilder {
+            state: WithMaterial(material),
+        }
+    }
+
+    pub fn shape(self, shape: Box<dyn Shape>) -> ShapeBuilder<WithShape> {
+        ShapeBuilder {
+            state: WithShape {
+                shape,
+                transform: None,
+            },
+        }
+    }
+
+    pub fn sphere(self, center: Float3, radius: f64) -> ShapeBuilder<WithShape> {
+        let material = self.material_arc();
+        self.shape(Box::new(Sphere::new(center, radius, material)))
+    }
+
+    pub fn cube(self) -> ShapeBuilder<WithShape> {
+        let material = self.material_arc();
+        self.shape(Box::new(Cube::new(material)))
+    }
+
+    fn rect(
+        self,
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        axis: RectAxisType,
+    ) -> ShapeBuilder<WithShape> {
+        let material = self.material_arc();
+        self.shape(Box::new(Rect::new(x0, x1, y0, y1, k, axis, material)))
+    }
+
+    pub fn rect_xy(self, x0: f64, x1: f64, y0: f64, y1: f64, k: f64) -> ShapeBuilder<WithShape> {
+        self.rect(x0, x1, y0, y1, k, RectAxisType::XY)
+    }
+    pub fn rect_xz(self, x0: f64, x1: f64, y0: f64, y1: f64, k: f64) -> ShapeBuilder<WithShape> {
+        self.rect(x0, x1, y0, y1, k, RectAxisType::XZ)
+    }
+    pub fn rect_yz(self, x0: f64, x1: f64, y0: f64, y1: f64, k: f64) -> ShapeBuilder<WithShape> {
+        self.rect(x0, x1, y0, y1, k, RectAxisType::YZ)
+    }
+}
+
+impl ShapeBuilder<WithShape> {
+    // 以下の変換は呼んだ順に適用される
+    pub fn transform(mut self, affine: &Affine) -> Self {
+        let current = self.state.transform.unwrap_or_default();
+        self.state.transform = Some(current.then(affine));
+        self
+    }
+
+    pub fn translate(self, position: Float3) -> Self {
+        self.transform(&Affine::translation(position))
+    }
+
+    pub fn rotate(self, rotation: Quat) -> Self {
+        self.transform(&Affine::rotation(rotation))
+    }
+
+    pub fn scale(self, scale: Float3) -> Self {
+        self.transform(&Affine::scaling(scale))
+    }
+
+    // 変換が一度も指定されていなければTransformで包まない
+    pub fn build(self) -> Box<dyn Shape> {
+        let WithShape { shape, transform } = self.state;
+        match transform {
+            Some(affine) => Box::new(Transform::from_affine(shape, affine)),
+            None => shape,
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use crate::rayt::*;
+
+// 見かけの直径 (度)
+pub const SUN_ANGULAR_DIAMETER: f64 = 0.53;
+// 大気の外の太陽の輝度 (kcd/m²)
+const SUN_LUMINANCE: f64 = 2.0e6;
+// 空の輝度 (kcd/m²) をレンダラの明るさにする倍率. 晴れた日中の地面がおよそ1になる
+const SKY_LUMINANCE_SCALE: f64 = 0.03;
+// 作る環境光の画像の大きさ. 空はなめらかなので粗くてよい
+const SKY_MAP_WIDTH: usize = 512;
+const SKY_MAP_HEIGHT: usize = 256;
+// 赤, 緑, 青の代表の波長 (μm). 太陽の光が大気で弱まる量に使う
+const RGB_WAVELENGTHS: [f64; 3] = [0.65, 0.57, 0.475];
+
+// Perezの分布関数の係数 A..E (濁度の1次式)
+type Perez = [f64; 5];
+
+fn perez(t: f64, coefficients: [[f64; 2]; 5]) -> Perez {
+    coefficients.map(|[a, b]| a * t + b)
+}
+
+// Perez et al. の天空の明るさの分布. thetaは天頂からの角度, gammaは太陽からの角度
+fn perez_distribution(p: &Perez, cos_theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = *p;
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+// 天頂の色度. [T², T, 1] x M x [θs³, θs², θs, 1]
+fn zenith_chromaticity(t: f64, theta_s: f64, m: [[f64; 4]; 3]) -> f64 {
+    let ts = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+    let tt = [t * t, t, 1.0];
+    (0..3)
+        .map(|i| tt[i] * (0..4).map(|j| m[i][j] * ts[j]).sum::<f64>())
+        .sum()
+}
+
+// xyYからリニアなsRGB
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Float3 {
+    if y <= 0.0 {
+        return Float3::zeros();
+    }
+    let cx = x * luminance / y;
+    let cz = (1.0 - x - y) * luminance / y;
+    let cy = luminance;
+    float3::new(
+        3.2406 * cx - 1.5372 * cy - 0.4986 * cz,
+        -0.9689 * cx + 1.8758 * cy + 0.0415 * cz,
+        0.0557 * cx - 0.2040 * cy + 1.0570 * cz,
+    )
+    .map(|c| c.max(0.0))
+}
+
+// Preethamの昼間の空のモデルと太陽
+// 太陽の向き, 大気の濁度 (2: とても澄んだ空 .. 10: かすんだ空), 地面の反射率で決まる
+// environmentで環境光にすると、空と太陽の両方を明るさに応じてサンプリングする
+#[derive(Debug, Clone, PartialEq)]
+pub struct SunSky {
+    sun_direction: Float3,
+    turbidity: f64,
+    ground_albedo: Float3,
+    // 太陽の見かけの直径 (度)
+    sun_size: f64,
+    intensity: f64,
+}
+
+impl SunSky {
+    // sun_directionは地面から太陽への向き
+    pub fn new(sun_direction: Float3) -> Self {
+        Self {
+            sun_direction: sun_direction.normalize(),
+            turbidity: 3.0,
+            ground_albedo: float3::fill(0.3),
+            sun_size: SUN_ANGULAR_DIAMETER,
+            intensity: 1.0,
+        }
+    }
+
+    // 高度 (地平線からの角度) と方位 (-zから+xへ回る角度) を度で指定する
+    pub fn from_angles(elevation: f64, azimuth: f64) -> Self {
+        let (el, az) = (elevation.to_radians(), azimuth.to_radians());
+        Self::new(float3::new(
+            el.cos() * az.sin(),
+            el.sin(),
+            -el.cos() * az.cos(),
+        ))
+    }
+
+    // Preethamのモデルが合うのはおよそ2..10
+    pub fn with_turbidity(mut self, turbidity: f64) -> Self {
+        self.turbidity = turbidity.clamp(1.7, 10.0);
+        self
+    }
+
+    pub fn with_ground_albedo(mut self, albedo: Float3) -> Self {
+        self.ground_albedo = albedo;
+        self
+    }
+
+    pub fn with_sun_size(mut self, angular_diameter: f64) -> Self {
+        self.sun_size = angular_diameter;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn sun_direction(&self) -> Float3 {
+        self.sun_direction
+    }
+
+    // 太陽の天頂角. 地平線の下の太陽は地平線にあるものとして空を計算する
+    fn sun_theta(&self) -> f64 {
+        self.sun_direction.y.clamp(0.0, 1.0).acos()
+    }
+
+    // 地平線より上の方向dの空の明るさ (太陽の円盤を除く)
+    pub fn sky_radiance(&self, d: &Float3) -> Float3 {
+        let d = d.normalize();
+        let t = self.turbidity;
+        let theta_s = self.sun_theta();
+        // 地平線の近くで発散しないようにする
+        let cos_theta = d.y.max(0.01);
+        let gamma = d.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let p_y = perez(
+            t,
+            [
+                [0.1787, -1.4630],
+                [-0.3554, 0.4275],
+                [-0.0227, 5.3251],
+                [0.1206, -2.5771],
+                [-0.0670, 0.3703],
+            ],
+        );
+        let p_x = perez(
+            t,
+            [
+                [-0.0193, -0.2592],
+                [-0.0665, 0.0008],
+                [-0.0004, 0.2125],
+                [-0.0641, -0.8989],
+                [-0.0033, 0.0452],
+            ],
+        );
+        let p_yc = perez(
+            t,
+            [
+                [-0.0167, -0.2608],
+                [-0.0950, 0.0092],
+                [-0.0079, 0.2102],
+                [-0.0441, -1.6537],
+                [-0.0109, 0.0529],
+            ],
+        );
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = zenith_chromaticity(
+            t,
+            theta_s,
+            [
+                [0.00166, -0.00375, 0.00209, 0.0],
+                [-0.02903, 0.06377, -0.03202, 0.00394],
+                [0.11693, -0.21196, 0.06052, 0.25886],
+            ],
+        );
+        let zenith_yc = zenith_chromaticity(
+            t,
+            theta_s,
+            [
+                [0.00275, -0.00610, 0.00317, 0.0],
+                [-0.04214, 0.08970, -0.04153, 0.00516],
+                [0.15346, -0.26756, 0.06670, 0.26688],
+            ],
+        );
+
+        // 天頂の値に対する比で分布させる
+        let relative = |p: &Perez| {
+            perez_distribution(p, cos_theta, gamma) / perez_distribution(p, 1.0, theta_s)
+        };
+        let y = zenith_y * relative(&p_y);
+        let x = zenith_x * relative(&p_x);
+        let yc = zenith_yc * relative(&p_yc);
+        xyy_to_rgb(x, yc, y.max(0.0) * SKY_LUMINANCE_SCALE) * self.intensity
+    }
+
+    // 大気を通って弱まった太陽の円盤の明るさ. 地平線の下なら0
+    // Rayleigh散乱とエアロゾル (Ångströmの式) による透過率だけを考える
+    pub fn sun_radiance(&self) -> Float3 {
+        if self.sun_direction.y <= 0.0 {
+            return Float3::zeros();
+        }
+        let theta_s = self.sun_theta();
+        // 相対的な大気の厚さ (Kasten)
+        let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = RGB_WAVELENGTHS.map(|lambda| {
+            let rayleigh = 0.008735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+            (-(rayleigh + aerosol) * air_mass).exp()
+        });
+        Float3::from(transmittance) * (SUN_LUMINANCE * SKY_LUMINANCE_SCALE * self.intensity)
+    }
+
+    pub fn sun(&self) -> SunLight {
+        SunLight::new(self.sun_direction, self.sun_size, self.sun_radiance())
+    }
+
+    // 地平線の下は、空と太陽に照らされた地面の色にする
+    pub fn environment(&self) -> EnvironmentLight {
+        let (w, h) = (SKY_MAP_WIDTH, SKY_MAP_HEIGHT);
+        let mut pixels = vec![Float3::zeros(); w * h];
+        // 空からの水平な面の放射照度
+        let mut irradiance = Float3::zeros();
+        for y in 0..h / 2 {
+            let v = (y as f64 + 0.5) / h as f64;
+            let solid_angle = (PI2 / w as f64) * (PI / h as f64) * (PI * v).sin();
+            for x in 0..w {
+                let d = equirect_direction((x as f64 + 0.5) / w as f64, v);
+                let l = self.sky_radiance(&d);
+                irradiance += l * (d.y * solid_angle);
+                pixels[y * w + x] = l;
+            }
+        }
+        let sun = self.sun();
+        irradiance += sun.radiance() * (sun.solid_angle() * self.sun_direction.y.max(0.0));
+        let ground = self.ground_albedo.component_mul(&irradiance) * FRAC_1_PI;
+        for pixel in &mut pixels[h / 2 * w..] {
+            *pixel = ground;
+        }
+        let texture =
+            ImageTexture::from_pixels(pixels, w, h, (1.0, 1.0)).expect("the sky map size is fixed");
+        EnvironmentLight::from_texture(texture).with_sun(sun)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TURBIDITIES: [f64; 4] = [2.0, 3.0, 6.0, 10.0];
+    const ELEVATIONS: [f64; 4] = [5.0, 20.0, 45.0, 80.0];
+
+    #[test]
+    fn zenith_luminance_is_finite_and_positive() {
+        for t in TURBIDITIES {
+            for elevation in ELEVATIONS {
+                let sky = SunSky::from_angles(elevation, 30.0).with_turbidity(t);
+                let y = luminance(sky.sky_radiance(&Float3::y()));
+                assert!(
+                    y.is_finite() && y > 0.0,
+                    "t={} elevation={}: {}",
+                    t,
+                    elevation,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sky_is_brighter_near_the_sun() {
+        for t in TURBIDITIES {
+            for elevation in ELEVATIONS {
+                let sky = SunSky::from_angles(elevation, 30.0).with_turbidity(t);
+                // 太陽から少し離れた方向と、同じ高さで反対側の方向
+                // 天頂に近い太陽では反対側も太陽に近くなるので、高さは60度までにする
+                let near = SunSky::from_angles(elevation.min(60.0), 40.0).sun_direction();
+                let opposite = SunSky::from_angles(elevation.min(60.0), 210.0).sun_direction();
+                let near = luminance(sky.sky_radiance(&near));
+                let opposite = luminance(sky.sky_radiance(&opposite));
+                assert!(near > opposite, "t={} elevation={}", t, elevation);
+            }
+        }
+    }
+}
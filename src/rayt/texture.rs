@@ -2,6 +2,20 @@ use crate::rayt::*;
 
 pub trait Texture: Sync + Send {
     fn value(&self, u: f64, v: f64, p: Float3) -> Float3;
+    // マテリアルからはこちらを呼ぶ. uv, p以外の交差情報(頂点カラーなど)を使うテクスチャはこれを実装する
+    fn value_at(&self, hit: &HitInfo) -> Float3 {
+        self.value(hit.u, hit.v, hit.p)
+    }
+}
+
+// Arcで共有したテクスチャを複数のマテリアルから参照できるようにする
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn value(&self, u: f64, v: f64, p: Float3) -> Float3 {
+        (**self).value(u, v, p)
+    }
+    fn value_at(&self, hit: &HitInfo) -> Float3 {
+        (**self).value_at(hit)
+    }
 }
 
 pub struct ColorTexture {
@@ -24,61 +38,646 @@ pub struct CheckerTexture {
     odd: Box<dyn Texture>,
     even: Box<dyn Texture>,
     freq: f64,
+    filtered: bool,
 }
 
 impl CheckerTexture {
     pub fn new(odd: Box<dyn Texture>, even: Box<dyn Texture>, freq: f64) -> Self {
-        Self { odd, even, freq }
+        Self {
+            odd,
+            even,
+            freq,
+            filtered: false,
+        }
+    }
+
+    // 画面上の微分があれば、画素が覆う範囲で市松模様を平均する (遠くでちらつかない)
+    pub fn with_filtering(mut self, filtered: bool) -> Self {
+        self.filtered = filtered;
+        self
+    }
+
+    fn is_odd(&self, p: Float3) -> bool {
+        let sines = p.iter().fold(1.0, |acc, x| acc * (x * self.freq).sin());
+        sines < 0.0
+    }
+
+    // 各軸のsign(sin(freq x))の積が-1なら奇数のマス. 軸ごとに独立なので、
+    // 箱型の範囲の平均は軸ごとの平均の積になる. 奇数のマスの割合を返す
+    fn odd_fraction(&self, p: Float3, d: &SurfaceDifferentials) -> f64 {
+        let period = PI2 / self.freq.abs();
+        // sign(sin(freq x))の0からxまでの積分 (三角波)
+        let integral = |x: f64| {
+            let y = x.rem_euclid(period);
+            if y < period * 0.5 {
+                y
+            } else {
+                period - y
+            }
+        };
+        let mean = (0..3).fold(1.0, |acc, i| {
+            let x = p[i];
+            let width = d.dpdx[i].abs().max(d.dpdy[i].abs());
+            let sign = if width < EPS {
+                (x * self.freq).sin().signum()
+            } else {
+                (integral(x + width) - integral(x - width)) / (2.0 * width)
+            };
+            acc * sign
+        });
+        (1.0 - mean) * 0.5
     }
 }
 
 impl Texture for CheckerTexture {
     fn value(&self, u: f64, v: f64, p: Float3) -> Float3 {
-        let sines = p.iter().fold(1.0, |acc, x| acc * (x * self.freq).sin());
-        if sines < 0.0 {
+        if self.is_odd(p) {
             self.odd.value(u, v, p)
         } else {
             self.even.value(u, v, p)
         }
     }
+
+    fn value_at(&self, hit: &HitInfo) -> Float3 {
+        if let (true, Some(d)) = (self.filtered, &hit.differentials) {
+            let odd = self.odd_fraction(hit.p, d);
+            return self.even.value_at(hit).lerp(&self.odd.value_at(hit), odd);
+        }
+        if self.is_odd(hit.p) {
+            self.odd.value_at(hit)
+        } else {
+            self.even.value_at(hit)
+        }
+    }
 }
 
-pub struct ImageTexture {
+// 交差した三角形の頂点カラーを補間した値
+// 頂点カラーの無い形状ではfallbackを使う
+pub struct VertexColorTexture {
+    fallback: Float3,
+}
+
+impl VertexColorTexture {
+    pub const fn new(fallback: Float3) -> Self {
+        Self { fallback }
+    }
+}
+
+impl Texture for VertexColorTexture {
+    fn value(&self, _: f64, _: f64, _: Float3) -> Float3 {
+        self.fallback
+    }
+
+    fn value_at(&self, hit: &HitInfo) -> Float3 {
+        hit.vertex_color.unwrap_or(self.fallback)
+    }
+}
+
+// 8bitの画像のチャンネルの値の意味
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    // 色の画像. リニアに変換して使う
+    Srgb,
+    // ノーマルマップや高さなどのデータ. 値をそのまま使う
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn decode(&self, r: u8, g: u8, b: u8) -> Float3 {
+        match self {
+            ColorSpace::Srgb => float3::from_rgb(r, g, b),
+            ColorSpace::Linear => float3::from_rgb_linear(r, g, b),
+        }
+    }
+
+    // 0..1に正規化したチャンネルの値をリニアにする
+    pub fn to_linear(&self, c: Float3) -> Float3 {
+        match self {
+            ColorSpace::Srgb => c.map(color::srgb_to_linear),
+            ColorSpace::Linear => c,
+        }
+    }
+}
+
+// 画像の外側のuvの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+    Repeat,
+    // 端のピクセルを伸ばす
+    Clamp,
+    // 1枚ごとに反転して繰り返す
+    Mirror,
+}
+
+impl WrapMode {
+    // ピクセルの位置をsize未満に収める
+    fn wrap(&self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Clamp => i.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * size);
+                if m < size {
+                    m
+                } else {
+                    2 * size - 1 - m
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFilter {
+    // 以下の3つは元の解像度の画像だけを使う
+    Nearest,
+    Bilinear,
+    // Catmull-Romの4x4
+    Bicubic,
+    // 画面上の大きさに合う2段のミップマップをバイリニアで補間する
+    Trilinear,
+    // 画面上の楕円の範囲をガウス関数で重み付けする. 斜めから見た面もぼやけにくい
+    Ewa,
+}
+
+// EWAで楕円の短径を長径のこの割合までしか小さくしない (計算量の上限)
+const EWA_MAX_ANISOTROPY: f64 = 8.0;
+// EWAのガウス関数の鋭さ
+const EWA_ALPHA: f64 = 2.0;
+
+// ミップマップの1段
+struct MipLevel {
     pixels: Vec<Float3>,
     width: usize,
     height: usize,
+}
+
+impl MipLevel {
+    // 縦横を半分にする. 奇数なら端のピクセルは余った分だけで平均する
+    fn downsample(&self) -> Self {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Float3::zeros();
+                let mut count = 0.0;
+                for sy in (2 * y)..(2 * y + 2).min(self.height) {
+                    for sx in (2 * x)..(2 * x + 2).min(self.width) {
+                        sum += self.pixels[sx + self.width * sy];
+                        count += 1.0;
+                    }
+                }
+                pixels.push(sum / count);
+            }
+        }
+        Self {
+            pixels,
+            width,
+            height,
+        }
+    }
+
+    fn texel(&self, x: i64, y: i64, wrap: WrapMode) -> Float3 {
+        let tx = wrap.wrap(x, self.width);
+        let ty = wrap.wrap(y, self.height);
+        self.pixels[tx + self.width * ty]
+    }
+
+    // s, tは画像全体が[0, 1]になる座標. ピクセルの中心が整数になる座標に直す
+    fn texel_position(&self, s: f64, t: f64) -> (f64, f64) {
+        (s * self.width as f64 - 0.5, t * self.height as f64 - 0.5)
+    }
+
+    fn nearest(&self, s: f64, t: f64, wrap: WrapMode) -> Float3 {
+        let (x, y) = self.texel_position(s, t);
+        self.texel(x.round() as i64, y.round() as i64, wrap)
+    }
+
+    fn bilinear(&self, s: f64, t: f64, wrap: WrapMode) -> Float3 {
+        let (x, y) = self.texel_position(s, t);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self
+            .texel(x0, y0, wrap)
+            .lerp(&self.texel(x0 + 1, y0, wrap), tx);
+        let bottom = self
+            .texel(x0, y0 + 1, wrap)
+            .lerp(&self.texel(x0 + 1, y0 + 1, wrap), tx);
+        top.lerp(&bottom, ty)
+    }
+
+    fn bicubic(&self, s: f64, t: f64, wrap: WrapMode) -> Float3 {
+        let (x, y) = self.texel_position(s, t);
+        let (x0, y0) = (x.floor(), y.floor());
+        let wx = catmull_rom_weights(x - x0);
+        let wy = catmull_rom_weights(y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let mut sum = Float3::zeros();
+        for (j, wy) in wy.iter().enumerate() {
+            for (i, wx) in wx.iter().enumerate() {
+                sum += self.texel(x0 + i as i64 - 1, y0 + j as i64 - 1, wrap) * (wx * wy);
+            }
+        }
+        // オーバーシュートで負にならないようにする
+        sum.map(|c| c.max(0.0))
+    }
+
+    // ds0, ds1は画面上で1画素動いたときのs, tの変化 (楕円の2つの軸)
+    fn ewa(&self, s: f64, t: f64, ds0: (f64, f64), ds1: (f64, f64), wrap: WrapMode) -> Float3 {
+        let (x, y) = self.texel_position(s, t);
+        let (w, h) = (self.width as f64, self.height as f64);
+        let (dx0, dy0) = (ds0.0 * w, ds0.1 * h);
+        let (dx1, dy1) = (ds1.0 * w, ds1.1 * h);
+        // 楕円 a x^2 + b x y + c y^2 < 1 の係数. 1を足して少なくとも1ピクセルの大きさにする
+        let a = dy0 * dy0 + dy1 * dy1 + 1.0;
+        let b = -2.0 * (dx0 * dy0 + dx1 * dy1);
+        let c = dx0 * dx0 + dx1 * dx1 + 1.0;
+        let inv_f = (a * c - b * b * 0.25).recip();
+        let (a, b, c) = (a * inv_f, b * inv_f, c * inv_f);
+
+        // 楕円を囲む長方形
+        let det = -b * b + 4.0 * a * c;
+        let inv_det = det.recip();
+        // 画像より大きい範囲は読まない (繰り返しても平均は同じなので)
+        let x_radius = (2.0 * inv_det * (det * c).sqrt()).min(w);
+        let y_radius = (2.0 * inv_det * (det * a).sqrt()).min(h);
+        let (x0, x1) = ((x - x_radius).ceil() as i64, (x + x_radius).floor() as i64);
+        let (y0, y1) = ((y - y_radius).ceil() as i64, (y + y_radius).floor() as i64);
+
+        let mut sum = Float3::zeros();
+        let mut weight_sum = 0.0;
+        for ty in y0..=y1 {
+            let dy = ty as f64 - y;
+            for tx in x0..=x1 {
+                let dx = tx as f64 - x;
+                let r2 = a * dx * dx + b * dx * dy + c * dy * dy;
+                if r2 < 1.0 {
+                    let weight = (-EWA_ALPHA * r2).exp() - (-EWA_ALPHA).exp();
+                    sum += self.texel(tx, ty, wrap) * weight;
+                    weight_sum += weight;
+                }
+            }
+        }
+        if weight_sum > 0.0 {
+            sum / weight_sum
+        } else {
+            self.bilinear(s, t, wrap)
+        }
+    }
+}
+
+// Catmull-Romスプラインの4点の重み
+fn catmull_rom_weights(t: f64) -> [f64; 4] {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    [
+        0.5 * (-t3 + 2.0 * t2 - t),
+        0.5 * (3.0 * t3 - 5.0 * t2 + 2.0),
+        0.5 * (-3.0 * t3 + 4.0 * t2 + t),
+        0.5 * (t3 - t2),
+    ]
+}
+
+// NaNや無限大は黒にする
+fn finite(c: Float3) -> Float3 {
+    c.map(|x| if x.is_finite() { x } else { 0.0 })
+}
+
+// image::openはRadiance HDRを8bitにしてしまうので、浮動小数点数のまま読む
+fn read_hdr(path: &str) -> Result<(Vec<Float3>, usize, usize)> {
+    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let decoder = image::codecs::hdr::HdrDecoder::new(reader)?;
+    let meta = decoder.metadata();
+    let pixels = decoder
+        .read_image_hdr()?
+        .iter()
+        .map(|px| finite(float3::new(px[0] as f64, px[1] as f64, px[2] as f64)))
+        .collect();
+    Ok((pixels, meta.width as usize, meta.height as usize))
+}
+
+// 作るときに1x1までのミップマップを用意しておく
+// ピクセルはリニアな浮動小数点数で持ち、1を超える値 (HDR) もそのまま使う
+// cloneしてもピクセルは共有する
+#[derive(Clone)]
+pub struct ImageTexture {
+    // 0が元の解像度
+    levels: Arc<Vec<MipLevel>>,
     scale: (f64, f64),
+    filter: TextureFilter,
+    wrap: WrapMode,
 }
 
 impl ImageTexture {
-    pub fn new(path: &str, scale: (f64, f64)) -> Self {
-        let rgbimg = image::open(path).unwrap().to_rgb8();
-        let (w, h) = rgbimg.dimensions();
-        let mut image = vec![Float3::zeros(); (w * h) as usize];
-        for (i, (_, _, pixel)) in image.iter_mut().zip(rgbimg.enumerate_pixels()) {
-            *i = float3::from_rgb(pixel[0], pixel[1], pixel[2]);
+    // 色の画像 (sRGB) を読む
+    pub fn new(path: &str, scale: (f64, f64)) -> Result<Self> {
+        Self::open(path, scale, ColorSpace::Srgb)
+    }
+
+    // 色ではないデータ (ノーマルマップや高さ) 用
+    pub fn new_linear(path: &str, scale: (f64, f64)) -> Result<Self> {
+        Self::open(path, scale, ColorSpace::Linear)
+    }
+
+    // 同じファイルは一度だけ読んで共有する (TextureCache::global). 書き換えられたファイルは読み直す
+    pub fn open(path: &str, scale: (f64, f64), color_space: ColorSpace) -> Result<Self> {
+        TextureCache::global().load(path, scale, color_space)
+    }
+
+    // キャッシュを使わずに読む
+    // Radiance HDRとOpenEXRはリニアな値なのでcolor_spaceを使わない. 8bit, 16bitの画像は0..1にしてから変換する
+    pub fn decode(path: &str, scale: (f64, f64), color_space: ColorSpace) -> Result<Self> {
+        let is_hdr = std::path::Path::new(path)
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("hdr"));
+        let (pixels, w, h) = if is_hdr {
+            read_hdr(path)?
+        } else {
+            let img = image::open(path)?;
+            let float = matches!(
+                img.color(),
+                image::ColorType::Rgb32F | image::ColorType::Rgba32F
+            );
+            let rgb = img.to_rgb32f();
+            let (w, h) = rgb.dimensions();
+            let pixels = rgb
+                .pixels()
+                .map(|px| {
+                    let c = finite(float3::new(px[0] as f64, px[1] as f64, px[2] as f64));
+                    if float {
+                        c
+                    } else {
+                        color_space.to_linear(c)
+                    }
+                })
+                .collect();
+            (pixels, w as usize, h as usize)
+        };
+        Self::from_pixels(pixels, w, h, scale)
+            .map_err(|_| Error::InvalidScene(format!("{} is an empty image", path)))
+    }
+
+    // 左上から行優先で並んだリニアなピクセル
+    pub fn from_pixels(
+        pixels: Vec<Float3>,
+        width: usize,
+        height: usize,
+        scale: (f64, f64),
+    ) -> Result<Self> {
+        if width == 0 || height == 0 || pixels.len() != width * height {
+            return Err(Error::InvalidScene(format!(
+                "{} pixels do not make a {}x{} image",
+                pixels.len(),
+                width,
+                height
+            )));
         }
-        Self {
-            pixels: image,
-            width: w as usize,
-            height: h as usize,
+        let mut levels = vec![MipLevel {
+            pixels,
+            width,
+            height,
+        }];
+        while let Some(last) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+            let next = last.downsample();
+            levels.push(next);
+        }
+        Ok(Self {
+            levels: Arc::new(levels),
             scale,
+            filter: TextureFilter::Trilinear,
+            wrap: WrapMode::Repeat,
+        })
+    }
+
+    pub fn with_scale(mut self, scale: (f64, f64)) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: TextureFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    // 元の解像度の幅と高さ
+    pub fn size(&self) -> (usize, usize) {
+        (self.levels[0].width, self.levels[0].height)
+    }
+
+    // 元の解像度のピクセル. 左上が(0, 0)
+    pub fn pixel(&self, x: usize, y: usize) -> Float3 {
+        let level = &self.levels[0];
+        level.pixels[y * level.width + x]
+    }
+
+    pub fn mip_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    // 連続的なミップマップの段. widthは画像全体を1とした大きさ
+    fn level_of_detail(&self, width: f64) -> f64 {
+        (self.levels.len() - 1) as f64 + width.max(1e-8).log2()
+    }
+
+    // lodの前後の段をそれぞれfで読んで補間する
+    fn lerp_levels(&self, lod: f64, f: impl Fn(&MipLevel) -> Float3) -> Float3 {
+        let last = self.levels.len() - 1;
+        if lod <= 0.0 {
+            return f(&self.levels[0]);
+        }
+        if lod >= last as f64 {
+            // 1x1の段は平均の色そのもの
+            return self.levels[last].pixels[0];
         }
+        let i = lod.floor() as usize;
+        f(&self.levels[i]).lerp(&f(&self.levels[i + 1]), lod - i as f64)
     }
 
-    fn sample(&self, u: i64, v: i64) -> Float3 {
-        let tu = (u as usize).clamp(0, self.width - 1);
-        let tv = (v as usize).clamp(0, self.height - 1);
-        self.pixels[tu + self.width * tv]
+    fn filtered(&self, s: f64, t: f64, ds0: (f64, f64), ds1: (f64, f64)) -> Float3 {
+        let wrap = self.wrap;
+        match self.filter {
+            TextureFilter::Nearest => self.levels[0].nearest(s, t, wrap),
+            TextureFilter::Bilinear => self.levels[0].bilinear(s, t, wrap),
+            TextureFilter::Bicubic => self.levels[0].bicubic(s, t, wrap),
+            TextureFilter::Trilinear => {
+                let width = 2.0
+                    * ds0
+                        .0
+                        .abs()
+                        .max(ds0.1.abs())
+                        .max(ds1.0.abs())
+                        .max(ds1.1.abs());
+                self.lerp_levels(self.level_of_detail(width), |l| l.bilinear(s, t, wrap))
+            }
+            TextureFilter::Ewa => {
+                // ds0を長径にする
+                let len = |d: (f64, f64)| (d.0 * d.0 + d.1 * d.1).sqrt();
+                let (ds0, mut ds1) = if len(ds0) < len(ds1) {
+                    (ds1, ds0)
+                } else {
+                    (ds0, ds1)
+                };
+                let major = len(ds0);
+                let mut minor = len(ds1);
+                if minor == 0.0 {
+                    return self.levels[0].bilinear(s, t, wrap);
+                }
+                // 細長すぎる楕円は短径を伸ばして、粗い段で読む
+                if minor * EWA_MAX_ANISOTROPY < major {
+                    let k = major / (minor * EWA_MAX_ANISOTROPY);
+                    ds1 = (ds1.0 * k, ds1.1 * k);
+                    minor *= k;
+                }
+                self.lerp_levels(self.level_of_detail(minor), |l| l.ewa(s, t, ds0, ds1, wrap))
+            }
+        }
     }
 }
 
 impl Texture for ImageTexture {
     fn value(&self, u: f64, v: f64, _p: Float3) -> Float3 {
-        let u = (u * self.scale.0).fract();
-        let v = (v * self.scale.1).fract();
-        let x = (u * self.width as f64) as i64;
-        let y = (v * self.height as f64) as i64;
-        self.sample(x, y)
+        self.filtered(u * self.scale.0, v * self.scale.1, (0.0, 0.0), (0.0, 0.0))
+    }
+
+    // 画面上の微分があればその大きさでミップマップを選ぶ
+    fn value_at(&self, hit: &HitInfo) -> Float3 {
+        let (su, sv) = self.scale;
+        match hit.differentials {
+            Some(d) => self.filtered(
+                hit.u * su,
+                hit.v * sv,
+                (d.dudx * su, d.dvdx * sv),
+                (d.dudy * su, d.dvdy * sv),
+            ),
+            None => self.value(hit.u, hit.v, hit.p),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 値がピクセルの番号になるn x 1 (横) または1 x n (縦) の画像
+    fn strip(n: usize, horizontal: bool) -> ImageTexture {
+        let pixels = (0..n).map(|i| float3::fill(i as f64)).collect();
+        let (w, h) = if horizontal { (n, 1) } else { (1, n) };
+        ImageTexture::from_pixels(pixels, w, h, (1.0, 1.0))
+            .unwrap()
+            .with_filter(TextureFilter::Nearest)
+    }
+
+    #[test]
+    fn wrap_modes_outside_the_image() {
+        // ピクセル-2, -1, 4, 5の中心
+        let coords = [-0.375, -0.125, 1.125, 1.375];
+        let cases = [
+            (WrapMode::Repeat, [2.0, 3.0, 0.0, 1.0]),
+            (WrapMode::Clamp, [0.0, 0.0, 3.0, 3.0]),
+            (WrapMode::Mirror, [1.0, 0.0, 3.0, 2.0]),
+        ];
+        for (wrap, expected) in cases {
+            let row = strip(4, true).with_wrap(wrap);
+            let column = strip(4, false).with_wrap(wrap);
+            for (c, e) in coords.iter().zip(expected) {
+                assert_eq!(
+                    row.value(*c, 0.5, Float3::zeros()).x,
+                    e,
+                    "{:?} u={}",
+                    wrap,
+                    c
+                );
+                assert_eq!(
+                    column.value(0.5, *c, Float3::zeros()).x,
+                    e,
+                    "{:?} v={}",
+                    wrap,
+                    c
+                );
+            }
+        }
+    }
+
+    fn hit_with_differentials(u: f64, v: f64, dudx: f64, dvdy: f64) -> HitInfo {
+        let material = Arc::new(Lambertian::new(Box::new(ColorTexture::new(float3::one()))));
+        HitInfo {
+            differentials: Some(SurfaceDifferentials {
+                dpdx: Float3::zeros(),
+                dpdy: Float3::zeros(),
+                dudx,
+                dvdx: 0.0,
+                dudy: 0.0,
+                dvdy,
+            }),
+            ..HitInfo::new(1.0, Float3::zeros(), Float3::z(), material, u, v)
+        }
+    }
+
+    #[test]
+    fn mip_levels_of_non_power_of_two_image() {
+        // 値がxになる5x3の画像
+        let pixels = (0..15).map(|i| float3::fill((i % 5) as f64)).collect();
+        let texture = ImageTexture::from_pixels(pixels, 5, 3, (1.0, 1.0)).unwrap();
+        let sizes = texture
+            .levels
+            .iter()
+            .map(|l| (l.width, l.height))
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![(5, 3), (3, 2), (2, 1), (1, 1)]);
+        // 奇数の端は余った列だけで平均する
+        let level = &texture.levels[1];
+        assert_eq!(level.pixels[0].x, 0.5);
+        assert_eq!(level.pixels[2].x, 4.0);
+        assert_eq!(level.pixels[5].x, 4.0);
+    }
+
+    #[test]
+    fn level_selection_from_differentials() {
+        // 8x8の市松模様. 1段目から先は全て0.5
+        let pixels = (0..64)
+            .map(|i| float3::fill(((i % 8 + i / 8) % 2) as f64))
+            .collect();
+        let texture = ImageTexture::from_pixels(pixels, 8, 8, (1.0, 1.0)).unwrap();
+        assert_eq!(texture.mip_levels(), 4);
+        assert_eq!(texture.level_of_detail(1.0 / 8.0), 0.0);
+        assert_eq!(texture.level_of_detail(1.0), 3.0);
+
+        // ピクセル(0, 0)の中心
+        let (u, v) = (1.0 / 16.0, 1.0 / 16.0);
+        let value = |d: f64| texture.value_at(&hit_with_differentials(u, v, d, d)).x;
+        // 微分がなければ元の解像度
+        assert_eq!(texture.value(u, v, Float3::zeros()).x, 0.0);
+        assert_eq!(value(1e-4), 0.0);
+        // 1画素でテクセル2つ分動くなら1段目
+        assert!((value(1.0 / 8.0) - 0.5).abs() < 1e-12);
+        // 0段目と1段目の中間
+        assert!((value(2f64.sqrt() / 16.0) - 0.25).abs() < 1e-12);
+        // 画像全体より大きければ1x1の段
+        assert!((value(4.0) - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn filters_keep_a_constant_texture_constant() {
+        let color = float3::new(0.3, 0.6, 0.9);
+        for filter in [TextureFilter::Trilinear, TextureFilter::Ewa] {
+            let texture = ImageTexture::from_pixels(vec![color; 15], 5, 3, (1.0, 1.0))
+                .unwrap()
+                .with_filter(filter);
+            for (u, v) in [(0.0, 0.0), (0.3, 0.7), (0.99, 0.5), (-0.2, 1.4)] {
+                for (dudx, dvdy) in [(0.0, 0.0), (0.01, 0.01), (0.3, 0.001), (0.002, 0.5)] {
+                    let c = texture.value_at(&hit_with_differentials(u, v, dudx, dvdy));
+                    assert!((c - color).norm() < 1e-9, "{:?} {:?}", filter, c);
+                }
+            }
+        }
     }
 }
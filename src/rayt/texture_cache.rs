@@ -0,0 +1,113 @@
+use crate::rayt::*;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+type Images = HashMap<(PathBuf, ColorSpace), (Option<SystemTime>, ImageTexture)>;
+
+// 読み込んだ画像をファイルのパスごとに共有する
+// 同じ画像を多くのマテリアルから参照しても、デコードとミップマップの作成は一度だけになる
+// ファイルの更新時刻も覚えておき、書き換えられていたら読み直す
+#[derive(Default)]
+pub struct TextureCache {
+    images: Mutex<Images>,
+}
+
+impl TextureCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // ImageTexture::openが使うキャッシュ. 使いたくなければImageTexture::decodeで読む
+    pub fn global() -> &'static TextureCache {
+        static GLOBAL: OnceLock<TextureCache> = OnceLock::new();
+        GLOBAL.get_or_init(TextureCache::new)
+    }
+
+    // 読んだことのある画像ならピクセルを共有したテクスチャを返す
+    pub fn load(
+        &self,
+        path: &str,
+        scale: (f64, f64),
+        color_space: ColorSpace,
+    ) -> Result<ImageTexture> {
+        // 同じファイルを別の書き方のパスで参照しても共有する
+        let key = (
+            std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path)),
+            color_space,
+        );
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        if let Some((_, texture)) = self
+            .lock()
+            .get(&key)
+            .filter(|(time, _)| modified.is_some() && *time == modified)
+        {
+            return Ok(texture.clone().with_scale(scale));
+        }
+        // デコードしている間は他のスレッドを止めない
+        let texture = ImageTexture::decode(path, scale, color_space)?;
+        self.lock().insert(key, (modified, texture.clone()));
+        Ok(texture)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    // 共有している画像を手放す. 既に作ったテクスチャはそのまま使える
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Images> {
+        // 他のスレッドがパニックしても中身は壊れていないので使い続ける
+        self.images.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn write_png(path: &std::path::Path, value: u8, modified: SystemTime) {
+        image::RgbImage::from_pixel(1, 1, image::Rgb([value; 3]))
+            .save(path)
+            .unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    #[test]
+    fn reloads_modified_file() {
+        let path = std::env::temp_dir().join(format!("ayanami-cache-{}.png", std::process::id()));
+        let name = path.to_string_lossy().to_string();
+        let cache = TextureCache::new();
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        write_png(&path, 255, t0);
+        let first = cache.load(&name, (1.0, 1.0), ColorSpace::Linear).unwrap();
+        assert_eq!(first.pixel(0, 0), float3::fill(1.0));
+
+        // 同じ更新時刻なら共有する
+        let again = cache.load(&name, (1.0, 1.0), ColorSpace::Linear).unwrap();
+        assert_eq!(again.pixel(0, 0), float3::fill(1.0));
+        assert_eq!(cache.len(), 1);
+
+        write_png(&path, 0, t0 + Duration::from_secs(1));
+        let reloaded = cache.load(&name, (1.0, 1.0), ColorSpace::Linear).unwrap();
+        assert_eq!(reloaded.pixel(0, 0), float3::fill(0.0));
+        assert_eq!(cache.len(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
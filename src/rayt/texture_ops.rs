@@ -0,0 +1,388 @@
+use crate::rayt::*;
+
+// テクスチャを組み合わせるテクスチャ
+// どれもBox<dyn Texture>を受け取るので入れ子にできる. 交差情報は子のvalue_atにそのまま渡す
+
+// maskの明るさ (3チャンネルの平均) でaとbを補間する. 0ならa, 1ならb
+pub struct MixTexture {
+    a: Box<dyn Texture>,
+    b: Box<dyn Texture>,
+    mask: Box<dyn Texture>,
+}
+
+impl MixTexture {
+    pub fn new(a: Box<dyn Texture>, b: Box<dyn Texture>, mask: Box<dyn Texture>) -> Self {
+        Self { a, b, mask }
+    }
+}
+
+impl Texture for MixTexture {
+    fn value(&self, u: f64, v: f64, p: Float3) -> Float3 {
+        let t = self.mask.value(u, v, p).mean();
+        self.a.value(u, v, p).lerp(&self.b.value(u, v, p), t)
+    }
+
+    fn value_at(&self, hit: &HitInfo) -> Float3 {
+        let t = self.mask.value_at(hit).mean();
+        self.a.value_at(hit).lerp(&self.b.value_at(hit), t)
+    }
+}
+
+// チャンネルごとの積
+pub struct MultiplyTexture {
+    a: Box<dyn Texture>,
+    b: Box<dyn Texture>,
+}
+
+impl MultiplyTexture {
+    pub fn new(a: Box<dyn Texture>, b: Box<dyn Texture>) -> Self {
+        Self { a, b }
+    }
+}
+
+impl Texture for MultiplyTexture {
+    fn value(&self, u: f64, v: f64, p: Float3) -> Float3 {
+        self.a.value(u, v, p).component_mul(&self.b.value(u, v, p))
+    }
+
+    fn value_at(&self, hit: &HitInfo) -> Float3 {
+        self.a.value_at(hit).component_mul(&self.b.value_at(hit))
+    }
+}
+
+pub struct AddTexture {
+    a: Box<dyn Texture>,
+    b: Box<dyn Texture>,
+}
+
+impl AddTexture {
+    pub fn new(a: Box<dyn Texture>, b: Box<dyn Texture>) -> Self {
+        Self { a, b }
+    }
+}
+
+impl Texture for AddTexture {
+    fn value(&self, u: f64, v: f64, p: Float3) -> Float3 {
+        self.a.value(u, v, p) + self.b.value(u, v, p)
+    }
+
+    fn value_at(&self, hit: &HitInfo) -> Float3 {
+        self.a.value_at(hit) + self.b.value_at(hit)
+    }
+}
+
+// 定数倍. 明るさを変えたり色を付けたりする
+pub struct ScaleTexture {
+    texture: Box<dyn Texture>,
+    scale: Float3,
+}
+
+impl ScaleTexture {
+    pub fn new(texture: Box<dyn Texture>, scale: Float3) -> Self {
+        Self { texture, scale }
+    }
+}
+
+impl Texture for ScaleTexture {
+    fn value(&self, u: f64, v: f64, p: Float3) -> Float3 {
+        self.texture.value(u, v, p).component_mul(&self.scale)
+    }
+
+    fn value_at(&self, hit: &HitInfo) -> Float3 {
+        self.texture.value_at(hit).component_mul(&self.scale)
+    }
+}
+
+// 明るさ (3チャンネルの平均) をColorRampで色にする
+pub struct RampTexture {
+    input: Box<dyn Texture>,
+    ramp: ColorRamp,
+}
+
+impl RampTexture {
+    pub fn new(input: Box<dyn Texture>, ramp: ColorRamp) -> Self {
+        Self { input, ramp }
+    }
+}
+
+impl Texture for RampTexture {
+    fn value(&self, u: f64, v: f64, p: Float3) -> Float3 {
+        self.ramp.sample(self.input.value(u, v, p).mean())
+    }
+
+    fn value_at(&self, hit: &HitInfo) -> Float3 {
+        self.ramp.sample(self.input.value_at(hit).mean())
+    }
+}
+
+// uvの拡大縮小, 回転 (ラジアン), 平行移動. この順に適用する
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvTransform {
+    pub scale: (f64, f64),
+    pub rotation: f64,
+    pub offset: (f64, f64),
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        Self {
+            scale: (1.0, 1.0),
+            rotation: 0.0,
+            offset: (0.0, 0.0),
+        }
+    }
+}
+
+impl UvTransform {
+    pub fn with_scale(mut self, su: f64, sv: f64) -> Self {
+        self.scale = (su, sv);
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: f64) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_offset(mut self, du: f64, dv: f64) -> Self {
+        self.offset = (du, dv);
+        self
+    }
+
+    // 平行移動を除いた部分. uvの微分の変換に使う
+    fn linear(&self, u: f64, v: f64) -> (f64, f64) {
+        let (u, v) = (u * self.scale.0, v * self.scale.1);
+        let (sin, cos) = self.rotation.sin_cos();
+        (u * cos - v * sin, u * sin + v * cos)
+    }
+
+    pub fn apply(&self, u: f64, v: f64) -> (f64, f64) {
+        let (u, v) = self.linear(u, v);
+        (u + self.offset.0, v + self.offset.1)
+    }
+}
+
+// uvを変換してから子のテクスチャを読む
+pub struct UvTransformTexture {
+    texture: Box<dyn Texture>,
+    transform: UvTransform,
+}
+
+impl UvTransformTexture {
+    pub fn new(texture: Box<dyn Texture>, transform: UvTransform) -> Self {
+        Self { texture, transform }
+    }
+}
+
+impl Texture for UvTransformTexture {
+    fn value(&self, u: f64, v: f64, p: Float3) -> Float3 {
+        let (u, v) = self.transform.apply(u, v);
+        self.texture.value(u, v, p)
+    }
+
+    fn value_at(&self, hit: &HitInfo) -> Float3 {
+        let (u, v) = self.transform.apply(hit.u, hit.v);
+        // 画面上の微分も同じ線形変換で変わる
+        let differentials = hit.differentials.map(|d| {
+            let (dudx, dvdx) = self.transform.linear(d.dudx, d.dvdx);
+            let (dudy, dvdy) = self.transform.linear(d.dudy, d.dvdy);
+            SurfaceDifferentials {
+                dudx,
+                dvdx,
+                dudy,
+                dvdy,
+                ..d
+            }
+        });
+        self.texture.value_at(&HitInfo {
+            u,
+            v,
+            differentials,
+            ..hit.clone()
+        })
+    }
+}
+
+// uvの代わりに位置を3つの軸の平面に投影して読み、法線の向きで重み付けして混ぜる
+// uvを持たない形状や、uvが伸びてしまう形状に画像を貼るのに使う
+pub struct TriplanarTexture {
+    texture: Box<dyn Texture>,
+    // 1単位の長さあたりのuv
+    scale: f64,
+    // 大きいほど平面の境目がはっきりする
+    sharpness: f64,
+}
+
+impl TriplanarTexture {
+    pub fn new(texture: Box<dyn Texture>, scale: f64) -> Self {
+        Self {
+            texture,
+            scale,
+            sharpness: 4.0,
+        }
+    }
+
+    pub fn with_sharpness(mut self, sharpness: f64) -> Self {
+        self.sharpness = sharpness;
+        self
+    }
+
+    // 軸axisに垂直な平面のuvにする軸
+    fn plane_axes(axis: usize) -> (usize, usize) {
+        match axis {
+            0 => (2, 1),
+            1 => (0, 2),
+            _ => (0, 1),
+        }
+    }
+}
+
+impl Texture for TriplanarTexture {
+    // 法線が分からないのでxy平面に投影する
+    fn value(&self, _u: f64, _v: f64, p: Float3) -> Float3 {
+        self.texture.value(p.x * self.scale, -p.y * self.scale, p)
+    }
+
+    fn value_at(&self, hit: &HitInfo) -> Float3 {
+        let weights = hit.n.map(|x| x.abs().powf(self.sharpness));
+        let total = weights.sum();
+        if total <= 0.0 {
+            return self.value(hit.u, hit.v, hit.p);
+        }
+        let mut sum = Float3::zeros();
+        for axis in 0..3 {
+            if weights[axis] <= 0.0 {
+                continue;
+            }
+            let (a, b) = Self::plane_axes(axis);
+            let s = self.scale;
+            // 画像テクスチャはv = 0が上端なので、vは軸と逆向きにする
+            let differentials = hit.differentials.map(|d| SurfaceDifferentials {
+                dudx: d.dpdx[a] * s,
+                dvdx: -d.dpdx[b] * s,
+                dudy: d.dpdy[a] * s,
+                dvdy: -d.dpdy[b] * s,
+                ..d
+            });
+            let projected = HitInfo {
+                u: hit.p[a] * s,
+                v: -hit.p[b] * s,
+                differentials,
+                ..hit.clone()
+            };
+            sum += self.texture.value_at(&projected) * (weights[axis] / total);
+        }
+        sum
+    }
+}
+
+// 子のテクスチャに、ワールド座標の代わりに形状自身の座標系の位置と法線を渡す
+// Transformで動かしても模様が形状に付いてくる
+pub struct ObjectSpaceTexture {
+    texture: Box<dyn Texture>,
+}
+
+impl ObjectSpaceTexture {
+    pub fn new(texture: Box<dyn Texture>) -> Self {
+        Self { texture }
+    }
+}
+
+impl Texture for ObjectSpaceTexture {
+    fn value(&self, u: f64, v: f64, p: Float3) -> Float3 {
+        self.texture.value(u, v, p)
+    }
+
+    fn value_at(&self, hit: &HitInfo) -> Float3 {
+        self.texture.value_at(&HitInfo {
+            p: hit.object_p,
+            n: hit.object_n,
+            // 画面上の微分はワールド座標の長さと向きなので、形状の座標系では使えない
+            // 間違った幅でぼかさないよう、微分の無い交差として扱う
+            differentials: None,
+            ..hit.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // uvをそのまま色にする
+    struct UvColor;
+
+    impl Texture for UvColor {
+        fn value(&self, u: f64, v: f64, _p: Float3) -> Float3 {
+            float3::new(u, v, 0.0)
+        }
+    }
+
+    #[test]
+    fn ramp_texture_clamps() {
+        let ramp = ColorRamp::new(float3::new(1.0, 0.0, 0.0), float3::new(0.0, 0.0, 1.0));
+        let below = RampTexture::new(
+            Box::new(ColorTexture::new(float3::fill(-2.0))),
+            ramp.clone(),
+        );
+        let above = RampTexture::new(Box::new(ColorTexture::new(float3::fill(3.0))), ramp);
+        assert_eq!(
+            below.value(0.0, 0.0, Float3::zeros()),
+            float3::new(1.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            above.value(0.0, 0.0, Float3::zeros()),
+            float3::new(0.0, 0.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn uv_transform_scales_rotates_then_offsets() {
+        let transform = UvTransform::default()
+            .with_scale(2.0, 4.0)
+            .with_rotation(PI * 0.5)
+            .with_offset(1.0, 2.0);
+        // (1, 1) -> (2, 4) -> (-4, 2) -> (-3, 4)
+        let (u, v) = transform.apply(1.0, 1.0);
+        assert!((u + 3.0).abs() < 1e-12 && (v - 4.0).abs() < 1e-12);
+        let texture = UvTransformTexture::new(Box::new(UvColor), transform);
+        let c = texture.value(1.0, 1.0, Float3::zeros());
+        assert!((c - float3::new(-3.0, 4.0, 0.0)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn uv_transform_round_trips() {
+        let forward = UvTransform::default()
+            .with_scale(2.0, 2.0)
+            .with_rotation(0.3)
+            .with_offset(0.5, -0.25);
+        // 逆変換は拡大縮小と回転を戻し、平行移動もそれで戻した分だけずらす
+        let back = UvTransform::default()
+            .with_scale(0.5, 0.5)
+            .with_rotation(-0.3);
+        let (du, dv) = back.apply(0.5, -0.25);
+        let back = back.with_offset(-du, -dv);
+        for (u, v) in [(0.0, 0.0), (0.3, 0.8), (-1.5, 2.0)] {
+            let (fu, fv) = forward.apply(u, v);
+            let (bu, bv) = back.apply(fu, fv);
+            assert!((bu - u).abs() < 1e-12 && (bv - v).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn triplanar_picks_projection_of_the_normal_axis() {
+        let texture = TriplanarTexture::new(Box::new(UvColor), 2.0);
+        let material = Arc::new(Lambertian::new(Box::new(ColorTexture::new(float3::one()))));
+        let p = float3::new(1.0, 0.25, 0.5);
+        // 法線の軸に垂直な平面に投影する. vは軸と逆向き
+        for (n, expected) in [
+            (Float3::x(), float3::new(1.0, -0.5, 0.0)),
+            (-Float3::y(), float3::new(2.0, -1.0, 0.0)),
+            (Float3::z(), float3::new(2.0, -0.5, 0.0)),
+        ] {
+            let hit = HitInfo::new(1.0, p, n, material.clone(), 0.0, 0.0);
+            let c = texture.value_at(&hit);
+            assert!((c - expected).norm() < 1e-12, "{:?}: {:?}", n, c);
+        }
+    }
+}
@@ -1,94 +1,422 @@
 use crate::rayt::*;
 
+// 4x4のアフィン変換. 逆行列も一緒に持つ
+// 平行移動, 回転, 拡大縮小のほか、せん断を含む任意のアフィン行列を扱える
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Affine {
+    m: Mat4,
+    inv: Mat4,
+}
+
+impl Default for Affine {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Affine {
+    pub fn identity() -> Self {
+        Self {
+            m: Mat4::identity(),
+            inv: Mat4::identity(),
+        }
+    }
+
+    // 逆行列が無い (潰れている) 場合や、最後の行が(0, 0, 0, 1)でない場合はNone
+    pub fn from_matrix(m: Mat4) -> Option<Self> {
+        let last_row = m.fixed_slice::<1, 4>(3, 0);
+        if last_row != na::RowVector4::new(0.0, 0.0, 0.0, 1.0) {
+            return None;
+        }
+        let inv = m.try_inverse()?;
+        Some(Self { m, inv })
+    }
+
+    pub fn translation(t: Float3) -> Self {
+        Self {
+            m: Mat4::new_translation(&t),
+            inv: Mat4::new_translation(&-t),
+        }
+    }
+
+    pub fn rotation(r: Quat) -> Self {
+        Self {
+            m: r.to_homogeneous(),
+            inv: r.inverse().to_homogeneous(),
+        }
+    }
+
+    pub fn scaling(s: Float3) -> Self {
+        Self {
+            m: Mat4::new_nonuniform_scaling(&s),
+            inv: Mat4::new_nonuniform_scaling(&s.map(f64::recip)),
+        }
+    }
+
+    // ローカルの+zがtargetを向き、+yがupに近くなるようにeyeに置く
+    pub fn look_at(eye: Float3, target: Float3, up: Float3) -> Option<Self> {
+        let z = (target - eye).try_normalize(0.0)?;
+        let x = up.cross(&z).try_normalize(0.0)?;
+        let y = z.cross(&x);
+        let rotation = Quat::from_rotation_matrix(&na::Rotation3::from_basis_unchecked(&[x, y, z]));
+        Some(Self::rotation(rotation).then(&Self::translation(eye)))
+    }
+
+    // selfの後にnextを適用する変換 (行列としてはnext * self)
+    pub fn then(&self, next: &Affine) -> Self {
+        Self {
+            m: next.m * self.m,
+            inv: self.inv * next.inv,
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            m: self.inv,
+            inv: self.m,
+        }
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        &self.m
+    }
+
+    pub fn inverse_matrix(&self) -> &Mat4 {
+        &self.inv
+    }
+
+    pub fn point(&self, p: &Float3) -> Float3 {
+        self.m.transform_point(&na::Point3::from(*p)).coords
+    }
+
+    pub fn vector(&self, v: &Float3) -> Float3 {
+        self.m.transform_vector(v)
+    }
+
+    // 法線は逆転置行列で変換する
+    pub fn normal(&self, n: &Float3) -> Float3 {
+        self.inv.fixed_slice::<3, 3>(0, 0).tr_mul(n).normalize()
+    }
+}
+
+// 親の座標系のレイを子の座標系にする
+// 方向は正規化しないので、tは親の座標系のレイと共通
+fn ray_to_child(affine: &Affine, ray: &Ray) -> Ray {
+    let inv = affine.inverse_matrix();
+    let point = |p: &Float3| inv.transform_point(&na::Point3::from(*p)).coords;
+    let ray_os = Ray::with_time(
+        point(&ray.origin),
+        inv.transform_vector(&ray.direction),
+        ray.time,
+    );
+    match ray.differential {
+        Some(d) => ray_os.with_differential(RayDifferential {
+            rx_origin: point(&d.rx_origin),
+            rx_direction: inv.transform_vector(&d.rx_direction),
+            ry_origin: point(&d.ry_origin),
+            ry_direction: inv.transform_vector(&d.ry_direction),
+        }),
+        None => ray_os,
+    }
+}
+
+// 子の座標系の交差情報を親の座標系にする
+fn hit_to_parent(affine: &Affine, hit: HitInfo) -> HitInfo {
+    let p = affine.point(&hit.p);
+    let n = affine.normal(&hit.n);
+    let dpdu = affine.vector(&hit.dpdu);
+    let dpdv = affine.vector(&hit.dpdv);
+    // uvの微分はアフィン変換で変わらない
+    let differentials = hit.differentials.map(|d| SurfaceDifferentials {
+        dpdx: affine.vector(&d.dpdx),
+        dpdy: affine.vector(&d.dpdy),
+        ..d
+    });
+    HitInfo {
+        p,
+        n,
+        differentials,
+        ..hit
+    }
+    .with_frame(dpdu, dpdv)
+}
+
+// 子の形状をアフィン変換して配置する
+// Transformを入れ子にすると親子関係になり、親の変換が後に掛かる
 pub struct Transform {
     shape: Box<dyn Shape>,
-    position: Option<Float3>,
-    rotation: Option<Quat>,
-    scale: Option<Float3>,
+    // 子の座標系 -> 親の座標系
+    affine: Affine,
 }
 
 impl Transform {
     pub fn new(shape: Box<dyn Shape>) -> Self {
-        Self {
-            shape,
-            position: None,
-            rotation: None,
-            scale: None,
-        }
+        Self::from_affine(shape, Affine::identity())
     }
 
-    pub fn translate_mut(&mut self, position: Float3) -> &Self {
-        let p = self.position.unwrap_or(Float3::zeros());
-        self.position = Some(p + position);
+    pub fn from_affine(shape: Box<dyn Shape>, affine: Affine) -> Self {
+        Self { shape, affine }
+    }
+
+    pub fn affine(&self) -> &Affine {
+        &self.affine
+    }
+
+    // 以下は呼んだ順に、現在の変換の後に適用される
+    pub fn transform_mut(&mut self, affine: &Affine) -> &Self {
+        self.affine = self.affine.then(affine);
         self
     }
 
+    pub fn translate_mut(&mut self, position: Float3) -> &Self {
+        self.transform_mut(&Affine::translation(position))
+    }
+
     pub fn rotate_mut(&mut self, rotation: Quat) -> &Self {
-        let r = self.rotation.unwrap_or(Quat::identity());
-        self.rotation = Some(rotation * r);
-        self
+        self.transform_mut(&Affine::rotation(rotation))
     }
 
     pub fn scale_mut(&mut self, scale: Float3) -> &Self {
-        let s = self.scale.unwrap_or(float3::one());
-        self.scale = Some(s.component_mul(&scale));
-        self
+        self.transform_mut(&Affine::scaling(scale))
     }
+}
 
-    fn ray_object_space(&self, ray: &Ray) -> Ray {
-        let mut origin = ray.origin;
-        let mut direction = ray.direction;
-        if let Some(pos) = self.position {
-            origin -= pos;
-        }
-        if let Some(rot) = self.rotation {
-            let rot_inv = rot.conjugate();
-            origin = rot_inv * origin;
-            direction = rot_inv * (direction);
-        }
-        if let Some(scale) = self.scale {
-            origin.component_div_assign(&scale);
-            direction.component_div_assign(&scale);
+impl Shape for Transform {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let ray_os = ray_to_child(&self.affine, ray);
+        self.shape
+            .hit(&ray_os, t0, t1)
+            .map(|hit| hit_to_parent(&self.affine, hit))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let b = self.shape.bounds()?;
+        Some(Aabb::from_points(
+            &b.corners().map(|c| self.affine.point(&c)),
+        ))
+    }
+
+    fn export(&self, exporter: &mut SceneExporter) {
+        exporter.with_transform(self.affine.matrix(), |exporter| self.shape.export(exporter));
+    }
+}
+
+// アニメーションのキーフレーム. 拡大縮小, 回転, 平行移動の順に適用する
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translation: Float3,
+    pub rotation: Quat,
+    pub scale: Float3,
+}
+
+impl Keyframe {
+    pub fn new(time: f64) -> Self {
+        Self {
+            time,
+            translation: Float3::zeros(),
+            rotation: Quat::identity(),
+            scale: float3::one(),
         }
-        Ray::new(origin, direction)
     }
 
-    fn object_to_world(&self, position: Float3) -> Float3 {
-        let mut result = position;
-        if let Some(scale) = self.scale {
-            result = result.component_mul(&scale);
+    pub fn with_translation(self, translation: Float3) -> Self {
+        Self {
+            translation,
+            ..self
         }
-        if let Some(rotation) = self.rotation {
-            result = rotation * result;
+    }
+
+    pub fn with_rotation(self, rotation: Quat) -> Self {
+        Self { rotation, ..self }
+    }
+
+    pub fn with_scale(self, scale: Float3) -> Self {
+        Self { scale, ..self }
+    }
+
+    pub fn affine(&self) -> Affine {
+        Affine::scaling(self.scale)
+            .then(&Affine::rotation(self.rotation))
+            .then(&Affine::translation(self.translation))
+    }
+
+    // 平行移動と拡大縮小は線形補間, 回転は球面線形補間
+    pub fn lerp(&self, other: &Keyframe, t: f64) -> Keyframe {
+        Keyframe {
+            time: self.time + (other.time - self.time) * t,
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: math::slerp(&self.rotation, &other.rotation, t),
+            scale: self.scale.lerp(&other.scale, t),
         }
-        if let Some(pos) = self.position {
-            result += pos;
+    }
+}
+
+// 境界箱を求めるときのキーフレーム間の分割数. 多いほど回転の膨らみの見積もりが小さくなる
+const MOTION_BOUNDS_STEPS: usize = 16;
+
+// キーフレームの間を補間して子の形状を動かす. レイの時刻の位置で交差判定する
+// 最初のキーより前と最後のキーより後は端のキーで止まる
+pub struct AnimatedTransform {
+    shape: Box<dyn Shape>,
+    keys: Vec<Keyframe>,
+}
+
+impl AnimatedTransform {
+    // キーが無い場合はInvalidScene
+    pub fn new(shape: Box<dyn Shape>, mut keys: Vec<Keyframe>) -> Result<Self> {
+        if keys.is_empty() {
+            return Err(Error::InvalidScene(
+                "animated transform needs at least one keyframe".to_string(),
+            ));
         }
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(Self { shape, keys })
+    }
 
-        result
+    pub fn keys(&self) -> &[Keyframe] {
+        &self.keys
     }
 
-    fn object_to_world_normal(&self, normal: Float3) -> Float3 {
-        let mut result = normal;
-        if let Some(scale) = self.scale {
-            result = result.component_mul(&scale);
+    pub fn keyframe_at(&self, time: f64) -> Keyframe {
+        let i = self.keys.partition_point(|k| k.time <= time);
+        if i == 0 {
+            return self.keys[0];
         }
-        if let Some(rotation) = self.rotation {
-            result = rotation * result;
+        if i == self.keys.len() {
+            return self.keys[i - 1];
         }
-        result.normalize()
+        let (k0, k1) = (&self.keys[i - 1], &self.keys[i]);
+        k0.lerp(k1, (time - k0.time) / (k1.time - k0.time))
+    }
+
+    pub fn affine_at(&self, time: f64) -> Affine {
+        self.keyframe_at(time).affine()
     }
 }
 
-impl Shape for Transform {
+impl Shape for AnimatedTransform {
     fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
-        let ray_os = self.ray_object_space(ray);
-        if let Some(hit) = self.shape.hit(&ray_os, t0, t1) {
-            let p = self.object_to_world(hit.p);
-            let n = self.object_to_world_normal(hit.n);
-            Some(HitInfo { p: p, n: n, ..hit })
-        } else {
-            None
+        let affine = self.affine_at(ray.time);
+        let ray_os = ray_to_child(&affine, ray);
+        let hit = self.shape.hit(&ray_os, t0, t1)?;
+        Some(hit_to_parent(&affine, hit))
+    }
+
+    // キーフレームの間を分割し、分割した区間ごとに両端の姿勢の箱を作る
+    // 区間の中では一定の軸のまわりに回るので、角は両端を結ぶ線分から
+    // (原点からの距離) x (回転角) 以上は離れない. その分だけ箱を広げる
+    fn bounds(&self) -> Option<Aabb> {
+        let b = self.shape.bounds()?;
+        let corners = b.corners();
+        let transformed = |key: &Keyframe| {
+            let affine = key.affine();
+            Aabb::from_points(&corners.map(|c| affine.point(&c)))
+        };
+        let radius = |key: &Keyframe| {
+            corners
+                .iter()
+                .map(|c| c.component_mul(&key.scale).norm())
+                .fold(0.0, f64::max)
+        };
+        let mut bounds = transformed(&self.keys[0]);
+        for pair in self.keys.windows(2) {
+            let mut k0 = pair[0];
+            for step in 1..=MOTION_BOUNDS_STEPS {
+                let k1 = pair[0].lerp(&pair[1], step as f64 / MOTION_BOUNDS_STEPS as f64);
+                let angle = k0.rotation.angle_to(&k1.rotation);
+                let margin = 2.0 * radius(&k0).max(radius(&k1)) * angle;
+                let swept = transformed(&k0).union(&transformed(&k1));
+                bounds = bounds.union(&swept.padded(margin));
+                k0 = k1;
+            }
         }
+        Some(bounds)
+    }
+
+    // 最初のキーの位置で書き出す
+    fn export(&self, exporter: &mut SceneExporter) {
+        exporter.with_transform(self.keys[0].affine().matrix(), |exporter| {
+            self.shape.export(exporter)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Box::new(ColorTexture::new(float3::fill(
+            0.5,
+        )))))
+    }
+
+    fn assert_near(a: Float3, b: Float3) {
+        assert!((a - b).norm() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn scaled_sphere_normal_is_perpendicular_to_surface() {
+        let mut shape = Transform::new(Box::new(Sphere::new(Float3::zeros(), 1.0, material())));
+        shape.scale_mut(float3::new(2.0, 1.0, 1.0));
+        // 楕円体 (x/2)² + y² + z² = 1 の斜めの位置を狙う
+        let ray = Ray::new(float3::new(1.2, 0.5, 5.0), -Float3::z());
+        let hit = shape.hit(&ray, 0.001, f64::MAX).unwrap();
+        let p = hit.p;
+        assert!(((p.x / 2.0).powi(2) + p.y * p.y + p.z * p.z - 1.0).abs() < 1e-9);
+        // 陰関数の勾配が面の法線
+        let expected = float3::new(p.x / 4.0, p.y, p.z).normalize();
+        assert_near(hit.n, expected);
+        // 接ベクトルとも直交する
+        assert!(hit.n.dot(&hit.tangent).abs() < 1e-9);
+        assert!(hit.n.dot(&hit.bitangent).abs() < 1e-9);
+    }
+
+    #[test]
+    fn then_applies_in_call_order() {
+        let translate = Affine::translation(float3::new(1.0, 0.0, 0.0));
+        let rotate = Affine::rotation(Quat::from_axis_angle(&Float3::z_axis(), PI * 0.5));
+        let p = float3::new(1.0, 0.0, 0.0);
+        // 平行移動してから回転: (2, 0, 0) -> (0, 2, 0)
+        assert_near(
+            translate.then(&rotate).point(&p),
+            float3::new(0.0, 2.0, 0.0),
+        );
+        // 回転してから平行移動: (0, 1, 0) -> (1, 1, 0)
+        assert_near(
+            rotate.then(&translate).point(&p),
+            float3::new(1.0, 1.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn transform_methods_apply_in_call_order() {
+        let mut shape = Transform::new(Box::new(Sphere::new(Float3::zeros(), 1.0, material())));
+        shape.scale_mut(float3::fill(2.0));
+        shape.translate_mut(float3::new(5.0, 0.0, 0.0));
+        // 半径2の球が(5, 0, 0)にある
+        let ray = Ray::new(Float3::zeros(), Float3::x());
+        let hit = shape.hit(&ray, 0.001, f64::MAX).unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn inverse_round_trips() {
+        let a = Affine::scaling(float3::new(2.0, 0.5, 3.0)).then(&Affine::rotation(
+            Quat::from_axis_angle(&Float3::y_axis(), 0.7),
+        ));
+        let b = Affine::translation(float3::new(1.0, -2.0, 0.5)).then(&Affine::rotation(
+            Quat::from_axis_angle(&Float3::x_axis(), -0.3),
+        ));
+        let ab = a.then(&b);
+        let inv = ab.inverse();
+        let v = float3::new(0.3, -1.2, 2.5);
+        assert_near(inv.point(&ab.point(&v)), v);
+        assert_near(inv.vector(&ab.vector(&v)), v);
+        assert_near(inv.normal(&ab.normal(&v)), v.normalize());
+        // 変換した法線は変換した接ベクトルと直交したまま
+        let tangent = v.cross(&Float3::y());
+        assert!(ab.normal(&v).dot(&ab.vector(&tangent)).abs() < 1e-9);
     }
 }
@@ -1,7 +1,8 @@
+use crate::rayt::Result;
 use image::RgbImage;
 use minifb::{Key, KeyRepeat, Window, WindowOptions};
 
-pub fn draw_in_window(backup_filename: &str, pixels: RgbImage) -> minifb::Result<()> {
+pub fn draw_in_window(backup_filename: &str, pixels: RgbImage) -> Result<()> {
     if cfg!(test) {
         return Ok(());
     }
@@ -15,8 +16,7 @@ pub fn draw_in_window(backup_filename: &str, pixels: RgbImage) -> minifb::Result
             topmost: true,
             ..Default::default()
         },
-    )
-    .unwrap_or_else(|e| panic!("{}", e));
+    )?;
 
     // Limit to max ~30 fps update here
     window.limit_update_rate(Some(std::time::Duration::from_micros(16600 * 2)));
@@ -1,32 +1,101 @@
-use crate::rayt::*;
+use ayanami::rayt::*;
+
+// コーネルボックスのアニメーション. 時刻ごとのカメラと照明の強さ, 手前の箱の動き
+pub struct CornelBoxAnimation {
+    pub camera: CameraTrack,
+    pub light: Track<f64>,
+    pub short_box: TransformTrack,
+}
+
+impl Default for CornelBoxAnimation {
+    fn default() -> Self {
+        Self {
+            camera: CameraTrack::new(
+                float3::new(0.0, 278.0, 880.0),
+                float3::new(0.0, 278.0, 0.0),
+                float3::new(0.0, 1.0, 0.0),
+                40.0,
+            ),
+            light: Track::constant(16.0),
+            short_box: TransformTrack {
+                translation: Track::constant(float3::new(80.0, 70.0, 0.0)),
+                rotation: Track::constant(Quat::from_axis_angle(&Float3::y_axis(), PI * 0.25)),
+                scale: Track::constant(float3::new(100.0, 140.0, 100.0)),
+            },
+        }
+    }
+}
+
+impl CornelBoxAnimation {
+    // 2秒かけて照明を点けながら箱に寄り、最後に奥の箱を見上げる
+    // 手前の箱は半回転し、24fpsで180度のシャッターのモーションブラーがかかる
+    pub fn fly_through() -> Self {
+        let default = Self::default();
+        let camera = default.camera.with_shutter(0.0, 1.0 / 48.0);
+        Self {
+            camera: CameraTrack {
+                origin: camera.origin.clone().key(
+                    2.0,
+                    float3::new(-100.0, 200.0, 450.0),
+                    Interpolation::EASE_IN_OUT,
+                ),
+                lookat: camera
+                    .lookat
+                    .clone()
+                    .key(1.0, float3::new(0.0, 278.0, 0.0), Interpolation::Linear)
+                    .key(
+                        2.0,
+                        float3::new(-160.0, 300.0, -100.0),
+                        Interpolation::Linear,
+                    ),
+                ..camera
+            },
+            light: Track::constant(2.0).key(1.0, 16.0, Interpolation::EASE_IN_OUT),
+            short_box: TransformTrack {
+                rotation: default.short_box.rotation.clone().key(
+                    2.0,
+                    Quat::from_axis_angle(&Float3::y_axis(), PI * 1.25),
+                    Interpolation::Linear,
+                ),
+                ..default.short_box
+            },
+        }
+    }
+}
 
 pub struct CornelBoxScene {
     shapes: ShapeList,
+    camera: CameraTrack,
+    time: f64,
 }
 
 impl CornelBoxScene {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self> {
+        Self::at(&CornelBoxAnimation::default(), 0.0)
+    }
+
+    pub fn at(animation: &CornelBoxAnimation, time: f64) -> Result<Self> {
         let mut shapes = ShapeList::new();
         let red = float3::new(0.64, 0.05, 0.05);
         let white = float3::fill(0.73);
         let green = float3::new(0.12, 0.45, 0.15);
 
+        // 白い壁と箱は同じマテリアルを共有する
+        let white_lambertian = ShapeBuilder::new().color_texture(white).lambertian();
+
         let size = 550.0;
         let half = size * 0.5;
 
         shapes.push(
-            ShapeBuilder::new()
-                .color_texture(white)
-                .lambertian()
+            white_lambertian
+                .clone()
                 .rect_xy(-half, half, 0.0, size, -half)
                 .build(),
         );
         shapes.push(
-            ShapeBuilder::new()
-                .color_texture(white)
-                .lambertian()
+            white_lambertian
+                .clone()
                 .rect_xy(-half, half, 0.0, size, -half)
-                .build_transform()
                 .rotate(Quat::from_axis_angle(&Float3::y_axis(), PI))
                 .build(),
         );
@@ -35,7 +104,6 @@ impl CornelBoxScene {
                 .color_texture(green)
                 .lambertian()
                 .rect_xy(-half, half, 0.0, size, -half)
-                .build_transform()
                 .rotate(Quat::from_axis_angle(&Float3::y_axis(), PI * 0.5))
                 .build(),
         );
@@ -44,23 +112,19 @@ impl CornelBoxScene {
                 .color_texture(red)
                 .lambertian()
                 .rect_xy(-half, half, 0.0, size, -half)
-                .build_transform()
                 .rotate(Quat::from_axis_angle(&Float3::y_axis(), -PI * 0.5))
                 .build(),
         );
         shapes.push(
-            ShapeBuilder::new()
-                .color_texture(white)
-                .lambertian()
+            white_lambertian
+                .clone()
                 .rect_xz(-half, half, -half, half, 0.0)
                 .build(),
         );
         shapes.push(
-            ShapeBuilder::new()
-                .color_texture(white)
-                .lambertian()
+            white_lambertian
+                .clone()
                 .rect_xy(-half, half, -half, half, -size)
-                .build_transform()
                 .rotate(Quat::from_axis_angle(&Float3::x_axis(), PI * 0.5))
                 .build(),
         );
@@ -69,51 +133,42 @@ impl CornelBoxScene {
         shapes.push(
             ShapeBuilder::new()
                 .color_texture(color::white())
-                .diffuse_light(16.0)
+                .diffuse_light(animation.light.sample(time))
                 .rect_xz(-lsize, lsize, -lsize, lsize, 0.0)
-                .build_transform()
                 .rotate(Quat::from_axis_angle(&Float3::x_axis(), PI))
                 .translate(float3::new(0.0, size - 5.0, 0.0))
                 .build(),
         );
 
-        shapes.push(
-            ShapeBuilder::new()
-                .color_texture(white)
-                .lambertian()
-                .cube()
-                .build_transform()
-                .scale(float3::new(100.0, 140., 100.0))
-                .translate(float3::new(80.0, 70.0, 0.0))
-                .rotate(Quat::from_axis_angle(&Float3::y_axis(), PI * 0.25))
-                .build(),
-        );
+        // シャッターが開いている間の動きを補間する
+        let (open, close) = animation.camera.shutter_at(time);
+        shapes.push(Box::new(animation.short_box.animate(
+            white_lambertian.clone().cube().build(),
+            open,
+            close,
+        )?));
 
         shapes.push(
-            ShapeBuilder::new()
-                .color_texture(white)
-                .lambertian()
+            white_lambertian
+                .clone()
                 .cube()
-                .build_transform()
                 .scale(float3::new(200.0, 300., 200.0))
-                .translate(float3::new(-160.0, 150.0, -100.0))
                 .rotate(Quat::from_axis_angle(&Float3::y_axis(), -PI * 0.1))
+                .translate(float3::new(-160.0, 150.0, -100.0))
                 .build(),
         );
 
-        Self { shapes }
+        Ok(Self {
+            shapes,
+            camera: animation.camera.clone(),
+            time,
+        })
     }
 }
 
 impl SceneWithDepth for CornelBoxScene {
-    fn camera(&self) -> Camera {
-        Camera::from_lookat(
-            float3::new(0.0, 278.0, 880.0),
-            float3::new(0.0, 278.0, 0.0),
-            float3::new(0.0, 1.0, 0.0),
-            40.0,
-            self.aspect(),
-        )
+    fn camera(&self) -> Box<dyn Projection> {
+        Box::new(self.camera.camera(self.time, self.aspect()))
     }
     fn width(&self) -> u32 {
         200
@@ -1,4 +0,0 @@
-mod random_scene;
-mod simple_scene;
-pub use self::random_scene::*;
-pub use self::simple_scene::*;